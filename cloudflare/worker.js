@@ -14,7 +14,7 @@ export default {
 };
 
 function isBackendPath(path) {
-  if (path === '/health') return true;
+  if (path === '/health' || path === '/live' || path === '/ready') return true;
   if (path === '/api' || path.startsWith('/api/')) return true;
   if (path === '/hook' || path.startsWith('/hook/')) return true;
   if (UUID_PATH.test(path)) return true;
@@ -172,8 +172,7 @@ async function onSubscribe() {
     });
 
     if (!response.ok) {
-      const errText = await response.text();
-      throw new Error(errText || 'Subscription failed');
+      throw new Error(await errorMessage(response, 'Subscription failed'));
     }
 
     const data = await response.json();
@@ -201,8 +200,7 @@ async function onUnsubscribe() {
     );
 
     if (!response.ok && response.status !== 404) {
-      const errText = await response.text();
-      throw new Error(errText || 'Unsubscribe failed');
+      throw new Error(await errorMessage(response, 'Unsubscribe failed'));
     }
 
     const registration = await navigator.serviceWorker.ready;
@@ -323,7 +321,7 @@ function selectRequest(id) {
   els.detailTime.title = selected.timestamp ? new Date(selected.timestamp).toLocaleString() : '';
   els.detailSize.textContent = formatSize(selected.content_length || 0);
 
-  if (selected.partial) {
+  if (selected.partial || selected.note) {
     els.detailNote.textContent = selected.note || 'Incomplete payload received.';
     els.detailNote.classList.remove('hidden');
   } else {
@@ -512,12 +510,21 @@ function clearStoredSubscription() {
 async function fetchJson(url) {
   const response = await fetch(url);
   if (!response.ok) {
-    const errText = await response.text();
-    throw new Error(errText || 'Request failed');
+    throw new Error(await errorMessage(response, 'Request failed'));
   }
   return response.json();
 }
 
+// Error bodies are { error, status, code }; older servers sent plain text.
+async function errorMessage(response, fallback) {
+  const text = await response.text();
+  try {
+    return JSON.parse(text).error || fallback;
+  } catch {
+    return text || fallback;
+  }
+}
+
 function formatHeaders(headers) {
   const entries = Object.entries(headers);
   if (!entries.length) return 'No headers';
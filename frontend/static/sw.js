@@ -35,6 +35,11 @@ async function handlePush(event) {
     }
   }
 
+  if (envelope?.heartbeat) {
+    await notifyHeartbeat();
+    return;
+  }
+
   if (
     !envelope ||
     !envelope.request_id ||
@@ -44,13 +49,26 @@ async function handlePush(event) {
     return;
   }
 
+  // Envelope v3: a chunk that fails its checksum is dropped, like a lost one.
+  if (
+    Number.isInteger(envelope.crc32) &&
+    crc32(base64ToBytes(envelope.data)) !== envelope.crc32
+  ) {
+    return;
+  }
+
   const db = await openDb();
+  // Delivery is at-least-once; a chunk of an already assembled request is a duplicate.
+  const existing = await getRequest(db, envelope.request_id);
+  if (existing && !existing.partial) return;
   await storeChunk(db, envelope);
 
   const result = await tryAssemble(db, envelope.request_id);
   if (result?.request) {
     await notifyClients(result.request.id, result.partial);
-    await showSummary(result.request, result.partial);
+    await showSummary(result.request, result.partial, envelope.topic);
+  } else if (envelope.summary) {
+    await showEarlySummary(envelope);
   }
 }
 
@@ -69,10 +87,18 @@ async function tryAssemble(db, requestId) {
     chunks[0].total_chunks;
   if (totalChunks && chunks.length === totalChunks) {
     chunks.sort((a, b) => a.chunk_index - b.chunk_index);
-    const bytes = concatChunks(chunks);
+    let bytes = concatChunks(chunks);
+    // Envelope v2: the last chunk carries a digest of the reassembled data.
+    const intact = await digestMatches(bytes, lastChunk);
+    if (chunks.some((chunk) => chunk.encoding === 'gzip')) {
+      bytes = await inflateGzip(bytes);
+    }
     const payload = parsePayload(bytes, requestId);
     payload.received_at = Date.now();
     payload.partial = false;
+    if (!intact) {
+      payload.note = 'Payload digest mismatch: chunks may be corrupt or duplicated.';
+    }
     await storeRequest(db, payload);
     await deleteChunks(db, chunks);
     return { request: payload, partial: false };
@@ -130,6 +156,8 @@ function parsePayload(bytes, requestId) {
           meta = {};
         }
         const bodyText = new TextDecoder().decode(bodyBytes);
+        // Older payloads don't announce a length.
+        const declared = meta.content_length ?? bodyBytes.length;
         return {
           id: requestId,
           timestamp: meta.timestamp || new Date().toISOString(),
@@ -137,9 +165,15 @@ function parsePayload(bytes, requestId) {
           path: meta.path || '/',
           query_string: meta.query_string || '',
           headers: meta.headers || {},
-          body: bodyText,
+          // Summarized multipart bodies arrive as a form object instead.
+          body: meta.form ? JSON.stringify(meta.form, null, 2) : bodyText,
           source_ip: meta.source_ip || '',
+          content_type: meta.content_type || '',
           content_length: bodyBytes.length,
+          note:
+            declared === bodyBytes.length
+              ? undefined
+              : `Body length mismatch: expected ${declared} bytes, got ${bodyBytes.length}.`,
         };
       }
     }
@@ -163,14 +197,28 @@ function parsePayload(bytes, requestId) {
   }
 }
 
-async function showSummary(request, partial) {
+// Coalesced hooks share a topic, so their notification is replaced, not stacked.
+async function showSummary(request, partial, topic) {
   const title = partial ? 'Partial webhook received' : 'Webhook received';
   const body = partial
     ? request.note || 'Some chunks did not arrive.'
     : `${request.method} ${request.path || ''}`;
   await self.registration.showNotification(title, {
     body,
-    tag: request.id,
+    tag: topic || request.id,
+    renotify: Boolean(topic),
+  });
+}
+
+// Shown from the first chunk; replaced (same tag) once the request is assembled.
+async function showEarlySummary(envelope) {
+  const { method, path, source, preview } = envelope.summary;
+  const title = source ? `Webhook from ${source}` : 'Webhook received';
+  const body = [`${method} ${path}`, preview].filter(Boolean).join('\n');
+  await self.registration.showNotification(title, {
+    body,
+    tag: envelope.topic || envelope.request_id,
+    renotify: Boolean(envelope.topic),
   });
 }
 
@@ -184,6 +232,16 @@ async function notifyClients(id, partial) {
   }
 }
 
+async function notifyHeartbeat() {
+  const clients = await self.clients.matchAll({
+    includeUncontrolled: true,
+    type: 'window',
+  });
+  for (const client of clients) {
+    client.postMessage({ type: 'heartbeat', at: Date.now() });
+  }
+}
+
 async function focusClient() {
   const clients = await self.clients.matchAll({
     type: 'window',
@@ -206,6 +264,11 @@ function storeChunk(db, envelope) {
     chunk_index: envelope.chunk_index,
     total_chunks: hasTotal ? envelope.total_chunks : null,
     is_last: Boolean(isLast),
+    encoding: envelope.encoding || null,
+    payload_sha256: envelope.payload_sha256 || null,
+    payload_len: Number.isInteger(envelope.payload_len) ? envelope.payload_len : null,
+    // First chunk only: the reassembled size, known before the rest arrives.
+    total_bytes: Number.isInteger(envelope.total_bytes) ? envelope.total_bytes : null,
     data: envelope.data,
     received_at: Date.now(),
   };
@@ -217,6 +280,15 @@ function storeChunk(db, envelope) {
   });
 }
 
+function getRequest(db, id) {
+  return new Promise((resolve, reject) => {
+    const tx = db.transaction(REQUESTS_STORE, 'readonly');
+    const request = tx.objectStore(REQUESTS_STORE).get(id);
+    request.onsuccess = () => resolve(request.result);
+    request.onerror = () => reject(request.error);
+  });
+}
+
 function storeRequest(db, payload) {
   return new Promise((resolve, reject) => {
     const tx = db.transaction(REQUESTS_STORE, 'readwrite');
@@ -248,6 +320,16 @@ function getChunksForRequest(db, requestId) {
   });
 }
 
+async function digestMatches(bytes, lastChunk) {
+  if (!lastChunk || !lastChunk.payload_sha256) return true;
+  if (lastChunk.payload_len !== null && lastChunk.payload_len !== bytes.length) {
+    return false;
+  }
+  const digest = new Uint8Array(await crypto.subtle.digest('SHA-256', bytes));
+  const hex = Array.from(digest, (byte) => byte.toString(16).padStart(2, '0')).join('');
+  return hex === lastChunk.payload_sha256;
+}
+
 function concatChunks(chunks) {
   const decoded = chunks.map((chunk) => base64ToBytes(chunk.data));
   const totalLength = decoded.reduce((sum, arr) => sum + arr.length, 0);
@@ -260,6 +342,34 @@ function concatChunks(chunks) {
   return output;
 }
 
+async function inflateGzip(bytes) {
+  const stream = new Blob([bytes])
+    .stream()
+    .pipeThrough(new DecompressionStream('gzip'));
+  return new Uint8Array(await new Response(stream).arrayBuffer());
+}
+
+let crcTable;
+
+// CRC-32 (IEEE), as the server computes over each chunk's raw bytes.
+function crc32(bytes) {
+  if (!crcTable) {
+    crcTable = new Uint32Array(256);
+    for (let n = 0; n < 256; n += 1) {
+      let c = n;
+      for (let k = 0; k < 8; k += 1) {
+        c = c & 1 ? 0xedb88320 ^ (c >>> 1) : c >>> 1;
+      }
+      crcTable[n] = c >>> 0;
+    }
+  }
+  let crc = 0xffffffff;
+  for (let i = 0; i < bytes.length; i += 1) {
+    crc = crcTable[(crc ^ bytes[i]) & 0xff] ^ (crc >>> 8);
+  }
+  return (crc ^ 0xffffffff) >>> 0;
+}
+
 function base64ToBytes(base64) {
   const binary = atob(base64);
   const bytes = new Uint8Array(binary.length);
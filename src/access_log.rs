@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use axum::response::Response;
+use tracing::{field::Empty, info, info_span, Span};
+
+use crate::error::AppError;
+
+// Stable log targets, so access lines can be filtered
+// (e.g. RUST_LOG=hook_access=info) or routed apart from diagnostics.
+pub const HOOK_ACCESS: &str = "hook_access";
+pub const SUBSCRIPTION_ACCESS: &str = "subscription_access";
+
+// Span for one hook request. The handler fills in the fields it learns as
+// it goes; `log_hook` emits them with the outcome when the hook is done.
+pub fn hook_span(uuid: &str, method: &str) -> Span {
+    info_span!(
+        target: HOOK_ACCESS,
+        "hook",
+        uuid = %uuid,
+        method = %method,
+        request_id = Empty,
+        source_ip = Empty,
+        body_bytes = Empty,
+        chunks = Empty,
+    )
+}
+
+// Record a field on the current hook span; a no-op outside one.
+pub fn record(field: &str, value: impl tracing::Value) {
+    Span::current().record(field, value);
+}
+
+pub fn log_hook(span: &Span, result: &Result<Response, AppError>, elapsed: Duration) {
+    let (status, outcome) = match result {
+        Ok(response) => (response.status(), "ok"),
+        Err(err) => (err.status, err.code.unwrap_or("error")),
+    };
+    info!(
+        target: HOOK_ACCESS,
+        parent: span,
+        status = status.as_u16(),
+        outcome,
+        duration_ms = elapsed.as_millis() as u64,
+    );
+}
+
+pub fn log_subscription(action: &str, uuid: &str) {
+    info!(target: SUBSCRIPTION_ACCESS, action, uuid = %uuid);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::subscriber::DefaultGuard;
+
+    // JSON log lines written while the returned guard is alive, as LOG_FORMAT=json
+    // would format them.
+    pub(crate) fn capture_json_logs() -> (Arc<Mutex<Vec<u8>>>, DefaultGuard) {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(move || Capture(writer.clone()))
+            .finish();
+        (buffer, tracing::subscriber::set_default(subscriber))
+    }
+
+    pub(crate) fn logged_lines(buffer: &Mutex<Vec<u8>>, target: &str) -> Vec<serde_json::Value> {
+        let buffer = buffer.lock().unwrap();
+        String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|line| line["target"] == target)
+            .collect()
+    }
+
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use redb::Database;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db::{db_put, is_reserved_id},
+    handlers::{validate_options, validate_subscription},
+    models::{StoredSubscription, SubscribeRequest},
+    verify::hash_delete_token,
+};
+
+// UUID_ALLOWLIST_FILE holds a JSON object of id -> subscribe request body.
+// Every entry is written at startup, and hooks only accept these ids.
+pub fn load_allowlist(
+    db: &Database,
+    cfg: &Config,
+    contents: &str,
+) -> anyhow::Result<HashSet<String>> {
+    let entries: HashMap<String, SubscribeRequest> = serde_json::from_str(contents)
+        .map_err(|err| anyhow::anyhow!("UUID_ALLOWLIST_FILE is not valid JSON: {err}"))?;
+    let mut ids = HashSet::with_capacity(entries.len());
+    for (uuid, request) in entries {
+        if uuid.is_empty()
+            || uuid.len() > 64
+            || !uuid.starts_with(&cfg.uuid_prefix)
+            || is_reserved_id(&uuid)
+            || !uuid
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        {
+            return Err(anyhow::anyhow!("UUID_ALLOWLIST_FILE: invalid id {uuid:?}"));
+        }
+        let invalid = |err: crate::error::AppError| {
+            anyhow::anyhow!("UUID_ALLOWLIST_FILE: {uuid}: {}", err.message)
+        };
+        validate_subscription(&request.subscription, &cfg.allowed_push_hosts).map_err(invalid)?;
+        validate_options(&request.options, cfg).map_err(invalid)?;
+
+        // The token is never revealed, so only the file can remove the entry.
+        let delete_token = Uuid::new_v4().simple().to_string();
+        let stored = StoredSubscription {
+            subscription: request.subscription,
+            created_at: Utc::now(),
+            delete_token: hash_delete_token(&uuid, &delete_token),
+            options: request.options,
+            last_active_at: None,
+            last_error: None,
+            last_error_at: None,
+        };
+        db_put(db, &uuid, &stored).map_err(|err| anyhow::anyhow!(err))?;
+        ids.insert(uuid);
+    }
+    Ok(ids)
+}
@@ -0,0 +1,206 @@
+use std::net::IpAddr;
+
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+
+// Comma-separated IPs or CIDRs; a bare IP is a single-host network.
+pub fn parse_trusted_proxies(value: &str) -> anyhow::Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            parse_cidr(item).ok_or_else(|| anyhow::anyhow!("invalid TRUSTED_PROXIES entry: {item}"))
+        })
+        .collect()
+}
+
+pub fn parse_cidr(item: &str) -> Option<IpNet> {
+    item.parse::<IpNet>()
+        .ok()
+        .or_else(|| item.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+// Subscription sender allowlist; entries were validated at subscribe time.
+pub fn source_allowed(ip: Option<IpAddr>, cidrs: &[String]) -> bool {
+    if cidrs.is_empty() {
+        return true;
+    }
+    ip.is_some_and(|ip| {
+        cidrs
+            .iter()
+            .filter_map(|cidr| parse_cidr(cidr))
+            .any(|net| net.contains(&ip))
+    })
+}
+
+// Resolve the real client behind proxies. With `trusted_proxies` set, forwarding
+// headers are only honoured when the peer is one of them, and the client is the
+// right-most untrusted entry. Otherwise each of `hops` proxies appends the address
+// it received from, so the client is the entry `hops` places from the right.
+// Anything further left is client-supplied and spoofable.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer_ip: Option<IpAddr>,
+    hops: usize,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    if !trusted_proxies.is_empty() {
+        let peer = peer_ip?;
+        if !is_trusted(peer, trusted_proxies) {
+            return Some(peer);
+        }
+        for entry in forwarded_chain(headers).iter().rev() {
+            let Ok(ip) = entry.parse::<IpAddr>() else {
+                return Some(peer);
+            };
+            if !is_trusted(ip, trusted_proxies) {
+                return Some(ip);
+            }
+        }
+        return Some(peer);
+    }
+
+    if hops == 0 {
+        return peer_ip;
+    }
+    let chain = forwarded_chain(headers);
+    let index = chain.len().saturating_sub(hops);
+    chain
+        .get(index)
+        .and_then(|entry| entry.parse().ok())
+        .or(peer_ip)
+}
+
+fn is_trusted(ip: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+// Client-to-proxy chain, oldest first: X-Forwarded-For, else Forwarded, else X-Real-IP.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<&str> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+    };
+
+    let chain: Vec<&str> = values("x-forwarded-for").map(strip_port).collect();
+    if !chain.is_empty() {
+        return chain;
+    }
+    // RFC 7239: `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`.
+    let chain: Vec<&str> = values("forwarded")
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| strip_port(value.trim_matches('"')))
+            })
+        })
+        .collect();
+    if !chain.is_empty() {
+        return chain;
+    }
+    values("x-real-ip").map(strip_port).take(1).collect()
+}
+
+// `[v6]:port` -> `v6`, `v4:port` -> `v4`; bare addresses pass through.
+fn strip_port(entry: &str) -> &str {
+    if let Some(rest) = entry.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match entry.split_once(':') {
+        Some((host, port)) if !port.contains(':') => host,
+        _ => entry,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn xff(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn client_ip_skips_trusted_hops_from_the_right() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        // The client spoofed 6.6.6.6; proxy 10.0.0.1 sits in front of the peer.
+        let headers = xff("6.6.6.6, 203.0.113.7, 10.0.0.1");
+
+        let ip = |hops| client_ip(&headers, Some(peer), hops, &[]).unwrap().to_string();
+        assert_eq!(ip(0), "10.0.0.2");
+        assert_eq!(ip(1), "10.0.0.1");
+        assert_eq!(ip(2), "203.0.113.7");
+
+        let headers = xff("6.6.6.6, not-an-ip");
+        assert_eq!(client_ip(&headers, Some(peer), 1, &[]), Some(peer));
+        assert_eq!(client_ip(&HeaderMap::new(), Some(peer), 2, &[]), Some(peer));
+    }
+
+    #[test]
+    fn trusted_proxies_match_ips_and_cidrs() {
+        let trusted = parse_trusted_proxies("127.0.0.1, 10.0.0.0/8, fd00::/8").unwrap();
+        assert!(is_trusted("127.0.0.1".parse().unwrap(), &trusted));
+        assert!(!is_trusted("127.0.0.2".parse().unwrap(), &trusted));
+        assert!(is_trusted("10.200.3.4".parse().unwrap(), &trusted));
+        assert!(!is_trusted("11.0.0.1".parse().unwrap(), &trusted));
+        assert!(is_trusted("fd12::1".parse().unwrap(), &trusted));
+        assert!(!is_trusted("2001:db8::1".parse().unwrap(), &trusted));
+        assert!(parse_trusted_proxies("10.0.0.0/33").is_err());
+        assert!(parse_trusted_proxies("nginx").is_err());
+    }
+
+    #[test]
+    fn source_allowlist_matches_v4_and_v6_cidrs() {
+        let cidrs = vec![
+            "3.18.12.0/22".to_string(),
+            "2001:db8:1::/48".to_string(),
+            "198.51.100.9".to_string(),
+        ];
+        let allowed = |ip: &str| source_allowed(Some(ip.parse().unwrap()), &cidrs);
+        assert!(allowed("3.18.15.255"));
+        assert!(!allowed("3.18.16.0"));
+        assert!(allowed("2001:db8:1:ffff::1"));
+        assert!(!allowed("2001:db8:2::1"));
+        assert!(allowed("198.51.100.9"));
+        assert!(!allowed("198.51.100.10"));
+        assert!(!source_allowed(None, &cidrs));
+        assert!(source_allowed(None, &[]));
+    }
+
+    #[test]
+    fn trusted_proxies_take_right_most_untrusted_entry() {
+        let trusted = parse_trusted_proxies("127.0.0.1, 10.0.0.0/8").unwrap();
+        let nginx: IpAddr = "127.0.0.1".parse().unwrap();
+        // Spoofed left-most entry, real client, then an internal load balancer.
+        let headers = xff("6.6.6.6, 203.0.113.7, 10.1.2.3");
+        let resolved = client_ip(&headers, Some(nginx), 0, &trusted).unwrap();
+        assert_eq!(resolved.to_string(), "203.0.113.7");
+
+        // An untrusted peer can't vouch for anyone.
+        let direct: IpAddr = "198.51.100.9".parse().unwrap();
+        assert_eq!(client_ip(&headers, Some(direct), 0, &trusted), Some(direct));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "forwarded",
+            HeaderValue::from_static(r#"for="[2001:db8::7]:4711";proto=https, for=10.0.0.5"#),
+        );
+        let resolved = client_ip(&headers, Some(nginx), 0, &trusted).unwrap();
+        assert_eq!(resolved.to_string(), "2001:db8::7");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", HeaderValue::from_static("203.0.113.9"));
+        let resolved = client_ip(&headers, Some(nginx), 0, &trusted).unwrap();
+        assert_eq!(resolved.to_string(), "203.0.113.9");
+    }
+}
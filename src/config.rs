@@ -1,72 +1,499 @@
-use std::env;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env, fs,
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use ipnet::IpNet;
+
+use crate::{client_ip::parse_trusted_proxies, models::OversizeMode};
+
+pub type Source<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+// The running config; a SIGHUP reload swaps in a new one.
+pub type SharedConfig = Arc<ArcSwap<Config>>;
+
+pub const MAX_HOOK_RESPONSE_BODY_BYTES: usize = 1024;
+pub const MAX_LABELS: usize = 16;
 
 #[derive(Clone)]
 pub struct Config {
     pub bind_addr: String,
     pub public_base_url: String,
+    pub uuid_prefix: String,
+    pub uuid_length: usize,
+    pub uuid_alphabet: UuidAlphabet,
+    pub uuid_allowlist_file: Option<String>,
     pub db_path: String,
     pub static_dir: String,
     pub serve_frontend: bool,
+    pub hide_server_header: bool,
+    pub log_format: LogFormat,
     pub cors_allow_any: bool,
     pub cors_origins: Vec<String>,
     pub allowed_push_hosts: Vec<String>,
     pub webhook_read_timeout_ms: u64,
+    pub max_webhook_age_secs: u64,
+    pub webhook_date_skew_secs: u64,
+    pub relay_preflight: bool,
+    pub serialize_hooks: bool,
+    pub live_streams_per_uuid: usize,
+    pub long_poll_timeout_ms: u64,
+    pub forward_query_string: bool,
+    pub summary_body_preview: bool,
+    pub trusted_proxy_hops: usize,
+    pub trusted_proxies: Vec<IpNet>,
+    pub client_cert_header: Option<String>,
+    pub capture_headers: Vec<String>,
+    pub hook_response_status: u16,
+    pub hook_response_body: String,
+    pub hook_response_content_type: String,
+    pub hook_ack_body: bool,
     pub vapid_public_key: String,
     pub vapid_private_key: String,
     pub vapid_subject: String,
-    pub max_payload_bytes: usize,
+    pub push_request_timeout_ms: u64,
+    pub sync_delivery_timeout_ms: u64,
+    pub push_ttl_secs: u32,
+    pub push_ttl_header: Option<String>,
+    pub record_push_errors: bool,
+    pub push_retry_base_ms: u64,
+    pub push_retry_max_ms: u64,
+    pub max_body_bytes: usize,
+    pub max_meta_bytes: usize,
+    pub oversize_mode: OversizeMode,
+    pub multipart_mode: MultipartMode,
+    pub multipart_max_bytes: usize,
+    pub oversize_strikes: u32,
+    pub heartbeat_interval_minutes: u64,
+    pub body_spill_bytes: usize,
+    pub oversize_cooldown_secs: u64,
     pub chunk_data_bytes: usize,
     pub chunk_delay_ms: u64,
+    pub compress_over_bytes: usize,
+    pub max_json_depth: usize,
+    pub max_json_elements: usize,
     pub subscription_ttl_days: i64,
+    pub subscription_ttl_from: TtlBasis,
+    pub max_subscriptions: u64,
+    pub require_owner_label: bool,
     pub rate_limit_per_minute: u32,
+    pub dedup_window_secs: u64,
+    pub content_dedup: bool,
+    pub content_dedup_window_secs: u64,
+    pub coalesce_window_secs: u64,
+    pub history_enabled: bool,
+    pub history_keep_per_uuid: usize,
     pub queue_db_path: String,
     pub queue_max_bytes: usize,
+    pub queue_max_bytes_per_uuid: usize,
+    pub queue_full_policy: QueueFullPolicy,
     pub queue_workers: usize,
+    pub queue_max_attempts: u32,
+    pub queue_idle_sleep_ms: u64,
+    pub queue_write_buffer: usize,
+    pub queue_visibility_timeout_ms: u64,
+    pub shutdown_drain_timeout_ms: u64,
+    pub queue_record_ttl_secs: u64,
+    pub dead_letter_ttl_days: i64,
+    pub dead_letter_max_bytes: u64,
+    pub admin_token: Option<String>,
+}
+
+// How multipart/form-data bodies are relayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultipartMode {
+    // Push the MIME body as received.
+    Raw,
+    // Parse it: text fields inline, file parts as size stubs.
+    Summarize,
+}
+
+// Characters generated subscription ids are drawn from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UuidAlphabet {
+    // 0-9a-f.
+    Hex,
+    // 0-9A-Za-z; ~6 bits per character instead of 4.
+    Base62,
+}
+
+// How log lines are written to stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line, for log aggregators.
+    Json,
+}
+
+// What SUBSCRIPTION_TTL_DAYS counts from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TtlBasis {
+    // Subscribing; even busy subscriptions expire.
+    Created,
+    // The latest relayed hook, else subscribing; only idle ones expire.
+    LastActive,
+}
+
+// What the disk queue does when a new record doesn't fit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueFullPolicy {
+    // Refuse the new record with 503.
+    Reject,
+    // Evict the oldest pending records to make room.
+    DropOldest,
 }
 
 impl Config {
+    // Env vars, over CONFIG_FILE's settings when one is given.
     pub fn from_env() -> anyhow::Result<Self> {
-        let bind_addr = env_or("BIND_ADDR", "0.0.0.0:3000");
-        let public_base_url = env_or("PUBLIC_BASE_URL", "http://localhost:3000");
-        let db_path = env_or("DB_PATH", "httptester.redb");
-        let static_dir = env_or("STATIC_DIR", "frontend");
-        let serve_frontend = env_or_parse("SERVE_FRONTEND", true)?;
-        let cors_raw = env_or("CORS_ORIGINS", "http://localhost:3000");
+        let env = |key: &str| env::var(key).ok();
+        let Some(path) = env_opt(&env, "CONFIG_FILE") else {
+            return Self::from_source(&env);
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|err| anyhow::anyhow!("CONFIG_FILE {path}: {err}"))?;
+        let file = parse_config_file(&text).map_err(|err| anyhow::anyhow!("{path}: {err}"))?;
+        Self::from_layers(&env, &file).map_err(|err| anyhow::anyhow!("{path}: {err}"))
+    }
+
+    // For a reload: keeps `running`'s values for settings only read at
+    // startup (listeners, databases, keys, worker pools, sizes fixed into
+    // state) and returns the ones that were changed.
+    pub fn keep_restart_only(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        macro_rules! keep {
+            ($($field:ident),* $(,)?) => {$(
+                if self.$field != running.$field {
+                    ignored.push(stringify!($field));
+                    self.$field = running.$field.clone();
+                }
+            )*};
+        }
+        keep!(
+            bind_addr,
+            public_base_url,
+            uuid_prefix,
+            uuid_allowlist_file,
+            db_path,
+            static_dir,
+            serve_frontend,
+            hide_server_header,
+            log_format,
+            cors_allow_any,
+            cors_origins,
+            serialize_hooks,
+            live_streams_per_uuid,
+            vapid_public_key,
+            vapid_private_key,
+            vapid_subject,
+            push_request_timeout_ms,
+            oversize_strikes,
+            oversize_cooldown_secs,
+            subscription_ttl_days,
+            subscription_ttl_from,
+            dedup_window_secs,
+            content_dedup_window_secs,
+            queue_db_path,
+            queue_max_bytes,
+            queue_max_bytes_per_uuid,
+            queue_full_policy,
+            queue_workers,
+            queue_write_buffer,
+            queue_visibility_timeout_ms,
+            shutdown_drain_timeout_ms,
+            dead_letter_ttl_days,
+        );
+        ignored
+    }
+
+    // `source` wins over `file`, whose keys are the env names in lowercase.
+    fn from_layers(source: Source, file: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let read = RefCell::new(HashSet::new());
+        let cfg = Self::from_source(&|key| {
+            read.borrow_mut().insert(key.to_ascii_lowercase());
+            source(key).or_else(|| file.get(&key.to_ascii_lowercase()).cloned())
+        })?;
+        // Every key is read once the config builds, so the rest are typos.
+        let read = read.into_inner();
+        let mut unknown: Vec<_> = file
+            .keys()
+            .filter(|key| !read.contains(*key))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(anyhow::anyhow!("unknown config key {}", unknown.join(", ")));
+        }
+        Ok(cfg)
+    }
+
+    // Builds the config from any key lookup (process env in production).
+    pub fn from_source(source: Source) -> anyhow::Result<Self> {
+        let bind_addr = env_or(source, "BIND_ADDR", "0.0.0.0:3000");
+        let public_base_url = env_or(source, "PUBLIC_BASE_URL", "http://localhost:3000");
+        // Namespaces generated ids per instance, e.g. "eu-"; hooks without it are unknown.
+        let uuid_prefix = env_or(source, "UUID_PREFIX", "");
+        let uuid_length = env_or_parse(source, "UUID_LENGTH", 12)?;
+        // Closed instance: only these pre-provisioned subscriptions exist.
+        let uuid_allowlist_file = env_opt(source, "UUID_ALLOWLIST_FILE");
+        let uuid_alphabet = match env_or(source, "UUID_ALPHABET", "hex").as_str() {
+            "hex" => UuidAlphabet::Hex,
+            "base62" => UuidAlphabet::Base62,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "UUID_ALPHABET must be hex or base62, got {other}"
+                ));
+            }
+        };
+        let db_path = env_or(source, "DB_PATH", "httptester.redb");
+        let static_dir = env_or(source, "STATIC_DIR", "frontend");
+        let serve_frontend = env_or_parse(source, "SERVE_FRONTEND", true)?;
+        // Responses name the server and its version unless hidden.
+        let hide_server_header = env_or_parse(source, "HIDE_SERVER_HEADER", false)?;
+        let log_format = match env_or(source, "LOG_FORMAT", "text").as_str() {
+            "text" => LogFormat::Text,
+            "json" => LogFormat::Json,
+            other => {
+                return Err(anyhow::anyhow!("LOG_FORMAT must be text or json, got {other}"));
+            }
+        };
+        let cors_raw = env_or(source, "CORS_ORIGINS", "http://localhost:3000");
         let (cors_allow_any, cors_origins) = parse_cors_origins(&cors_raw);
         // Host allowlist prevents SSRF against arbitrary endpoints.
         let allowed_push_hosts_raw = env_or(
+            source,
             "ALLOWED_PUSH_HOSTS",
             "fcm.googleapis.com,updates.push.services.mozilla.com,wns.windows.com,notify.windows.com,web.push.apple.com",
         );
         let allowed_push_hosts = parse_list(&allowed_push_hosts_raw);
-        let webhook_read_timeout_ms = env_or_parse("WEBHOOK_READ_TIMEOUT_MS", 3000)?;
-        let vapid_public_key = env::var("VAPID_PUBLIC_KEY")
-            .map_err(|_| anyhow::anyhow!("VAPID_PUBLIC_KEY is required"))?;
-        let vapid_private_key = env::var("VAPID_PRIVATE_KEY")
-            .map_err(|_| anyhow::anyhow!("VAPID_PRIVATE_KEY is required"))?;
-        let vapid_subject = env_or("VAPID_SUBJECT", "mailto:admin@example.com");
-        let max_payload_bytes = env_or_parse("MAX_PAYLOAD_BYTES", 100 * 1024)?;
-        let chunk_data_bytes = env_or_parse("CHUNK_DATA_BYTES", 2400)?;
-        let chunk_delay_ms = env_or_parse("CHUNK_DELAY_MS", 50)?;
-        let subscription_ttl_days = env_or_parse("SUBSCRIPTION_TTL_DAYS", 30)?;
-        let rate_limit_per_minute = env_or_parse("RATE_LIMIT_PER_MINUTE", 60)?;
-        let queue_db_path = env_or("QUEUE_DB_PATH", "httptester.queue.redb");
-        let queue_max_bytes = env_or_parse("QUEUE_MAX_BYTES", 1_073_741_824)?;
-        let queue_workers = env_or_parse("QUEUE_WORKERS", 8)?;
+        let webhook_read_timeout_ms = env_or_parse(source, "WEBHOOK_READ_TIMEOUT_MS", 3000)?;
+        // Default hook reply; subscriptions may override it.
+        let hook_response_status = env_or_parse(source, "HOOK_RESPONSE_STATUS", 202)?;
+        let hook_response_body = env_or(source, "HOOK_RESPONSE_BODY", "");
+        let hook_response_content_type =
+            env_or(source, "HOOK_RESPONSE_CONTENT_TYPE", "text/plain; charset=utf-8");
+        // Without a configured body, reply with the request id and chunk count.
+        let hook_ack_body = env_or_parse(source, "HOOK_ACK_BODY", true)?;
+        let vapid_public_key = source("VAPID_PUBLIC_KEY")
+            .ok_or_else(|| anyhow::anyhow!("VAPID_PUBLIC_KEY is required"))?;
+        let vapid_private_key = source("VAPID_PRIVATE_KEY")
+            .ok_or_else(|| anyhow::anyhow!("VAPID_PRIVATE_KEY is required"))?;
+        let vapid_subject = env_or(source, "VAPID_SUBJECT", "mailto:admin@example.com");
+        // Bounds the subscriptions table so subscribe can't fill the disk; 0 is unlimited.
+        let max_subscriptions = env_or_parse(source, "MAX_SUBSCRIPTIONS", 0)?;
+        // Shared instances can insist every subscription names its owner.
+        let require_owner_label = env_or_parse(source, "REQUIRE_OWNER_LABEL", false)?;
+        // Reject deliveries whose Date header is older than this; 0 disables.
+        let max_webhook_age_secs = env_or_parse(source, "MAX_WEBHOOK_AGE_SECONDS", 0)?;
+        let webhook_date_skew_secs = env_or_parse(source, "WEBHOOK_DATE_SKEW_SECONDS", 60)?;
+        // OPTIONS/HEAD probes are answered directly unless explicitly relayed.
+        let relay_preflight = env_or_parse(source, "RELAY_PREFLIGHT", false)?;
+        // Enqueue one hook per uuid at a time, so notifications keep arrival order.
+        let serialize_hooks = env_or_parse(source, "SERIALIZE_HOOKS", false)?;
+        // Concurrent GET /api/subscribe/:uuid/stream watchers per uuid; 0 disables it.
+        let live_streams_per_uuid = env_or_parse(source, "LIVE_STREAMS_PER_UUID", 2)?;
+        // How long GET /api/subscribe/:uuid/poll waits for a hook before 204.
+        let long_poll_timeout_ms = env_or_parse(source, "LONG_POLL_TIMEOUT_MS", 30_000)?;
+        // Query strings often carry tokens; allow keeping them off the device.
+        let forward_query_string = env_or_parse(source, "FORWARD_QUERY_STRING", true)?;
+        // Notification summaries quote the start of text bodies unless disabled.
+        let summary_body_preview = env_or_parse(source, "SUMMARY_BODY_PREVIEW", true)?;
+        // Proxies in front of the server; 0 ignores X-Forwarded-For entirely.
+        let trusted_proxy_hops = env_or_parse(source, "TRUSTED_PROXY_HOPS", 0)?;
+        // Peers allowed to set forwarding headers; takes precedence over the hop count.
+        let trusted_proxies = parse_trusted_proxies(&env_or(source, "TRUSTED_PROXIES", ""))?;
+        // Header the TLS-terminating proxy uses for the client cert subject.
+        let client_cert_header =
+            env_opt(source, "CLIENT_CERT_HEADER").map(|value| value.to_ascii_lowercase());
+        // Headers relayed in hook metadata; "*" keeps all of them.
+        let capture_headers = parse_list(&env_or(
+            source,
+            "CAPTURE_HEADERS",
+            "content-type,user-agent,x-request-id,x-github-event,stripe-signature",
+        ))
+        .into_iter()
+        .map(|name| name.to_ascii_lowercase())
+        .collect();
+        let push_request_timeout_ms = env_or_parse(source, "PUSH_REQUEST_TIMEOUT_MS", 10_000)?;
+        // Bounds all pushes of one `delivery=sync` hook together.
+        let sync_delivery_timeout_ms = env_or_parse(source, "SYNC_DELIVERY_TIMEOUT_MS", 10_000)?;
+        // How long push services hold an undelivered push; also the cap for
+        // a TTL taken from PUSH_TTL_HEADER.
+        let push_ttl_secs = env_or_parse(source, "PUSH_TTL_SECS", 60)?;
+        let push_ttl_header = env_opt(source, "PUSH_TTL_HEADER").map(|name| name.to_lowercase());
+        // Keep the latest push failure on the subscription for GET /api/subscribe/:uuid.
+        let record_push_errors = env_or_parse(source, "RECORD_PUSH_ERRORS", true)?;
+        // Failed pushes wait base * 2^(attempt - 1), +/-20%, up to the max,
+        // unless the push service sends Retry-After.
+        let push_retry_base_ms = env_or_parse(source, "PUSH_RETRY_BASE_MS", 500)?;
+        let push_retry_max_ms = env_or_parse(source, "PUSH_RETRY_MAX_MS", 60_000)?;
+        // Body and metadata have separate budgets, so header-heavy senders
+        // don't shrink the body limit. MAX_PAYLOAD_BYTES is the old name.
+        let legacy_max_body_bytes = env_or_parse(source, "MAX_PAYLOAD_BYTES", 100 * 1024)?;
+        let max_body_bytes = env_or_parse(source, "MAX_BODY_BYTES", legacy_max_body_bytes)?;
+        let max_meta_bytes = env_or_parse(source, "MAX_META_BYTES", 16 * 1024)?;
+        let oversize_mode = match env_or(source, "OVERSIZE_MODE", "reject").as_str() {
+            "reject" => OversizeMode::Reject,
+            "truncate" => OversizeMode::Truncate,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "OVERSIZE_MODE must be reject or truncate, got {other}"
+                ));
+            }
+        };
+        let multipart_mode = match env_or(source, "MULTIPART_MODE", "raw").as_str() {
+            "raw" => MultipartMode::Raw,
+            "summarize" => MultipartMode::Summarize,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "MULTIPART_MODE must be raw or summarize, got {other}"
+                ));
+            }
+        };
+        // Raw form bodies may exceed MAX_BODY_BYTES; only the summary must fit.
+        let multipart_max_bytes = env_or_parse(source, "MULTIPART_MAX_BYTES", 10 * 1024 * 1024)?;
+        // After this many 413s a UUID's oversized hooks are refused up front; 0 disables.
+        let oversize_strikes = env_or_parse(source, "OVERSIZE_STRIKES", 5)?;
+        let oversize_cooldown_secs = env_or_parse(source, "OVERSIZE_COOLDOWN_SECS", 300)?;
+        // Buffered bodies past this go to a temp file until chunked; 0 disables.
+        let body_spill_bytes = env_or_parse(source, "BODY_SPILL_BYTES", 256 * 1024)?;
+        let chunk_data_bytes = env_or_parse(source, "CHUNK_DATA_BYTES", 2400)?;
+        let chunk_delay_ms = env_or_parse(source, "CHUNK_DELAY_MS", 50)?;
+        // 0 disables compression and keeps the fully streaming path.
+        let compress_over_bytes = env_or_parse(source, "COMPRESS_OVER_BYTES", 4096)?;
+        // Bodies beyond these limits are relayed untouched rather than parsed.
+        let max_json_depth = env_or_parse(source, "MAX_JSON_DEPTH", 32)?;
+        let max_json_elements = env_or_parse(source, "MAX_JSON_ELEMENTS", 10_000)?;
+        let subscription_ttl_days = env_or_parse(source, "SUBSCRIPTION_TTL_DAYS", 30)?;
+        let subscription_ttl_from =
+            match env_or(source, "SUBSCRIPTION_TTL_FROM", "last_active").as_str() {
+                "created" => TtlBasis::Created,
+                "last_active" => TtlBasis::LastActive,
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "SUBSCRIPTION_TTL_FROM must be created or last_active, got {other}"
+                    ));
+                }
+            };
+        // For subscriptions with options.heartbeat; 0 disables heartbeats.
+        let heartbeat_interval_minutes = env_or_parse(source, "HEARTBEAT_INTERVAL_MINUTES", 60)?;
+        let rate_limit_per_minute = env_or_parse(source, "RATE_LIMIT_PER_MINUTE", 60)?;
+        // Repeats of an Idempotency-Key within this window are not relayed; 0 disables.
+        let dedup_window_secs = env_or_parse(source, "DEDUP_WINDOW_SECS", 600)?;
+        // Identical method, path and body within this window are not relayed
+        // again. Needs the whole body, so it is opt-in.
+        let content_dedup = env_or_parse(source, "CONTENT_DEDUP", false)?;
+        let content_dedup_window_secs = env_or_parse(source, "CONTENT_DEDUP_WINDOW_SECS", 10)?;
+        // Default window for subscriptions that coalesce repeated hooks.
+        let coalesce_window_secs = env_or_parse(source, "COALESCE_WINDOW_SECS", 300)?;
+        // History keeps bodies server-side, so it is opt-in.
+        let history_enabled = env_or_parse(source, "HISTORY_ENABLED", false)?;
+        let history_keep_per_uuid = env_or_parse(source, "HISTORY_KEEP_PER_UUID", 50)?;
+        let queue_db_path = env_or(source, "QUEUE_DB_PATH", "httptester.queue.redb");
+        let queue_max_bytes = env_or_parse(source, "QUEUE_MAX_BYTES", 1_073_741_824)?;
+        // One subscriber's share of the queue; past it its hooks get 429. 0 disables.
+        let queue_max_bytes_per_uuid = env_or_parse(source, "QUEUE_MAX_BYTES_PER_UUID", 262_144)?;
+        let queue_full_policy = match env_or(source, "QUEUE_FULL_POLICY", "reject").as_str() {
+            "reject" => QueueFullPolicy::Reject,
+            "drop_oldest" => QueueFullPolicy::DropOldest,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "QUEUE_FULL_POLICY must be reject or drop_oldest, got {other}"
+                ));
+            }
+        };
+        let queue_workers = env_or_parse(source, "QUEUE_WORKERS", 8)?;
+        // Pushes per record before it is dead-lettered (counting the first).
+        let queue_max_attempts = env_or_parse(source, "QUEUE_MAX_ATTEMPTS", 5)?;
+        // Idle workers are woken by enqueues; this rechecks for delayed records.
+        let queue_idle_sleep_ms = env_or_parse(source, "QUEUE_IDLE_SLEEP_MS", 50)?;
+        // Enqueues waiting for the single queue writer before callers block.
+        let queue_write_buffer = env_or_parse(source, "QUEUE_WRITE_BUFFER", 1024)?;
+        // Undeliverable records are kept this long for inspection; 0 keeps them.
+        let dead_letter_ttl_days = env_or_parse(source, "DEAD_LETTER_TTL_DAYS", 7)?;
+        // Past this, the oldest dead letters are evicted to make room.
+        let dead_letter_max_bytes = env_or_parse(source, "DEAD_LETTER_MAX_BYTES", 67_108_864)?;
+        // Admin endpoints are disabled (404) unless a token is configured.
+        let admin_token = env_opt(source, "ADMIN_TOKEN");
+        // Inflight records older than this are handed to another worker.
+        let queue_visibility_timeout_ms =
+            env_or_parse(source, "QUEUE_VISIBILITY_TIMEOUT_MS", 60_000)?;
+        // On shutdown, workers get this long to push what is still queued.
+        let shutdown_drain_timeout_ms = env_or_parse(source, "SHUTDOWN_DRAIN_TIMEOUT_MS", 10_000)?;
+        // Records queued longer than this are dead-lettered unsent; 0 keeps them.
+        let queue_record_ttl_secs = env_or_parse(source, "QUEUE_RECORD_TTL_SECS", 900)?;
 
         // Guardrail checks for nonsensical configuration.
         if chunk_data_bytes == 0 {
             return Err(anyhow::anyhow!("CHUNK_DATA_BYTES must be > 0"));
         }
-        if max_payload_bytes == 0 {
-            return Err(anyhow::anyhow!("MAX_PAYLOAD_BYTES must be > 0"));
+        if max_body_bytes == 0 {
+            return Err(anyhow::anyhow!("MAX_BODY_BYTES must be > 0"));
+        }
+        if max_meta_bytes == 0 {
+            return Err(anyhow::anyhow!("MAX_META_BYTES must be > 0"));
         }
         if queue_max_bytes == 0 {
             return Err(anyhow::anyhow!("QUEUE_MAX_BYTES must be > 0"));
         }
+        if push_request_timeout_ms == 0 {
+            return Err(anyhow::anyhow!("PUSH_REQUEST_TIMEOUT_MS must be > 0"));
+        }
+        if push_retry_base_ms == 0 || push_retry_max_ms < push_retry_base_ms {
+            return Err(anyhow::anyhow!(
+                "PUSH_RETRY_BASE_MS must be > 0 and at most PUSH_RETRY_MAX_MS"
+            ));
+        }
+        if queue_visibility_timeout_ms <= push_request_timeout_ms {
+            return Err(anyhow::anyhow!(
+                "QUEUE_VISIBILITY_TIMEOUT_MS must exceed PUSH_REQUEST_TIMEOUT_MS"
+            ));
+        }
+        if history_enabled && history_keep_per_uuid == 0 {
+            return Err(anyhow::anyhow!("HISTORY_KEEP_PER_UUID must be > 0"));
+        }
+        if long_poll_timeout_ms == 0 {
+            return Err(anyhow::anyhow!("LONG_POLL_TIMEOUT_MS must be > 0"));
+        }
+        if content_dedup && content_dedup_window_secs == 0 {
+            return Err(anyhow::anyhow!("CONTENT_DEDUP_WINDOW_SECS must be > 0"));
+        }
         if queue_workers == 0 {
             return Err(anyhow::anyhow!("QUEUE_WORKERS must be > 0"));
         }
+        if !(1..=50).contains(&queue_max_attempts) {
+            return Err(anyhow::anyhow!("QUEUE_MAX_ATTEMPTS must be between 1 and 50"));
+        }
+        if queue_idle_sleep_ms == 0 {
+            return Err(anyhow::anyhow!("QUEUE_IDLE_SLEEP_MS must be > 0"));
+        }
+        if !(16..=65_536).contains(&queue_write_buffer) {
+            return Err(anyhow::anyhow!("QUEUE_WRITE_BUFFER must be between 16 and 65536"));
+        }
+        if dead_letter_max_bytes == 0 {
+            return Err(anyhow::anyhow!("DEAD_LETTER_MAX_BYTES must be > 0"));
+        }
+        if !(200..=299).contains(&hook_response_status) {
+            return Err(anyhow::anyhow!("HOOK_RESPONSE_STATUS must be 200-299"));
+        }
+        if hook_response_body.len() > MAX_HOOK_RESPONSE_BODY_BYTES {
+            return Err(anyhow::anyhow!("HOOK_RESPONSE_BODY must be <= 1024 bytes"));
+        }
+        if uuid_prefix.len() > 16
+            || !uuid_prefix
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        {
+            return Err(anyhow::anyhow!(
+                "UUID_PREFIX must be <= 16 chars of [A-Za-z0-9_-]"
+            ));
+        }
+        if !(6..=64).contains(&uuid_length) {
+            return Err(anyhow::anyhow!("UUID_LENGTH must be 6-64"));
+        }
         if queue_max_bytes > u32::MAX as usize {
             return Err(anyhow::anyhow!("QUEUE_MAX_BYTES must fit in u32"));
         }
@@ -74,40 +501,139 @@ impl Config {
         Ok(Self {
             bind_addr,
             public_base_url,
+            uuid_prefix,
+            uuid_length,
+            uuid_alphabet,
+            uuid_allowlist_file,
             db_path,
             static_dir,
             serve_frontend,
+            hide_server_header,
+            log_format,
             cors_allow_any,
             cors_origins,
             allowed_push_hosts,
             webhook_read_timeout_ms,
+            max_webhook_age_secs,
+            webhook_date_skew_secs,
+            relay_preflight,
+            serialize_hooks,
+            live_streams_per_uuid,
+            long_poll_timeout_ms,
+            forward_query_string,
+            summary_body_preview,
+            trusted_proxy_hops,
+            trusted_proxies,
+            client_cert_header,
+            capture_headers,
+            hook_response_status,
+            hook_response_body,
+            hook_response_content_type,
+            hook_ack_body,
             vapid_public_key,
             vapid_private_key,
             vapid_subject,
-            max_payload_bytes,
+            push_request_timeout_ms,
+            sync_delivery_timeout_ms,
+            push_ttl_secs,
+            push_ttl_header,
+            record_push_errors,
+            push_retry_base_ms,
+            push_retry_max_ms,
+            max_body_bytes,
+            max_meta_bytes,
+            oversize_mode,
+            multipart_mode,
+            multipart_max_bytes,
+            oversize_strikes,
+            heartbeat_interval_minutes,
+            body_spill_bytes,
+            oversize_cooldown_secs,
             chunk_data_bytes,
             chunk_delay_ms,
+            compress_over_bytes,
+            max_json_depth,
+            max_json_elements,
             subscription_ttl_days,
+            subscription_ttl_from,
+            max_subscriptions,
+            require_owner_label,
             rate_limit_per_minute,
+            dedup_window_secs,
+            content_dedup,
+            content_dedup_window_secs,
+            coalesce_window_secs,
+            history_enabled,
+            history_keep_per_uuid,
             queue_db_path,
             queue_max_bytes,
+            queue_max_bytes_per_uuid,
+            queue_full_policy,
             queue_workers,
+            queue_max_attempts,
+            queue_idle_sleep_ms,
+            queue_write_buffer,
+            queue_visibility_timeout_ms,
+            shutdown_drain_timeout_ms,
+            queue_record_ttl_secs,
+            dead_letter_ttl_days,
+            dead_letter_max_bytes,
+            admin_token,
         })
     }
 }
 
-fn env_or(key: &str, default: &str) -> String {
-    env::var(key).unwrap_or_else(|_| default.to_string())
+// Unset or blank values mean "not configured".
+fn env_opt(source: Source, key: &str) -> Option<String> {
+    source(key)
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
 }
 
-fn env_or_parse<T>(key: &str, default: T) -> anyhow::Result<T>
+fn env_or(source: Source, key: &str, default: &str) -> String {
+    source(key).unwrap_or_else(|| default.to_string())
+}
+
+fn env_or_parse<T>(source: Source, key: &str, default: T) -> anyhow::Result<T>
 where
     T: std::str::FromStr,
     T::Err: std::error::Error + Send + Sync + 'static,
 {
-    match env::var(key) {
-        Ok(value) => Ok(value.parse()?),
-        Err(_) => Ok(default),
+    match source(key) {
+        Some(value) => value
+            .parse()
+            .map_err(|err| anyhow::anyhow!("{key} is invalid ({value:?}): {err}")),
+        None => Ok(default),
+    }
+}
+
+// Flattens a CONFIG_FILE into env-style values: lists join with commas.
+fn parse_config_file(text: &str) -> anyhow::Result<HashMap<String, String>> {
+    let table: toml::Table = toml::from_str(text)?;
+    let mut values = HashMap::new();
+    for (key, value) in table {
+        let value = match value {
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(|item| config_scalar(&key, item))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join(","),
+            value => config_scalar(&key, value)?,
+        };
+        values.insert(key, value);
+    }
+    Ok(values)
+}
+
+fn config_scalar(key: &str, value: toml::Value) -> anyhow::Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(anyhow::anyhow!(
+            "config key {key} must be a string, number, boolean or list of them"
+        )),
     }
 }
 
@@ -128,3 +654,76 @@ fn parse_list(value: &str) -> Vec<String> {
         .filter(|item| !item.is_empty())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn required(key: &str) -> Option<String> {
+        match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn config_file_values_apply_under_env_overrides() {
+        let file = parse_config_file(
+            r#"
+            queue_workers = 3
+            history_enabled = true
+            public_base_url = "https://hooks.example.com"
+            cors_origins = ["https://a.example.com", "https://b.example.com"]
+            "#,
+        )
+        .unwrap();
+        let cfg = Config::from_layers(
+            &|key| match key {
+                "QUEUE_WORKERS" => Some("5".to_string()),
+                _ => required(key),
+            },
+            &file,
+        )
+        .unwrap();
+        assert_eq!(cfg.queue_workers, 5);
+        assert!(cfg.history_enabled);
+        assert_eq!(cfg.public_base_url, "https://hooks.example.com");
+        assert_eq!(cfg.cors_origins, ["https://a.example.com", "https://b.example.com"]);
+    }
+
+    #[test]
+    fn config_file_errors_name_the_key() {
+        let file = parse_config_file("queue_wrokers = 3").unwrap();
+        let Err(err) = Config::from_layers(&required, &file) else {
+            panic!("config built");
+        };
+        assert_eq!(err.to_string(), "unknown config key queue_wrokers");
+
+        let file = parse_config_file("queue_workers = \"many\"").unwrap();
+        let Err(err) = Config::from_layers(&required, &file) else {
+            panic!("config built");
+        };
+        assert!(err.to_string().starts_with("QUEUE_WORKERS is invalid"), "{err}");
+
+        let err = parse_config_file("[queue]\nworkers = 3").unwrap_err();
+        assert!(err.to_string().contains("config key queue must be"), "{err}");
+        assert!(parse_config_file("queue_workers = ").is_err());
+    }
+
+    #[test]
+    fn reloads_keep_restart_only_settings() {
+        let running = Config::from_source(&required).unwrap();
+        let mut next = Config::from_source(&|key| match key {
+            "BIND_ADDR" => Some("0.0.0.0:4000".to_string()),
+            "QUEUE_WORKERS" => Some("2".to_string()),
+            "RATE_LIMIT_PER_MINUTE" => Some("5".to_string()),
+            "CHUNK_DELAY_MS" => Some("10".to_string()),
+            _ => required(key),
+        })
+        .unwrap();
+        assert_eq!(next.keep_restart_only(&running), ["bind_addr", "queue_workers"]);
+        assert_eq!(next.bind_addr, running.bind_addr);
+        assert_eq!(next.queue_workers, running.queue_workers);
+        assert_eq!((next.rate_limit_per_minute, next.chunk_delay_ms), (5, 10));
+    }
+}
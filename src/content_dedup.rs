@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+// Hashes remembered per uuid; the least recently seen is dropped first.
+const HASHES_PER_UUID: usize = 32;
+// Uuids tracked at once, so the cache stays bounded however many are hit.
+const MAX_UUIDS: usize = 10_000;
+
+// Recently relayed hooks by sha256 of method, path and body, so a sender
+// retrying the same payload within the window isn't relayed twice.
+pub struct ContentDedup {
+    window: Duration,
+    inner: Mutex<HashMap<String, VecDeque<Seen>>>,
+}
+
+struct Seen {
+    hash: [u8; 32],
+    at: Instant,
+}
+
+// A hash held for a hook while it is relayed. Dropped without `keep`, as when
+// the relay fails, it is released so the sender's retry goes through.
+pub struct Reservation<'a> {
+    dedup: &'a ContentDedup,
+    uuid: &'a str,
+    hash: [u8; 32],
+    kept: bool,
+}
+
+impl Reservation<'_> {
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let mut map = self.dedup.inner.lock().unwrap();
+        if let Some(recent) = map.get_mut(self.uuid) {
+            recent.retain(|seen| seen.hash != self.hash);
+        }
+    }
+}
+
+pub fn content_hash(method: &str, path: &str, mut body: impl Read) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    // Length-prefixed, so fields can't run into each other.
+    for field in [method, path] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    io::copy(&mut body, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+impl ContentDedup {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // None if `hash` was recorded for the uuid within the window; otherwise
+    // it is recorded now, under the same lock, so a concurrent retry of a
+    // hook still being relayed is caught too.
+    pub fn check_and_insert<'a>(
+        &'a self,
+        uuid: &'a str,
+        hash: [u8; 32],
+    ) -> Option<Reservation<'a>> {
+        let mut map = self.inner.lock().unwrap();
+        if let Some(recent) = map.get_mut(uuid)
+            && let Some(index) = recent.iter().position(|seen| seen.hash == hash)
+            && recent[index].at.elapsed() < self.window
+        {
+            // Keep a hash that is still being retried from being evicted.
+            let seen = recent.remove(index).unwrap();
+            recent.push_back(seen);
+            return None;
+        }
+        if !map.contains_key(uuid) && map.len() >= MAX_UUIDS {
+            map.retain(|_, recent| {
+                recent.retain(|seen| seen.at.elapsed() < self.window);
+                !recent.is_empty()
+            });
+            if map.len() >= MAX_UUIDS {
+                // Still full of live entries: make room by dropping any one uuid.
+                let victim = map.keys().next().cloned();
+                if let Some(victim) = victim {
+                    map.remove(&victim);
+                }
+            }
+        }
+        let recent = map.entry(uuid.to_string()).or_default();
+        recent.retain(|seen| seen.hash != hash && seen.at.elapsed() < self.window);
+        if recent.len() >= HASHES_PER_UUID {
+            recent.pop_front();
+        }
+        recent.push_back(Seen {
+            hash,
+            at: Instant::now(),
+        });
+        Some(Reservation {
+            dedup: self,
+            uuid,
+            hash,
+            kept: false,
+        })
+    }
+
+    // Drops a deleted subscription's hashes.
+    pub fn forget(&self, uuid: &str) {
+        self.inner.lock().unwrap().remove(uuid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_expire_are_bounded_and_forgotten() {
+        let hash = |body: &str| content_hash("POST", "/hook/abc", body.as_bytes()).unwrap();
+        assert_ne!(hash("a"), content_hash("PUT", "/hook/abc", &b"a"[..]).unwrap());
+
+        let dedup = ContentDedup::new(Duration::from_secs(60));
+        let fresh = |dedup: &ContentDedup, uuid: &str, body: &str| {
+            dedup.check_and_insert(uuid, hash(body)).map(Reservation::keep).is_some()
+        };
+        assert!(fresh(&dedup, "abc", "a"));
+        assert!(!fresh(&dedup, "abc", "a"));
+        assert!(fresh(&dedup, "abc", "b"));
+        assert!(fresh(&dedup, "other", "a"));
+
+        // The oldest hash makes way once the uuid's list is full.
+        for n in 0..HASHES_PER_UUID {
+            fresh(&dedup, "abc", &n.to_string());
+        }
+        assert!(fresh(&dedup, "abc", "a"));
+        assert!(!fresh(&dedup, "abc", "1"));
+
+        dedup.forget("abc");
+        assert!(fresh(&dedup, "abc", "1"));
+
+        // A reservation that isn't kept is released.
+        drop(dedup.check_and_insert("abc", hash("c")));
+        assert!(fresh(&dedup, "abc", "c"));
+
+        let expired = ContentDedup::new(Duration::ZERO);
+        fresh(&expired, "abc", "a");
+        assert!(fresh(&expired, "abc", "a"));
+    }
+}
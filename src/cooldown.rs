@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+// Counts 413s per key. A key reaching `threshold` stays in cooldown until
+// `cooldown` passes without another one.
+pub struct OversizeCooldown {
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<HashMap<String, Strikes>>,
+}
+
+struct Strikes {
+    count: u32,
+    last: Instant,
+}
+
+impl OversizeCooldown {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn record(&self, key: &str) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut map = self.inner.lock().await;
+        let now = Instant::now();
+        map.retain(|_, strikes| now.duration_since(strikes.last) < self.cooldown);
+        let strikes = map.entry(key.to_string()).or_insert(Strikes {
+            count: 0,
+            last: now,
+        });
+        strikes.count = strikes.count.saturating_add(1);
+        strikes.last = now;
+    }
+
+    pub async fn cooling_down(&self, key: &str) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+
+        let map = self.inner.lock().await;
+        map.get(key).is_some_and(|strikes| {
+            strikes.count >= self.threshold && strikes.last.elapsed() < self.cooldown
+        })
+    }
+}
@@ -1,13 +1,47 @@
-use std::path::Path;
+use std::{ops::Bound, path::Path};
 
-use chrono::Utc;
-use redb::{Database, ReadableTable, TableDefinition};
-use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, Rng};
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
 
-use crate::{error::AppError, models::StoredSubscription};
+use crate::{
+    config::{Config, TtlBasis, UuidAlphabet},
+    dedup::dedup_purge,
+    error::AppError,
+    history::history_purge,
+    models::StoredSubscription,
+};
 
 const SUBSCRIPTIONS: TableDefinition<&str, &str> = TableDefinition::new("subscriptions");
-const SHORT_ID_LEN: usize = 12;
+// Subscriptions scanned per cleanup transaction.
+const CLEANUP_BATCH: usize = 1000;
+// Hooks refresh last_active_at at most this often, so a busy subscription
+// doesn't cost a write per request.
+pub const ACTIVITY_RESOLUTION_SECS: i64 = 3600;
+const HEX: &[u8] = b"0123456789abcdef";
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+// Top-level paths served by real routes; an id equal to one would be shadowed
+// by (or shadow) that route under `/:uuid`.
+const RESERVED_WORDS: &[&str] = &[
+    "api",
+    "favicon.ico",
+    "health",
+    "hook",
+    "index.html",
+    "live",
+    "metrics",
+    "ready",
+    "robots.txt",
+    "static",
+    "sw.js",
+];
+
+pub fn is_reserved_id(candidate: &str) -> bool {
+    RESERVED_WORDS
+        .iter()
+        .any(|word| word.eq_ignore_ascii_case(candidate))
+}
 
 pub fn open_db(path: &str) -> Result<Database, AppError> {
     if Path::new(path).exists() {
@@ -24,16 +58,21 @@ pub fn init_db(db: &Database) -> Result<(), AppError> {
     Ok(())
 }
 
-pub fn generate_uuid(db: &Database) -> Result<String, AppError> {
+pub fn generate_uuid(db: &Database, cfg: &Config) -> Result<String, AppError> {
+    let alphabet = match cfg.uuid_alphabet {
+        UuidAlphabet::Hex => HEX,
+        UuidAlphabet::Base62 => BASE62,
+    };
     // Short IDs are user-facing; keep them compact and collision-checked.
-    for _ in 0..5 {
-        let candidate = Uuid::new_v4()
-            .to_string()
-            .replace('-', "")
-            .chars()
-            .take(SHORT_ID_LEN)
-            .collect::<String>();
-        if db_get(db, &candidate)?.is_none() {
+    // Smaller id spaces fill up sooner, so they get more attempts.
+    let bits = cfg.uuid_length as f64 * (alphabet.len() as f64).log2();
+    let attempts = (256.0 / bits).clamp(5.0, 32.0) as usize;
+    for _ in 0..attempts {
+        let id: String = (0..cfg.uuid_length)
+            .map(|_| alphabet[OsRng.gen_range(0..alphabet.len())] as char)
+            .collect();
+        let candidate = format!("{}{id}", cfg.uuid_prefix);
+        if !is_reserved_id(&candidate) && db_get(db, &candidate)?.is_none() {
             return Ok(candidate);
         }
     }
@@ -54,6 +93,32 @@ pub fn db_put(db: &Database, uuid: &str, stored: &StoredSubscription) -> Result<
     Ok(())
 }
 
+// Insert a new subscription unless `max_subscriptions` (0 = unlimited) are
+// already stored. redb keeps each table's length in its header, so the check
+// is a counter read, not a scan, and shares the insert's write transaction.
+pub fn db_create(
+    db: &Database,
+    uuid: &str,
+    stored: &StoredSubscription,
+    max_subscriptions: u64,
+) -> Result<(), AppError> {
+    let value = serde_json::to_string(stored)?;
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(SUBSCRIPTIONS)?;
+        if max_subscriptions > 0 && table.len()? >= max_subscriptions {
+            return Err(AppError::new(
+                axum::http::StatusCode::INSUFFICIENT_STORAGE,
+                "subscription limit reached",
+            )
+            .with_code("subscriptions_full"));
+        }
+        table.insert(uuid, value.as_str())?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
 pub fn db_get(db: &Database, uuid: &str) -> Result<Option<StoredSubscription>, AppError> {
     let read_txn = db.begin_read()?;
     let table = read_txn.open_table(SUBSCRIPTIONS)?;
@@ -65,34 +130,307 @@ pub fn db_get(db: &Database, uuid: &str) -> Result<Option<StoredSubscription>, A
     }
 }
 
+// Store a push failure on the subscription, or clear it after a success.
+// Successes are the common case, so they only write when there is
+// something to clear.
+pub fn db_record_push_result(
+    db: &Database,
+    uuid: &str,
+    error: Option<&str>,
+) -> Result<(), AppError> {
+    if error.is_none() && db_get(db, uuid)?.is_none_or(|stored| stored.last_error.is_none()) {
+        return Ok(());
+    }
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(SUBSCRIPTIONS)?;
+        let stored = table
+            .get(uuid)?
+            .map(|value| serde_json::from_str::<StoredSubscription>(value.value()))
+            .transpose()?;
+        // Deleted while the push was in flight.
+        let Some(mut stored) = stored else {
+            return Ok(());
+        };
+        stored.last_error = error.map(str::to_string);
+        stored.last_error_at = error.map(|_| Utc::now());
+        let value = serde_json::to_string(&stored)?;
+        table.insert(uuid, value.as_str())?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Note a relayed hook on `stored` (as loaded by the caller) unless it was
+// already marked within ACTIVITY_RESOLUTION_SECS.
+pub fn db_mark_active(
+    db: &Database,
+    uuid: &str,
+    stored: &StoredSubscription,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    let resolution = chrono::Duration::seconds(ACTIVITY_RESOLUTION_SECS);
+    if stored.last_active_at.is_some_and(|at| now - at < resolution) {
+        return Ok(());
+    }
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(SUBSCRIPTIONS)?;
+        let current = table
+            .get(uuid)?
+            .map(|value| serde_json::from_str::<StoredSubscription>(value.value()))
+            .transpose()?;
+        let Some(mut current) = current else {
+            return Ok(());
+        };
+        current.last_active_at = Some(now);
+        let value = serde_json::to_string(&current)?;
+        table.insert(uuid, value.as_str())?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// When a subscription's TTL started counting.
+fn ttl_start(stored: &StoredSubscription, basis: TtlBasis) -> DateTime<Utc> {
+    match basis {
+        TtlBasis::Created => stored.created_at,
+        // Records from before last_active_at existed count from creation.
+        TtlBasis::LastActive => stored.last_active_at.unwrap_or(stored.created_at),
+    }
+}
+
 pub fn db_delete(db: &Database, uuid: &str) -> Result<bool, AppError> {
     let write_txn = db.begin_write()?;
     let removed = {
         let mut table = write_txn.open_table(SUBSCRIPTIONS)?;
         table.remove(uuid)?.is_some()
     };
+    history_purge(&write_txn, uuid)?;
+    dedup_purge(&write_txn, uuid)?;
     write_txn.commit()?;
     Ok(removed)
 }
 
-pub fn cleanup_expired(db: &Database, ttl_days: i64) -> Result<(), AppError> {
+pub fn cleanup_expired(db: &Database, ttl_days: i64, basis: TtlBasis) -> Result<usize, AppError> {
     // Periodic cleanup of expired subscriptions (TTL).
     let cutoff = Utc::now() - chrono::Duration::days(ttl_days);
-    let write_txn = db.begin_write()?;
-    {
-        let mut table = write_txn.open_table(SUBSCRIPTIONS)?;
-        let mut to_remove = Vec::new();
-        for entry in table.iter()? {
-            let (key, value) = entry?;
-            let stored: StoredSubscription = serde_json::from_str(value.value())?;
-            if stored.created_at < cutoff {
-                to_remove.push(key.value().to_string());
+    let (removed, _batches) = cleanup_expired_batched(db, cutoff, basis, CLEANUP_BATCH)?;
+    Ok(removed)
+}
+
+// Scan `batch` subscriptions per read transaction and remove the expired ones
+// in a short write transaction, so a large table never holds the write lock
+// or a full key list for the whole pass. Returns (removed, write batches).
+fn cleanup_expired_batched(
+    db: &Database,
+    cutoff: DateTime<Utc>,
+    basis: TtlBasis,
+    batch: usize,
+) -> Result<(usize, usize), AppError> {
+    let mut removed = 0;
+    let mut batches = 0;
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut expired = Vec::new();
+        let mut scanned = 0;
+        {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(SUBSCRIPTIONS)?;
+            let start = match &cursor {
+                Some(key) => Bound::Excluded(key.as_str()),
+                None => Bound::Unbounded,
+            };
+            for entry in table.range::<&str>((start, Bound::Unbounded))?.take(batch) {
+                let (key, value) = entry?;
+                scanned += 1;
+                cursor = Some(key.value().to_string());
+                let stored: StoredSubscription = serde_json::from_str(value.value())?;
+                if ttl_start(&stored, basis) < cutoff {
+                    expired.push(key.value().to_string());
+                }
             }
         }
-        for key in to_remove {
-            let _ = table.remove(key.as_str());
+
+        if !expired.is_empty() {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(SUBSCRIPTIONS)?;
+                for key in &expired {
+                    // Skip ids deleted or re-created since the scan.
+                    let still_expired = match table.get(key.as_str())? {
+                        Some(value) => {
+                            let stored: StoredSubscription = serde_json::from_str(value.value())?;
+                            ttl_start(&stored, basis) < cutoff
+                        }
+                        None => false,
+                    };
+                    if !still_expired {
+                        continue;
+                    }
+                    table.remove(key.as_str())?;
+                    history_purge(&write_txn, key)?;
+                    dedup_purge(&write_txn, key)?;
+                    removed += 1;
+                }
+            }
+            write_txn.commit()?;
+            batches += 1;
+        }
+        if scanned < batch {
+            return Ok((removed, batches));
         }
     }
-    write_txn.commit()?;
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_route_words_are_rejected() {
+        for word in ["health", "API", "sw.js", "static", "metrics"] {
+            assert!(is_reserved_id(word), "{word}");
+        }
+        assert!(!is_reserved_id("a1b2c3d4e5f6"));
+        assert!(!is_reserved_id("healthz"));
+    }
+
+    fn config(vars: &[(&str, &str)]) -> anyhow::Result<Config> {
+        let lookup = |key: &str| {
+            vars.iter()
+                .chain(&[("VAPID_PUBLIC_KEY", "pub"), ("VAPID_PRIVATE_KEY", "priv")])
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        };
+        Config::from_source(&lookup)
+    }
+
+    fn memory_db() -> Database {
+        let db = Database::builder()
+            .create_with_backend(redb::backends::InMemoryBackend::new())
+            .unwrap();
+        init_db(&db).unwrap();
+        db
+    }
+
+    #[test]
+    fn subscriptions_are_capped_until_one_is_removed() {
+        let db = memory_db();
+        let stored: StoredSubscription = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": "2024-01-01T00:00:00Z"
+        }))
+        .unwrap();
+        db_create(&db, "first", &stored, 2).unwrap();
+        db_create(&db, "second", &stored, 2).unwrap();
+        let err = db_create(&db, "third", &stored, 2).unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::INSUFFICIENT_STORAGE);
+
+        assert!(db_delete(&db, "first").unwrap());
+        db_create(&db, "third", &stored, 2).unwrap();
+        db_create(&db, "fourth", &stored, 0).unwrap();
+    }
+
+    #[test]
+    fn generated_ids_carry_the_instance_prefix() {
+        let db = memory_db();
+        let id = generate_uuid(&db, &config(&[("UUID_PREFIX", "eu-")]).unwrap()).unwrap();
+        assert!(id.starts_with("eu-"));
+        assert_eq!(id.len(), 3 + 12);
+        assert_eq!(generate_uuid(&db, &config(&[]).unwrap()).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn generated_ids_follow_length_and_alphabet() {
+        let db = memory_db();
+        let cfg = config(&[("UUID_LENGTH", "24"), ("UUID_ALPHABET", "base62")]).unwrap();
+        let id = generate_uuid(&db, &cfg).unwrap();
+        assert_eq!(id.len(), 24);
+        assert!(id.bytes().all(|byte| BASE62.contains(&byte)));
+
+        let hex = generate_uuid(&db, &config(&[("UUID_LENGTH", "6")]).unwrap()).unwrap();
+        assert!(hex.bytes().all(|byte| HEX.contains(&byte)));
+        assert!(config(&[("UUID_LENGTH", "5")]).is_err());
+    }
+
+    #[test]
+    fn cleanup_removes_expired_subscriptions_in_batches() {
+        let db = memory_db();
+        let now = Utc::now();
+        for index in 0..25 {
+            let created_at = if index % 2 == 0 {
+                now - chrono::Duration::days(40)
+            } else {
+                now
+            };
+            let stored: StoredSubscription = serde_json::from_value(serde_json::json!({
+                "subscription": {
+                    "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                    "expirationTime": null,
+                    "keys": {"p256dh": "p", "auth": "a"}
+                },
+                "created_at": created_at
+            }))
+            .unwrap();
+            db_put(&db, &format!("sub{index:02}"), &stored).unwrap();
+        }
+
+        let cutoff = now - chrono::Duration::days(30);
+        // 25 records in scans of 10: three passes, each with expired ids.
+        assert_eq!(
+            cleanup_expired_batched(&db, cutoff, TtlBasis::Created, 10).unwrap(),
+            (13, 3)
+        );
+        for index in 0..25 {
+            let kept = db_get(&db, &format!("sub{index:02}")).unwrap().is_some();
+            assert_eq!(kept, index % 2 == 1, "sub{index:02}");
+        }
+        assert_eq!(
+            cleanup_expired_batched(&db, cutoff, TtlBasis::Created, 10).unwrap(),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn last_active_ttl_keeps_busy_subscriptions() {
+        let db = memory_db();
+        let now = Utc::now();
+        let stored: StoredSubscription = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": now - chrono::Duration::days(40)
+        }))
+        .unwrap();
+        db_put(&db, "busy", &stored).unwrap();
+        db_put(&db, "idle", &stored).unwrap();
+
+        let yesterday = now - chrono::Duration::days(1);
+        db_mark_active(&db, "busy", &stored, yesterday).unwrap();
+        let busy = db_get(&db, "busy").unwrap().unwrap();
+        assert_eq!(busy.last_active_at, Some(yesterday));
+        // Within the resolution nothing is written.
+        db_mark_active(&db, "busy", &busy, yesterday + chrono::Duration::minutes(5)).unwrap();
+        assert_eq!(db_get(&db, "busy").unwrap().unwrap().last_active_at, Some(yesterday));
+
+        // "idle" never saw a hook, so it still counts from created_at.
+        let cutoff = now - chrono::Duration::days(30);
+        assert_eq!(
+            cleanup_expired_batched(&db, cutoff, TtlBasis::LastActive, 10).unwrap(),
+            (1, 1)
+        );
+        assert!(db_get(&db, "busy").unwrap().is_some());
+        assert!(db_get(&db, "idle").unwrap().is_none());
+        assert_eq!(
+            cleanup_expired_batched(&db, cutoff, TtlBasis::Created, 10).unwrap(),
+            (1, 1)
+        );
+    }
 }
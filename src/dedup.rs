@@ -0,0 +1,163 @@
+use redb::{Database, ReadableTable, TableDefinition, WriteTransaction};
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+
+// (uuid, sha256 of the idempotency key) -> first seen, unix seconds.
+const DEDUP: TableDefinition<(&str, &[u8]), i64> = TableDefinition::new("hook_dedup");
+
+// (uuid, sha256 of the coalesce key) -> (window end unix seconds, push topic).
+const COALESCE: TableDefinition<(&str, &[u8]), (i64, &str)> =
+    TableDefinition::new("hook_coalesce");
+
+const HASH_MAX: [u8; 32] = [0xff; 32];
+
+pub fn init_dedup(db: &Database) -> Result<(), AppError> {
+    let write_txn = db.begin_write()?;
+    write_txn.open_table(DEDUP)?;
+    write_txn.open_table(COALESCE)?;
+    write_txn.commit()?;
+    Ok(())
+}
+
+fn key_hash(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+// True if this key was recorded for the uuid within the window.
+pub fn dedup_seen(
+    db: &Database,
+    uuid: &str,
+    key: &str,
+    now_secs: i64,
+    window_secs: i64,
+) -> Result<bool, AppError> {
+    let hash = key_hash(key);
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(DEDUP)?;
+    Ok(table
+        .get((uuid, hash.as_slice()))?
+        .is_some_and(|seen_at| seen_at.value() + window_secs > now_secs))
+}
+
+// Remember a key once its hook has been relayed.
+pub fn dedup_record(db: &Database, uuid: &str, key: &str, now_secs: i64) -> Result<(), AppError> {
+    let hash = key_hash(key);
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(DEDUP)?;
+        table.insert((uuid, hash.as_slice()), now_secs)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Topic already issued for this key while its window is open; otherwise
+// `candidate`, which then holds for `window_secs`.
+pub fn coalesce_topic(
+    db: &Database,
+    uuid: &str,
+    key: &[u8],
+    candidate: &str,
+    now_secs: i64,
+    window_secs: i64,
+) -> Result<String, AppError> {
+    let hash: [u8; 32] = Sha256::digest(key).into();
+    let write_txn = db.begin_write()?;
+    let topic = {
+        let mut table = write_txn.open_table(COALESCE)?;
+        let existing = table
+            .get((uuid, hash.as_slice()))?
+            .and_then(|entry| {
+                let (ends_at, topic) = entry.value();
+                (ends_at > now_secs).then(|| topic.to_string())
+            });
+        match existing {
+            Some(topic) => topic,
+            None => {
+                table.insert((uuid, hash.as_slice()), (now_secs + window_secs, candidate))?;
+                candidate.to_string()
+            }
+        }
+    };
+    write_txn.commit()?;
+    Ok(topic)
+}
+
+// Drop coalesce windows that have closed; run from the cleanup task.
+pub fn coalesce_purge_expired(db: &Database, now_secs: i64) -> Result<(), AppError> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(COALESCE)?;
+        table.retain(|_, (ends_at, _)| ends_at > now_secs)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Drop keys whose window has passed; run from the cleanup task.
+pub fn dedup_purge_expired(db: &Database, now_secs: i64, window_secs: i64) -> Result<(), AppError> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(DEDUP)?;
+        table.retain(|_, seen_at| seen_at + window_secs > now_secs)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Remove every key for a uuid inside the caller's transaction.
+pub fn dedup_purge(write_txn: &WriteTransaction, uuid: &str) -> Result<(), AppError> {
+    let mut table = write_txn.open_table(DEDUP)?;
+    table.retain_in((uuid, &[][..])..=(uuid, &HASH_MAX[..]), |_, _| false)?;
+    let mut table = write_txn.open_table(COALESCE)?;
+    table.retain_in((uuid, &[][..])..=(uuid, &HASH_MAX[..]), |_, _| false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::backends::InMemoryBackend;
+
+    #[test]
+    fn dedup_window_expires_and_is_scoped_per_uuid() {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_dedup(&db).unwrap();
+
+        dedup_record(&db, "abc", "evt_1", 1_000).unwrap();
+        assert!(dedup_seen(&db, "abc", "evt_1", 1_599, 600).unwrap());
+        assert!(!dedup_seen(&db, "abc", "evt_1", 1_600, 600).unwrap());
+        assert!(!dedup_seen(&db, "other", "evt_1", 1_000, 600).unwrap());
+        assert!(!dedup_seen(&db, "abc", "evt_2", 1_000, 600).unwrap());
+
+        dedup_record(&db, "other", "evt_1", 1_500).unwrap();
+        dedup_purge_expired(&db, 1_600, 600).unwrap();
+        assert!(!dedup_seen(&db, "abc", "evt_1", 0, 600).unwrap());
+        assert!(dedup_seen(&db, "other", "evt_1", 1_600, 600).unwrap());
+
+        let write_txn = db.begin_write().unwrap();
+        dedup_purge(&write_txn, "other").unwrap();
+        write_txn.commit().unwrap();
+        assert!(!dedup_seen(&db, "other", "evt_1", 1_600, 600).unwrap());
+    }
+
+    #[test]
+    fn coalesce_topic_is_reused_within_window() {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_dedup(&db).unwrap();
+
+        assert_eq!(coalesce_topic(&db, "abc", b"cpu", "t1", 1_000, 300).unwrap(), "t1");
+        assert_eq!(coalesce_topic(&db, "abc", b"cpu", "t2", 1_299, 300).unwrap(), "t1");
+        assert_eq!(coalesce_topic(&db, "abc", b"disk", "t3", 1_299, 300).unwrap(), "t3");
+        assert_eq!(coalesce_topic(&db, "other", b"cpu", "t4", 1_299, 300).unwrap(), "t4");
+        assert_eq!(coalesce_topic(&db, "abc", b"cpu", "t5", 1_300, 300).unwrap(), "t5");
+
+        coalesce_purge_expired(&db, 1_600).unwrap();
+        assert_eq!(coalesce_topic(&db, "abc", b"cpu", "t6", 1_300, 300).unwrap(), "t6");
+    }
+}
@@ -1,12 +1,23 @@
 use axum::{
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::Serialize;
+
+use crate::request_id::current_request_id;
 
 #[derive(Debug)]
 pub struct AppError {
     pub status: StatusCode,
     pub message: String,
+    // Stable identifier for clients, e.g. "rate_limited".
+    pub code: Option<&'static str>,
+    // X-Request-Id of the request that failed, when built inside a handler.
+    pub request_id: Option<String>,
+    // Sent alongside the body, e.g. Retry-After on a 429. Boxed to keep
+    // every Result<_, AppError> small.
+    pub headers: Option<Box<HeaderMap>>,
 }
 
 impl AppError {
@@ -14,13 +25,43 @@ impl AppError {
         Self {
             status,
             message: message.into(),
+            code: None,
+            request_id: current_request_id(),
+            headers: None,
         }
     }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.get_or_insert_default().extend(headers);
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
 }
 
 impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        (self.status, self.message).into_response()
+    fn into_response(mut self) -> Response {
+        let headers = self.headers.take().map(|headers| *headers).unwrap_or_default();
+        let body = ErrorBody {
+            error: &self.message,
+            status: self.status.as_u16(),
+            code: self.code,
+            request_id: self.request_id.as_deref(),
+        };
+        (self.status, headers, Json(body)).into_response()
     }
 }
 
@@ -38,3 +79,27 @@ impl std::fmt::Display for AppError {
         write!(f, "{}", self.message)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn error_body_is_json() {
+        let response = AppError::new(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded")
+            .with_code("rate_limited")
+            .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "rate limit exceeded",
+                "status": 429,
+                "code": "rate_limited",
+            })
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use serde_json::{Map, Value};
+
+pub const MAX_FILTERS: usize = 32;
+const MAX_FILTER_LEN: usize = 256;
+
+// An RFC 6901 pointer, where a `*` segment matches every element.
+pub fn valid_filter(filter: &str) -> bool {
+    filter.starts_with('/') && filter.len() <= MAX_FILTER_LEN
+}
+
+// One entry per filter that matched, keyed by the filter itself. Wildcard
+// filters collect every match into an array.
+pub fn filter_json(value: &Value, filters: &[String]) -> Value {
+    let mut reduced = Map::new();
+    for filter in filters {
+        let segments: Vec<String> = filter[1..].split('/').map(unescape).collect();
+        let mut matches = Vec::new();
+        select(value, &segments, &mut matches);
+        let selected = if segments.iter().any(|segment| segment == "*") {
+            Value::Array(matches.into_iter().cloned().collect())
+        } else if let Some(found) = matches.pop() {
+            found.clone()
+        } else {
+            continue;
+        };
+        reduced.insert(filter.clone(), selected);
+    }
+    Value::Object(reduced)
+}
+
+fn select<'a>(value: &'a Value, segments: &[String], matches: &mut Vec<&'a Value>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        matches.push(value);
+        return;
+    };
+    match (value, segment.as_str()) {
+        (Value::Array(items), "*") => items.iter().for_each(|item| select(item, rest, matches)),
+        (Value::Object(fields), "*") => fields.values().for_each(|item| select(item, rest, matches)),
+        (Value::Array(items), index) => {
+            if let Some(item) = index.parse::<usize>().ok().and_then(|index| items.get(index)) {
+                select(item, rest, matches);
+            }
+        }
+        (Value::Object(fields), key) => {
+            if let Some(item) = fields.get(key) {
+                select(item, rest, matches);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn unescape(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters_pick_nested_fields_and_array_elements() {
+        let push = json!({
+            "repository": {"full_name": "octo/hello", "size": 1234},
+            "pusher": {"name": "octocat", "email": "o@example.com"},
+            "commits": [
+                {"message": "fix", "files": [{"path": "a.rs"}, {"path": "b.rs"}]},
+                {"message": "docs", "files": []},
+                {"id": "no-message"}
+            ],
+            "a/b": {"~c": 1}
+        });
+        let filters: Vec<String> = [
+            "/repository/full_name",
+            "/pusher/name",
+            "/commits/*/message",
+            "/commits/*/files/*/path",
+            "/commits/0/message",
+            "/a~1b/~0c",
+            "/missing",
+        ]
+        .iter()
+        .map(|filter| filter.to_string())
+        .collect();
+        assert_eq!(
+            filter_json(&push, &filters),
+            json!({
+                "/repository/full_name": "octo/hello",
+                "/pusher/name": "octocat",
+                "/commits/*/message": ["fix", "docs"],
+                "/commits/*/files/*/path": ["a.rs", "b.rs"],
+                "/commits/0/message": "fix",
+                "/a~1b/~0c": 1
+            })
+        );
+        assert!(!valid_filter("repository"));
+    }
+}
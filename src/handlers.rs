@@ -1,63 +1,228 @@
 use axum::{
-    extract::{ConnectInfo, Path, Request, State},
-    http::{header::CONTENT_LENGTH, HeaderMap, StatusCode, Uri},
+    body::{Body, BodyDataStream, Bytes},
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{
+        header::{
+            ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS, ALLOW, AUTHORIZATION,
+            CONTENT_LENGTH, CONTENT_TYPE, DATE,
+        },
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
-use base64::{decode_config, encode as base64_encode, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::{decode, decode_config, encode as base64_encode, URL_SAFE, URL_SAFE_NO_PAD};
 use chrono::Utc;
+use flate2::{write::GzEncoder, Compression};
 use futures_util::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{
+    borrow::Cow,
     collections::HashMap,
+    io::{Read, Write},
     net::SocketAddr,
-    time::Duration,
+    ops::Range,
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::time::timeout;
+use tokio::{sync::broadcast::error::RecvError, time::timeout};
+use tracing::{warn, Instrument};
 use uuid::Uuid;
 
 use crate::{
-    db::{db_delete, db_get, db_put, generate_uuid},
+    access_log::{self, hook_span, log_hook, log_subscription},
+    client_ip::{client_ip, parse_cidr, source_allowed},
+    config::{Config, MultipartMode, MAX_HOOK_RESPONSE_BODY_BYTES, MAX_LABELS},
+    content_dedup::content_hash,
+    db::{
+        db_create, db_delete, db_get, db_mark_active, db_put, db_record_push_result, generate_uuid,
+        is_reserved_id,
+    },
+    dedup::{coalesce_topic, dedup_record, dedup_seen},
     error::AppError,
+    filter::{filter_json, valid_filter, MAX_FILTERS},
+    heartbeat::{heartbeat_enabled, schedule_heartbeat},
+    history::{
+        history_get, history_latest_seq, history_list, history_next, history_put, history_seq,
+    },
+    json_guard::parse_json_body,
+    metrics::{self, render_json, render_prometheus},
+    multipart::{form_data_boundary, summarize},
+    rate_limiter::RateDecision,
     models::{
-        ChunkEnvelope, ConfigResponse, HookMeta, PushSubscription, StoredSubscription,
-        SubscribeResponse,
+        Challenge, ChallengeProvider, ChunkEnvelope, ConfigResponse, DeadLettersResponse,
+        DeliveryMode, ENVELOPE_VERSION, FlushResponse, HistoryEntry, HistoryResponse, HookAck,
+        HookMeta, HookSummary, OversizeMode, PurgeResponse, PushSubscription, QueueStats,
+        ReadyResponse, ReplayResponse, ResponseFormat, RotateTokenResponse, StoredRequest,
+        StoredSubscription, SubscribeRequest, SubscribeResponse, SubscriptionOptions,
+        SubscriptionStatus, Verification,
     },
+    spill::SpillBuffer,
     state::AppState,
+    summary::{hook_summary, PREVIEW_READ_BYTES},
+    verify::{constant_time_eq, hash_delete_token, is_hashed_delete_token, Verifier},
 };
 
-pub async fn health() -> StatusCode {
+// Liveness: the process is up and serving; also answers `/health`.
+pub async fn live() -> StatusCode {
     StatusCode::OK
 }
 
+// Readiness: both databases answer and the queue writer is running. The
+// body reports the queue backlog.
+pub async fn ready(State(state): State<AppState>) -> Result<Json<ReadyResponse>, AppError> {
+    state.db.begin_read().map_err(|err| {
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("database unavailable: {err}"),
+        )
+        .with_code("not_ready")
+    })?;
+    state.push_queue.check_ready()?;
+    let queue = state.push_queue.stats().map_err(|err| {
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("queue database unavailable: {}", err.message),
+        )
+        .with_code("not_ready")
+    })?;
+    Ok(Json(ReadyResponse { queue }))
+}
+
+// Prometheus text by default; JSON when the client asks for it.
+pub async fn metrics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let samples = state.metrics.snapshot();
+    let wants_json = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"));
+    if wants_json {
+        Json(render_json(&samples)).into_response()
+    } else {
+        (
+            [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+            render_prometheus(&samples),
+        )
+            .into_response()
+    }
+}
+
 pub async fn config(State(state): State<AppState>) -> Json<ConfigResponse> {
     Json(ConfigResponse {
-        public_key: state.cfg.vapid_public_key.clone(),
+        public_key: state.cfg().vapid_public_key.clone(),
     })
 }
 
 pub async fn subscribe(
     State(state): State<AppState>,
-    Json(subscription): Json<PushSubscription>,
+    Json(request): Json<SubscribeRequest>,
 ) -> Result<Json<SubscribeResponse>, AppError> {
+    let cfg = state.cfg();
+    if state.uuid_allowlist.is_some() {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            "subscriptions are closed on this instance",
+        )
+        .with_code("subscriptions_closed"));
+    }
+    let SubscribeRequest {
+        subscription,
+        options,
+    } = request;
     // Validate subscription endpoint + keys before persisting.
-    validate_subscription(&subscription, &state.cfg.allowed_push_hosts)?;
+    validate_subscription(&subscription, &cfg.allowed_push_hosts)?;
+    validate_options(&options, &cfg)?;
 
-    let uuid = generate_uuid(&state.db)?;
+    let uuid = generate_uuid(&state.db, &cfg)?;
     // Delete token is required for unsubscribe; kept off the URL.
-    let delete_token = Uuid::new_v4().to_string().replace('-', "");
+    let delete_token = new_delete_token();
     let stored = StoredSubscription {
         subscription,
         created_at: Utc::now(),
-        delete_token: delete_token.clone(),
+        delete_token: hash_delete_token(&uuid, &delete_token),
+        options,
+        last_active_at: None,
+        last_error: None,
+        last_error_at: None,
     };
-    db_put(&state.db, &uuid, &stored)?;
+    db_create(&state.db, &uuid, &stored, cfg.max_subscriptions)?;
+    if heartbeat_enabled(&cfg, &stored.options)
+        && let Err(err) = schedule_heartbeat(&state.push_queue, &cfg, &uuid).await
+    {
+        // The client never learns the delete token, so don't keep it around.
+        db_delete(&state.db, &uuid)?;
+        return Err(err);
+    }
+    log_subscription("subscribe", &uuid);
 
-    let base = state.cfg.public_base_url.trim_end_matches('/');
+    let base = cfg.public_base_url.trim_end_matches('/');
     let url = format!("{base}/{uuid}");
 
     Ok(Json(SubscribeResponse {
         uuid,
         url,
         delete_token,
+        effective_chunk_bytes: effective_chunk_bytes(&cfg),
+    }))
+}
+
+// Keep allowlisted headers (case-insensitive, "*" for all) and count the rest.
+fn captured_headers(headers: &HeaderMap, allowlist: &[String]) -> (HashMap<String, String>, usize) {
+    let keep_all = allowlist.iter().any(|name| name == "*");
+    let mut captured = HashMap::new();
+    let mut omitted = 0;
+    for (name, value) in headers.iter() {
+        if keep_all || allowlist.iter().any(|allowed| allowed.eq_ignore_ascii_case(name.as_str())) {
+            let value_str = value.to_str().unwrap_or("<binary>");
+            captured.insert(name.to_string(), value_str.to_string());
+        } else {
+            omitted += 1;
+        }
+    }
+    (captured, omitted)
+}
+
+// Per-push capacity for a worst-case uncompressed payload; None if the
+// configuration can't fit a single chunk.
+fn effective_chunk_bytes(cfg: &Config) -> Option<usize> {
+    let request_id = Uuid::nil().to_string();
+    let max_total_bytes = cfg.max_body_bytes.saturating_add(cfg.max_meta_bytes);
+    resolve_chunk_size(&request_id, None, None, cfg.chunk_data_bytes, max_total_bytes).ok()
+}
+
+fn new_delete_token() -> String {
+    Uuid::new_v4().to_string().replace('-', "")
+}
+
+// Swap a leaked delete token for a fresh one without changing the hook URL.
+pub async fn rotate_token(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<RotateTokenResponse>, AppError> {
+    let mut stored = authorize_subscription(&state, &uuid, &headers)?;
+    let delete_token = new_delete_token();
+    stored.delete_token = hash_delete_token(&uuid, &delete_token);
+    db_put(&state.db, &uuid, &stored)?;
+    Ok(Json(RotateTokenResponse { delete_token }))
+}
+
+pub async fn subscription_status(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<SubscriptionStatus>, AppError> {
+    let stored = authorize_subscription(&state, &uuid, &headers)?;
+    Ok(Json(SubscriptionStatus {
+        uuid,
+        created_at: stored.created_at,
+        last_active_at: stored.last_active_at,
+        last_error: stored.last_error,
+        last_error_at: stored.last_error_at,
     }))
 }
 
@@ -66,7 +231,210 @@ pub async fn unsubscribe(
     Path(uuid): Path<String>,
     headers: HeaderMap,
 ) -> Result<StatusCode, AppError> {
-    // Require delete token to prevent anyone from deleting by UUID alone.
+    authorize_subscription(&state, &uuid, &headers)?;
+
+    let _ = db_delete(&state.db, &uuid)?;
+    // Queued chunks would only be dropped by the workers; free their share now.
+    state.push_queue.purge(&uuid)?;
+    state.live_streams.close(&uuid);
+    state.content_dedup.forget(&uuid);
+    log_subscription("unsubscribe", &uuid);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    before: Option<u64>,
+    limit: Option<usize>,
+}
+
+pub async fn history(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    Query(query): Query<HistoryQuery>,
+    headers: HeaderMap,
+) -> Result<Json<HistoryResponse>, AppError> {
+    if !state.cfg().history_enabled {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            "history disabled",
+        )
+        .with_code("history_disabled"));
+    }
+    authorize_subscription(&state, &uuid, &headers)?;
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let entries = history_list(&state.db, &uuid, query.before, limit)?;
+    let next_before = if entries.len() == limit {
+        entries.last().map(|(seq, _)| *seq)
+    } else {
+        None
+    };
+    let requests = entries
+        .into_iter()
+        .map(|(seq, request)| HistoryEntry { seq, request })
+        .collect();
+
+    Ok(Json(HistoryResponse {
+        requests,
+        next_before,
+    }))
+}
+
+// Drop every dead-lettered record now instead of waiting for DEAD_LETTER_TTL_DAYS.
+pub async fn clear_dead_letters(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<PurgeResponse>, AppError> {
+    authorize_admin(&state.cfg(), &headers)?;
+    let purged = state.push_queue.purge_dead_letters(i64::MAX)?;
+    Ok(Json(PurgeResponse { purged }))
+}
+
+#[derive(Deserialize)]
+pub struct DeadLettersQuery {
+    uuid: Option<String>,
+}
+
+pub async fn dead_letters(
+    State(state): State<AppState>,
+    Query(query): Query<DeadLettersQuery>,
+    headers: HeaderMap,
+) -> Result<Json<DeadLettersResponse>, AppError> {
+    authorize_admin(&state.cfg(), &headers)?;
+    let dead_letters = state.push_queue.dead_letters(query.uuid.as_deref())?;
+    Ok(Json(DeadLettersResponse { dead_letters }))
+}
+
+pub async fn queue_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<QueueStats>, AppError> {
+    authorize_admin(&state.cfg(), &headers)?;
+    Ok(Json(state.push_queue.stats()?))
+}
+
+// Server-Sent Events: one `hook` event per relayed hook, carrying the same
+// JSON as a history entry. A watcher that falls behind gets a `lagged` event
+// with the number it missed.
+pub async fn stream(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    authorize_subscription(&state, &uuid, &headers)?;
+    let receiver = state.live_streams.subscribe(&uuid)?;
+    log_subscription("stream", &uuid);
+    // Ends when the subscription is deleted; a disconnect drops the receiver.
+    let events = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(request) => Event::default()
+                .event("hook")
+                .id(request.request_id.clone())
+                .json_data(&*request),
+            Err(RecvError::Lagged(missed)) => Ok(Event::default()
+                .event("lagged")
+                .data(missed.to_string())),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((event, receiver))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct PollQuery {
+    after: Option<String>,
+}
+
+// Long-poll alternative to Web Push: the first history entry after `after`
+// (without it, the next hook to arrive), or 204 after LONG_POLL_TIMEOUT_MS.
+pub async fn poll(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    Query(query): Query<PollQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if !state.cfg().history_enabled {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            "history disabled",
+        )
+        .with_code("history_disabled"));
+    }
+    authorize_subscription(&state, &uuid, &headers)?;
+
+    let cursor = match &query.after {
+        Some(request_id) => Some(history_seq(&state.db, &uuid, request_id)?.ok_or_else(|| {
+            AppError::new(StatusCode::NOT_FOUND, "request not found").with_code("request_not_found")
+        })?),
+        None => history_latest_seq(&state.db, &uuid)?,
+    };
+    let waiter = state.poll_waiters.waiter(&uuid);
+    let deadline = Instant::now() + Duration::from_millis(state.cfg().long_poll_timeout_ms);
+    loop {
+        // Enabled before reading history, so a hook stored in between still wakes us.
+        let notified = waiter.notify().notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if let Some((seq, request)) = history_next(&state.db, &uuid, cursor)? {
+            return Ok(Json(HistoryEntry { seq, request }).into_response());
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if timeout(remaining, notified).await.is_err() {
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+    }
+}
+
+// Give one dead letter a fresh set of attempts.
+pub async fn retry_dead_letter(
+    State(state): State<AppState>,
+    Path(seq): Path<u64>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    authorize_admin(&state.cfg(), &headers)?;
+    state.push_queue.retry_dead_letter(seq)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+// Send a subscription's delayed chunks now, ignoring their send_after.
+pub async fn flush_queue(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<FlushResponse>, AppError> {
+    authorize_admin(&state.cfg(), &headers)?;
+    let flushed = state.push_queue.flush(&uuid)?;
+    Ok(Json(FlushResponse { flushed }))
+}
+
+// Admin endpoints stay invisible unless ADMIN_TOKEN is set.
+fn authorize_admin(cfg: &Config, headers: &HeaderMap) -> Result<(), AppError> {
+    let Some(expected) = cfg.admin_token.as_deref() else {
+        return Err(AppError::new(StatusCode::NOT_FOUND, "not found").with_code("not_found"));
+    };
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if !constant_time_eq(expected.as_bytes(), provided.as_bytes()) {
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "admin token required",
+        )
+        .with_code("admin_token_required"));
+    }
+    Ok(())
+}
+
+// Require delete token to prevent anyone from acting on a UUID alone.
+fn authorize_subscription(
+    state: &AppState,
+    uuid: &str,
+    headers: &HeaderMap,
+) -> Result<StoredSubscription, AppError> {
     let provided = headers
         .get("x-delete-token")
         .and_then(|value| value.to_str().ok())
@@ -75,273 +443,1535 @@ pub async fn unsubscribe(
         return Err(AppError::new(
             StatusCode::UNAUTHORIZED,
             "delete token required",
-        ));
+        )
+        .with_code("delete_token_required"));
     }
 
-    let stored = match db_get(&state.db, &uuid)? {
+    let mut stored = match db_get(&state.db, uuid)? {
         Some(stored) => stored,
         None => {
             return Err(AppError::new(
                 StatusCode::NOT_FOUND,
                 "subscription not found",
-            ));
+            )
+            .with_code("subscription_not_found"));
         }
     };
 
-    if stored.delete_token != provided {
+    let provided_hash = hash_delete_token(uuid, provided);
+    // Subscriptions created before hashing hold the plaintext token.
+    let legacy = !is_hashed_delete_token(&stored.delete_token);
+    let expected = if legacy { provided } else { &provided_hash };
+    if !constant_time_eq(stored.delete_token.as_bytes(), expected.as_bytes()) {
         return Err(AppError::new(
             StatusCode::FORBIDDEN,
             "invalid delete token",
-        ));
+        )
+        .with_code("invalid_delete_token"));
+    }
+    if legacy {
+        stored.delete_token = provided_hash;
+        db_put(&state.db, uuid, &stored)?;
     }
 
-    let _ = db_delete(&state.db, &uuid)?;
-    Ok(StatusCode::NO_CONTENT)
+    Ok(stored)
 }
 
 pub async fn hook(
     State(state): State<AppState>,
     Path(uuid): Path<String>,
     req: Request,
-) -> Result<StatusCode, AppError> {
+) -> Result<Response, AppError> {
+    let started = Instant::now();
+    let span = hook_span(&uuid, req.method().as_str());
+    let result = hook_with_limits(&state, &uuid, req).instrument(span.clone()).await;
+    log_hook(&span, &result, started.elapsed());
+    result
+}
+
+// Oversize cooldown and rate-limit headers around the relay itself.
+async fn hook_with_limits(
+    state: &AppState,
+    uuid: &str,
+    req: Request,
+) -> Result<Response, AppError> {
+    let cfg = state.cfg();
+    // A sender that keeps posting oversized bodies is refused on its
+    // Content-Length alone, before any lookup or buffering.
+    let max_read_bytes = match cfg.multipart_mode {
+        MultipartMode::Summarize => cfg.max_body_bytes.max(cfg.multipart_max_bytes),
+        MultipartMode::Raw => cfg.max_body_bytes,
+    };
+    let oversized = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .is_some_and(|length| length > max_read_bytes);
+    if oversized && state.oversize_cooldown.cooling_down(uuid).await {
+        state.oversize_cooldown.record(uuid).await;
+        return Err(AppError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload exceeds limit; oversize cooldown active",
+        )
+        .with_code("oversize_cooldown"));
+    }
+
+    let mut rate = None;
+    let mut result = relay_hook(state, uuid, req, &mut rate).await;
+    if let Err(err) = &result
+        && err.status == StatusCode::PAYLOAD_TOO_LARGE
+    {
+        state.oversize_cooldown.record(uuid).await;
+    }
+    // Senders that got past the limiter learn where they stand, either way.
+    if let Some(rate) = rate {
+        result = match result {
+            Ok(mut response) => {
+                response.headers_mut().extend(rate.headers());
+                Ok(response)
+            }
+            Err(err) => Err(err.with_headers(rate.headers())),
+        };
+    }
+    result
+}
+
+// `rate` is filled in once the hook has been counted by the rate limiter.
+async fn relay_hook(
+    state: &AppState,
+    uuid: &str,
+    req: Request,
+    rate: &mut Option<RateDecision>,
+) -> Result<Response, AppError> {
+    let cfg = state.cfg();
+    // Browser and crawler probes (favicon.ico, robots.txt, .well-known) land
+    // on `/:uuid`; no id can match them, so skip redb and the rate limiter.
+    if is_reserved_id(uuid) || uuid.starts_with('.') {
+        return Err(AppError::new(StatusCode::NOT_FOUND, "not found").with_code("not_found"));
+    }
+
     let (parts, body) = req.into_parts();
     let method = parts.method;
     let headers = parts.headers;
     let uri = parts.uri;
-    let source_ip = parts
+    let peer_ip = parts
         .extensions
         .get::<ConnectInfo<SocketAddr>>()
-        .map(|info| info.0.ip().to_string())
+        .map(|info| info.0.ip());
+    let resolved_ip = client_ip(
+        &headers,
+        peer_ip,
+        cfg.trusted_proxy_hops,
+        &cfg.trusted_proxies,
+    );
+    let source_ip = resolved_ip
+        .map(|ip| ip.to_string())
         .unwrap_or_else(|| "unknown".to_string());
+    access_log::record("source_ip", source_ip.as_str());
+
+    // Lookup subscription; unknown UUIDs, ids minted under another
+    // UUID_PREFIX and, on a closed instance, unlisted ids are rejected.
+    let listed = state
+        .uuid_allowlist
+        .as_ref()
+        .is_none_or(|allowlist| allowlist.contains(uuid));
+    let stored = if listed && uuid.starts_with(&cfg.uuid_prefix) {
+        db_get(&state.db, uuid)?
+    } else {
+        None
+    };
+    let stored = stored.ok_or_else(|| {
+            AppError::new(StatusCode::NOT_FOUND, "subscription not found")
+                .with_code("subscription_not_found")
+        })?;
+
+    // CORS preflights and uptime probes would otherwise each become a push.
+    if !cfg.relay_preflight {
+        if method == Method::OPTIONS {
+            return Ok(preflight_response(&headers));
+        }
+        if method == Method::HEAD {
+            return Ok(StatusCode::OK.into_response());
+        }
+    }
+    metrics::inc(&state.metrics.hooks_received);
 
-    // Lookup subscription; unknown UUIDs are rejected.
-    if db_get(&state.db, &uuid)?.is_none() {
+    if !source_allowed(resolved_ip, &stored.options.allowed_source_cidrs) {
         return Err(AppError::new(
-            StatusCode::NOT_FOUND,
-            "subscription not found",
-        ));
+            StatusCode::FORBIDDEN,
+            "source address not allowed",
+        )
+        .with_code("source_not_allowed"));
     }
 
     // Per-UUID rate limiting to prevent abuse.
-    if !state.rate_limiter.allow(&uuid).await {
+    let decision = rate.insert(state.rate_limiter.allow(uuid).await);
+    if !decision.allowed {
         return Err(AppError::new(
             StatusCode::TOO_MANY_REQUESTS,
             "rate limit exceeded",
-        ));
+        )
+        .with_code("rate_limited"));
+    }
+
+    // Checked after rate limiting so token guesses are throttled too.
+    if let Some(expected) = &stored.options.hook_auth_token {
+        let provided = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or("");
+        if !constant_time_eq(expected.as_bytes(), provided.as_bytes()) {
+            return Err(AppError::new(
+                StatusCode::UNAUTHORIZED,
+                "hook authorization required",
+            )
+            .with_code("hook_unauthorized"));
+        }
     }
 
+    check_webhook_age(&headers, &cfg, SystemTime::now())?;
 
-    let mut headers_map = HashMap::new();
-    for (name, value) in headers.iter() {
-        let value_str = value.to_str().unwrap_or("<binary>");
-        headers_map.insert(name.to_string(), value_str.to_string());
+    // Provider URL checks are answered with the challenge they carry. Slack's
+    // sits in the JSON body, so those subscriptions always buffer it.
+    let challenge_provider = stored.options.challenge.as_ref().map(|c| &c.provider);
+    let push_challenge = stored.options.challenge.as_ref().is_some_and(|c| c.push);
+    let slack_challenges = matches!(challenge_provider, Some(ChallengeProvider::Slack));
+    let mut challenge = match challenge_provider {
+        Some(ChallengeProvider::Query { param }) => {
+            let name = param.as_deref().unwrap_or("hub.challenge");
+            query_param(uri.query(), name).map(str::to_string)
+        }
+        _ => None,
+    };
+    if let Some(value) = &challenge
+        && !push_challenge
+    {
+        return Ok(challenge_response(value));
+    }
+
+    // Challenges are unsigned, so a relayed one skips signature checks.
+    let mut verifier = stored
+        .options
+        .verification
+        .as_ref()
+        .filter(|_| challenge.is_none())
+        .map(|verification| Verifier::new(verification, &headers, Utc::now().timestamp()))
+        .transpose()?;
+
+    // Providers retry aggressively; a repeated key is acknowledged but not
+    // relayed. Only authenticated requests are answered, so a replayed key
+    // can't be used to probe which keys were seen; signed hooks are checked
+    // once their signature is.
+    let dedup_window_secs = cfg.dedup_window_secs as i64;
+    let idempotency_key = if dedup_window_secs > 0 {
+        idempotency_key(&headers, &stored.options)
+    } else {
+        None
+    };
+    let repeated_key = || match &idempotency_key {
+        Some(key) => dedup_seen(&state.db, uuid, key, Utc::now().timestamp(), dedup_window_secs),
+        None => Ok(false),
+    };
+    if verifier.is_none() && repeated_key()? {
+        return Ok(StatusCode::OK.into_response());
     }
 
+    let capture_headers = stored
+        .options
+        .capture_headers
+        .as_deref()
+        .unwrap_or(&cfg.capture_headers);
+    let (headers_map, headers_omitted) = captured_headers(&headers, capture_headers);
+
+    let delivery = match query_param(uri.query(), "delivery") {
+        Some("sync") => DeliveryMode::Sync,
+        Some("async") => DeliveryMode::Async,
+        Some(_) => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "delivery must be sync or async",
+            )
+            .with_code("invalid_delivery"));
+        }
+        None => stored.options.delivery.unwrap_or(DeliveryMode::Async),
+    };
+    let sync = (delivery == DeliveryMode::Sync).then(|| SyncDelivery {
+        subscription: &stored.subscription,
+        deadline: tokio::time::Instant::now()
+            + Duration::from_millis(cfg.sync_delivery_timeout_ms),
+    });
+    let ttl_secs = push_ttl(&headers, &cfg);
+
+    let forward_query_string = stored
+        .options
+        .forward_query_string
+        .unwrap_or(cfg.forward_query_string);
+    let query_string = if forward_query_string {
+        uri.query().unwrap_or("").to_string()
+    } else {
+        String::new()
+    };
+
+    let client_cert_subject = cfg
+        .client_cert_header
+        .as_deref()
+        .and_then(|name| headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let form_boundary = match cfg.multipart_mode {
+        MultipartMode::Summarize => headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(form_data_boundary),
+        MultipartMode::Raw => None,
+    };
+    // Forms are summarized rather than cut.
+    let truncate = stored
+        .options
+        .oversize_mode
+        .unwrap_or(cfg.oversize_mode)
+        == OversizeMode::Truncate
+        && form_boundary.is_none();
+
+    let declared_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
     let request_id = Uuid::new_v4().to_string();
-    let meta = HookMeta {
+    access_log::record("request_id", request_id.as_str());
+    let mut meta = HookMeta {
         timestamp: Utc::now().to_rfc3339(),
         method: method.to_string(),
         path: uri.path().to_string(),
-        query_string: uri.query().unwrap_or("").to_string(),
+        query_string,
+        content_type: headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        // Worst case until the body has been read.
+        content_length: usize::MAX,
         headers: headers_map,
+        headers_omitted,
+        form: None,
+        truncated: truncate,
+        // Worst case, so the body budget leaves room for the real value.
+        original_content_length: truncate.then_some(usize::MAX),
         source_ip,
+        client_cert_subject,
+        filtered: false,
+        verification: challenge.is_some(),
     };
     let meta_bytes = serde_json::to_vec(&meta)?;
-    if meta_bytes.len() > state.cfg.max_payload_bytes {
+    // Metadata has its own budget, so many headers never shrink the body limit.
+    if meta_bytes.len() > cfg.max_meta_bytes {
         return Err(AppError::new(
-            StatusCode::PAYLOAD_TOO_LARGE,
-            "payload exceeds limit",
-        ));
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            "too many headers",
+        )
+        .with_code("metadata_too_large"));
     }
     if meta_bytes.len() > u32::MAX as usize {
         return Err(AppError::new(
             StatusCode::PAYLOAD_TOO_LARGE,
             "metadata too large",
-        ));
+        )
+        .with_code("payload_too_large"));
     }
-    let max_body_bytes = state.cfg.max_payload_bytes - meta_bytes.len();
-    if let Some(length) = headers
-        .get(CONTENT_LENGTH)
-        .and_then(|value| value.to_str().ok())
-        .and_then(|value| value.parse::<usize>().ok())
+    let max_body_bytes = cfg.max_body_bytes;
+    let read_limit = if form_boundary.is_some() {
+        cfg.multipart_max_bytes
+    } else {
+        max_body_bytes
+    };
+    if let Some(length) = declared_length
+        && length > read_limit
+        && !truncate
     {
-        if length > max_body_bytes {
-            return Err(AppError::new(
-                StatusCode::PAYLOAD_TOO_LARGE,
-                "payload exceeds limit",
-            ));
-        }
+        return Err(AppError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload exceeds limit",
+        )
+        .with_code("payload_too_large"));
     }
 
-    let mut prefix = Vec::with_capacity(8 + meta_bytes.len());
-    prefix.extend_from_slice(b"WHP1");
-    prefix.extend_from_slice(&(meta_bytes.len() as u32).to_be_bytes());
-    prefix.extend_from_slice(&meta_bytes);
-
-    // Resolve a safe chunk size that fits every envelope.
-    let max_total_bytes = prefix.len().saturating_add(max_body_bytes);
-    let chunk_size = resolve_chunk_size(
-        &request_id,
-        state.cfg.chunk_data_bytes,
-        max_total_bytes,
-    )?;
+    // Held until this hook's chunks are all enqueued, so a concurrent hook to
+    // the same uuid can't interleave with them.
+    let _hook_lock = state.hook_locks.lock(uuid).await;
 
-    let mut stream = body.into_data_stream();
-    let deadline = tokio::time::Instant::now() + Duration::from_millis(state.cfg.webhook_read_timeout_ms);
-    let mut buffer = prefix;
-    let mut chunk_index = 0usize;
-    let mut total_body_bytes = 0usize;
-    let mut next_send_after_ms = Utc::now().timestamp_millis();
-    let delay_ms = state.cfg.chunk_delay_ms as i64;
+    let mut reader = BodyReader::new(
+        body,
+        read_limit,
+        truncate,
+        Duration::from_millis(cfg.webhook_read_timeout_ms),
+    );
 
-    loop {
-        while buffer.len() >= chunk_size {
-            let chunk: Vec<u8> = buffer.drain(..chunk_size).collect();
-            chunk_index += 1;
-            enqueue_chunk(
-                &state,
-                &uuid,
-                &request_id,
-                chunk_index,
-                false,
-                None,
-                chunk,
-                next_send_after_ms,
-            )
-            .await?;
-            next_send_after_ms += delay_ms;
+    // Hold back the start of the body until the subscription's minimum is met.
+    let mut head = Vec::new();
+    while head.len() < stored.options.min_body_bytes {
+        match reader.next().await? {
+            Some(bytes) => head.extend_from_slice(&bytes),
+            None => return Ok(StatusCode::OK.into_response()),
         }
+    }
 
-        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
-        if remaining.is_zero() {
+    // Compression, history, signature checks, form parsing, filters and
+    // coalescing by body need the whole body before chunking; truncation and
+    // the length of a body sent without Content-Length are only known once
+    // it has been read.
+    let coalesce_by_body = stored
+        .options
+        .coalesce
+        .as_ref()
+        .is_some_and(|coalesce| coalesce.header.is_none());
+    // A live watcher gets the whole body, so it is buffered like history.
+    let live = state.live_streams.is_watched(uuid);
+    let buffered = cfg.compress_over_bytes > 0
+        || cfg.history_enabled
+        || live
+        || verifier.is_some()
+        || truncate
+        || form_boundary.is_some()
+        || slack_challenges
+        || !stored.options.filters.is_empty()
+        || coalesce_by_body
+        || cfg.content_dedup
+        || declared_length.is_none();
+    let mut dedup_reservation = None;
+    let total_chunks = if !buffered && let Some(length) = declared_length {
+        // Stream: emit chunks as bytes arrive. hyper fails a body that
+        // doesn't match its Content-Length, so the metadata can carry it.
+        meta.content_length = length;
+        let meta_bytes = serde_json::to_vec(&meta)?;
+        let prefix = payload_prefix(&meta_bytes);
+        let total_bytes = prefix.len().saturating_add(length);
+        // Enough of the body for the summary's preview.
+        let preview = cfg.summary_body_preview;
+        let mut ended = false;
+        while preview && !ended && head.len() < PREVIEW_READ_BYTES {
+            match reader.next().await? {
+                Some(bytes) => head.extend_from_slice(&bytes),
+                None => ended = true,
+            }
+        }
+        let body_start = preview.then_some(head.as_slice());
+        let push = PushOptions {
+            topic: hook_topic(state, uuid, &request_id, &stored.options, &headers, &[])?,
+            ttl_secs,
+            sync,
+            summary: Some(hook_summary(&meta.method, &meta.path, &meta.headers, body_start)),
+        };
+        let mut writer = ChunkWriter::new(state, uuid, &request_id, None, push, total_bytes)?;
+        writer.write(&prefix).await?;
+        writer.write(&head).await?;
+        while !ended && let Some(bytes) = reader.next().await? {
+            writer.write(&bytes).await?;
+            // Chunks go out while the rest of the body is still arriving.
+            writer.flush().await?;
+        }
+        // Without a last chunk the client reports a partial delivery.
+        if reader.total_body_bytes != length {
             return Err(AppError::new(
-                StatusCode::REQUEST_TIMEOUT,
-                "request body timeout",
-            ));
+                StatusCode::BAD_REQUEST,
+                "body length does not match Content-Length",
+            )
+            .with_code("content_length_mismatch"));
         }
-
-        match timeout(remaining, stream.next()).await {
-            Ok(Some(Ok(bytes))) => {
-                total_body_bytes = total_body_bytes.saturating_add(bytes.len());
-                if total_body_bytes > max_body_bytes {
-                    return Err(AppError::new(
-                        StatusCode::PAYLOAD_TOO_LARGE,
-                        "payload exceeds limit",
-                    ));
-                }
-                buffer.extend_from_slice(&bytes);
+        writer.finish().await?
+    } else {
+        // Challenges, coalescing, forms, filters and history read the whole
+        // body, so only bodies that are just chunked spill to disk.
+        let spill_bytes = if slack_challenges
+            || coalesce_by_body
+            || !stored.options.filters.is_empty()
+            || form_boundary.is_some()
+            || cfg.history_enabled
+            || live
+        {
+            0
+        } else {
+            cfg.body_spill_bytes
+        };
+        let mut body = SpillBuffer::new(spill_bytes);
+        body.write_all(&head).await?;
+        if let Some(verifier) = verifier.as_mut() {
+            verifier.update(&head);
+        }
+        while let Some(bytes) = reader.next().await? {
+            if let Some(verifier) = verifier.as_mut() {
+                verifier.update(&bytes);
             }
-            Ok(Some(Err(_))) => {
-                return Err(AppError::new(
-                    StatusCode::BAD_REQUEST,
-                    "invalid request body",
-                ))
+            body.write_all(&bytes).await?;
+        }
+        // Nothing is stored or enqueued for a forged request.
+        if let Some(verifier) = verifier {
+            verifier.finish()?;
+            if repeated_key()? {
+                return Ok(StatusCode::OK.into_response());
+            }
+        }
+        if slack_challenges && challenge.is_none() {
+            challenge = slack_challenge(&body.contents()?, &cfg);
+            if let Some(value) = &challenge
+                && !push_challenge
+            {
+                return Ok(challenge_response(value));
             }
-            Ok(None) => break,
-            Err(_) => {
+            meta.verification = challenge.is_some();
+        }
+        // Like a repeated idempotency key: acknowledged, not relayed again.
+        if cfg.content_dedup {
+            let hash = content_hash(&meta.method, &meta.path, body.reader()?)?;
+            let Some(reservation) = state.content_dedup.check_and_insert(uuid, hash) else {
+                return Ok(StatusCode::OK.into_response());
+            };
+            dedup_reservation = Some(reservation);
+        }
+        // Only coalescing by body reads it here, and that keeps it in memory.
+        let topic_body = if coalesce_by_body {
+            body.contents()?
+        } else {
+            Cow::Borrowed(&[][..])
+        };
+        let topic = hook_topic(state, uuid, &request_id, &stored.options, &headers, &topic_body)?;
+        if let Some(boundary) = &form_boundary {
+            let form = summarize(&body.contents()?, boundary);
+            if let Some(form) = form {
+                meta.form = Some(form);
+                body.clear();
+            } else if body.len() > max_body_bytes {
                 return Err(AppError::new(
-                    StatusCode::REQUEST_TIMEOUT,
-                    "request body timeout",
-                ))
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "payload exceeds limit",
+                )
+                .with_code("payload_too_large"));
             }
         }
-    }
+        // Filters replace a JSON body with just the selected fields.
+        if !stored.options.filters.is_empty()
+            && meta.form.is_none()
+            && let Some(value) = parse_json_body(&body.contents()?, &cfg)
+        {
+            let reduced = filter_json(&value, &stored.options.filters);
+            body = SpillBuffer::from(serde_json::to_vec(&reduced)?);
+            meta.filtered = true;
+        }
+        meta.truncated = reader.truncated;
+        meta.original_content_length = reader.truncated.then_some(reader.total_body_bytes);
+        meta.content_length = body.len();
+        let meta_bytes = serde_json::to_vec(&meta)?;
+        // A form summary replaces the body, so it may use the body's budget.
+        let max_relayed_bytes = cfg.max_meta_bytes + max_body_bytes;
+        if meta_bytes.len() + body.len() > max_relayed_bytes {
+            return Err(AppError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload exceeds limit",
+            )
+            .with_code("payload_too_large"));
+        }
+        let prefix = payload_prefix(&meta_bytes);
+        let mut body_start = Vec::new();
+        if cfg.summary_body_preview {
+            body.reader()?
+                .take(PREVIEW_READ_BYTES as u64)
+                .read_to_end(&mut body_start)?;
+        }
+        let body_start = cfg.summary_body_preview.then_some(body_start.as_slice());
+        let summary = hook_summary(&meta.method, &meta.path, &meta.headers, body_start);
+        let captured = if cfg.history_enabled || live {
+            Some(StoredRequest {
+                request_id: request_id.clone(),
+                meta,
+                body: base64_encode(body.contents()?),
+            })
+        } else {
+            None
+        };
+        if cfg.history_enabled
+            && let Some(request) = &captured
+        {
+            history_put(&state.db, uuid, request, cfg.history_keep_per_uuid)?;
+            state.poll_waiters.notify(uuid);
+        }
 
-    let final_chunk = if buffer.is_empty() { Vec::new() } else { buffer };
-    chunk_index += 1;
-    let total_chunks = Some(chunk_index);
-    enqueue_chunk(
-        &state,
-        &uuid,
-        &request_id,
-        chunk_index,
-        true,
+        let push = PushOptions {
+            topic,
+            ttl_secs,
+            sync,
+            summary: Some(summary),
+        };
+        let total_chunks = relay_buffered(state, uuid, &request_id, push, prefix, &body).await?;
+        if live && let Some(request) = captured {
+            state.live_streams.publish(uuid, request);
+        }
+        total_chunks
+    };
+
+    if let Some(key) = &idempotency_key {
+        dedup_record(&state.db, uuid, key, Utc::now().timestamp())?;
+    }
+    if let Some(reservation) = dedup_reservation {
+        reservation.keep();
+    }
+    db_mark_active(&state.db, uuid, &stored, Utc::now())?;
+    metrics::inc(&state.metrics.hooks_relayed);
+    access_log::record("body_bytes", reader.total_body_bytes);
+    access_log::record("chunks", total_chunks);
+    let ack = HookAck {
+        request_id,
         total_chunks,
-        final_chunk,
-        next_send_after_ms,
-    )
-    .await?;
+        received_bytes: reader.total_body_bytes,
+    };
+    if let Some(value) = &challenge {
+        return Ok(challenge_response(value));
+    }
+    Ok(hook_response(&cfg, &stored.options, ack, delivery))
+}
 
-    Ok(StatusCode::ACCEPTED)
+// Drop replayed deliveries by their Date header; senders without one pass.
+fn check_webhook_age(headers: &HeaderMap, cfg: &Config, now: SystemTime) -> Result<(), AppError> {
+    if cfg.max_webhook_age_secs == 0 {
+        return Ok(());
+    }
+    let Some(value) = headers.get(DATE) else {
+        return Ok(());
+    };
+    let sent_at = value
+        .to_str()
+        .ok()
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .ok_or_else(|| {
+            AppError::new(StatusCode::BAD_REQUEST, "invalid Date header").with_code("invalid_date")
+        })?;
+    let skew = Duration::from_secs(cfg.webhook_date_skew_secs);
+    let max_age = Duration::from_secs(cfg.max_webhook_age_secs) + skew;
+    let too_old = now
+        .duration_since(sent_at)
+        .is_ok_and(|age| age > max_age);
+    let too_new = sent_at
+        .duration_since(now)
+        .is_ok_and(|ahead| ahead > skew);
+    if too_old || too_new {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "webhook Date outside allowed age",
+        )
+        .with_code("webhook_too_old"));
+    }
+    Ok(())
 }
 
-async fn enqueue_chunk(
+// Push topic for a coalescing subscription: the header value or the body is
+// the key, and the first hook's compact request id becomes the topic.
+fn hook_topic(
     state: &AppState,
     uuid: &str,
     request_id: &str,
-    chunk_index: usize,
-    is_last: bool,
-    total_chunks: Option<usize>,
-    chunk: Vec<u8>,
-    send_after_ms: i64,
-) -> Result<(), AppError> {
-    let envelope = ChunkEnvelope {
-        request_id: request_id.to_string(),
-        chunk_index,
-        total_chunks,
-        is_last,
-        data: base64_encode(chunk),
+    options: &SubscriptionOptions,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Option<String>, AppError> {
+    let Some(coalesce) = &options.coalesce else {
+        return Ok(None);
     };
-    let envelope_bytes = serde_json::to_vec(&envelope)?;
-    state.push_queue.enqueue(uuid, envelope_bytes, send_after_ms).await?;
-    Ok(())
+    let key = match &coalesce.header {
+        Some(name) => match headers.get(name.as_str()) {
+            Some(value) => value.as_bytes(),
+            None => return Ok(None),
+        },
+        None => body,
+    };
+    let window_secs = coalesce
+        .window_secs
+        .unwrap_or(state.cfg().coalesce_window_secs);
+    let topic = coalesce_topic(
+        &state.db,
+        uuid,
+        key,
+        &request_id.replace('-', ""),
+        Utc::now().timestamp(),
+        window_secs as i64,
+    )?;
+    Ok(Some(topic))
+}
+
+// Sender-chosen TTL in seconds, shorter or longer than PUSH_TTL_SECS.
+const X_PUSH_TTL: &str = "x-push-ttl";
+// Push services keep a message at most this long (FCM's four weeks).
+const MAX_PUSH_TTL_SECS: u64 = 2_419_200;
+
+// TTL for this hook's pushes: X-Push-TTL clamped to what push services allow,
+// else the PUSH_TTL_HEADER freshness hint capped at PUSH_TTL_SECS, so that
+// header can only shorten how long a push waits for an offline device.
+fn push_ttl(headers: &HeaderMap, cfg: &Config) -> Option<u32> {
+    if let Some(secs) = header_secs(headers, X_PUSH_TTL) {
+        return Some(secs.min(MAX_PUSH_TTL_SECS) as u32);
+    }
+    let name = cfg.push_ttl_header.as_deref()?;
+    let secs = header_secs(headers, name)?;
+    Some(secs.min(u64::from(cfg.push_ttl_secs)) as u32)
+}
+
+// A header holding a whole number of seconds; anything else is ignored.
+fn header_secs(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+// First value of `name` in a raw query string.
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn idempotency_key(headers: &HeaderMap, options: &SubscriptionOptions) -> Option<String> {
+    let name = options
+        .idempotency_header
+        .as_deref()
+        .unwrap_or("idempotency-key");
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+// Re-push a stored request under a fresh request id, e.g. after the
+// original push expired while the device was offline.
+pub async fn replay(
+    State(state): State<AppState>,
+    Path((uuid, request_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<ReplayResponse>), AppError> {
+    if !state.cfg().history_enabled {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            "history disabled",
+        )
+        .with_code("history_disabled"));
+    }
+    let stored = authorize_subscription(&state, &uuid, &headers)?;
+
+    let mut stored_request = history_get(&state.db, &uuid, &request_id)?
+        .ok_or_else(|| {
+            AppError::new(StatusCode::NOT_FOUND, "request not found").with_code("request_not_found")
+        })?;
+
+    let decision = state.rate_limiter.allow(&uuid).await;
+    if !decision.allowed {
+        return Err(AppError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate limit exceeded",
+        )
+        .with_code("rate_limited")
+        .with_headers(decision.headers()));
+    }
+
+    let body = decode(&stored_request.body).map_err(|_| {
+        AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "stored request body is corrupt",
+        )
+    })?;
+    // History written before content_length existed reads back as 0.
+    stored_request.meta.content_length = body.len();
+    let meta = &stored_request.meta;
+    let meta_bytes = serde_json::to_vec(meta)?;
+    let prefix = payload_prefix(&meta_bytes);
+
+    // Push settings as the hook handler derived them, from what was captured.
+    let cfg = state.cfg();
+    let request_id = Uuid::new_v4().to_string();
+    let captured_headers = HeaderMap::try_from(&meta.headers).unwrap_or_default();
+    let body_start = &body[..body.len().min(PREVIEW_READ_BYTES)];
+    let body_start = cfg.summary_body_preview.then_some(body_start);
+    let push = PushOptions {
+        topic: hook_topic(&state, &uuid, &request_id, &stored.options, &captured_headers, &body)?,
+        ttl_secs: push_ttl(&captured_headers, &cfg),
+        sync: None,
+        summary: Some(hook_summary(&meta.method, &meta.path, &meta.headers, body_start)),
+    };
+    let body = SpillBuffer::from(body);
+    let _hook_lock = state.hook_locks.lock(&uuid).await;
+    relay_buffered(&state, &uuid, &request_id, push, prefix, &body).await?;
+    metrics::inc(&state.metrics.hooks_relayed);
+
+    Ok((StatusCode::ACCEPTED, Json(ReplayResponse { request_id })))
+}
+
+// WHP1 magic + u32 BE metadata length + metadata JSON; the body follows.
+fn payload_prefix(meta_bytes: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(8 + meta_bytes.len());
+    prefix.extend_from_slice(b"WHP1");
+    prefix.extend_from_slice(&(meta_bytes.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(meta_bytes);
+    prefix
+}
+
+// Encode and chunk a fully buffered payload.
+async fn relay_buffered(
+    state: &AppState,
+    uuid: &str,
+    request_id: &str,
+    push: PushOptions<'_>,
+    prefix: Vec<u8>,
+    body: &SpillBuffer,
+) -> Result<usize, AppError> {
+    let compressed = encode_payload(
+        &prefix,
+        body,
+        state.cfg().compress_over_bytes,
+        state.cfg().body_spill_bytes,
+    )
+    .await?;
+    let writer = match &compressed {
+        Some(compressed) => {
+            let encoding = Some("gzip".to_string());
+            let mut writer =
+                ChunkWriter::new(state, uuid, request_id, encoding, push, compressed.len())?;
+            writer.write_from(compressed).await?;
+            writer
+        }
+        None => {
+            let payload_len = prefix.len() + body.len();
+            let mut writer = ChunkWriter::new(state, uuid, request_id, None, push, payload_len)?;
+            writer.write(&prefix).await?;
+            writer.write_from(body).await?;
+            writer
+        }
+    };
+    writer.finish().await
+}
+
+fn preflight_response(headers: &HeaderMap) -> Response {
+    const ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, HEAD, OPTIONS";
+    let allow_headers = headers
+        .get(ACCESS_CONTROL_REQUEST_HEADERS)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static("*"));
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (ALLOW, HeaderValue::from_static(ALLOWED_METHODS)),
+            (ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*")),
+            (ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static(ALLOWED_METHODS)),
+            (ACCESS_CONTROL_ALLOW_HEADERS, allow_headers),
+        ],
+    )
+        .into_response()
+}
+
+// Some providers only treat a specific 2xx status or literal body as delivered;
+// without a configured body the sender gets the ack JSON unless HOOK_ACK_BODY=false.
+// A synchronous delivery answers 200 unless the subscription picks a status.
+fn hook_response(
+    cfg: &Config,
+    options: &SubscriptionOptions,
+    ack: HookAck,
+    delivery: DeliveryMode,
+) -> Response {
+    let status = options
+        .response_status
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .or((delivery == DeliveryMode::Sync).then_some(StatusCode::OK))
+        .or_else(|| StatusCode::from_u16(cfg.hook_response_status).ok())
+        .unwrap_or(StatusCode::ACCEPTED);
+    if let Some(format) = options.response_format {
+        return formatted_response(status, options, format, ack);
+    }
+    let body = options
+        .response_body
+        .as_deref()
+        .unwrap_or(&cfg.hook_response_body);
+    if body.is_empty() {
+        if cfg.hook_ack_body {
+            return (status, Json(ack)).into_response();
+        }
+        return status.into_response();
+    }
+
+    let content_type = options
+        .response_content_type
+        .as_deref()
+        .unwrap_or(&cfg.hook_response_content_type);
+    match HeaderValue::from_str(content_type) {
+        Ok(value) => (status, [(CONTENT_TYPE, value)], body.to_string()).into_response(),
+        Err(_) => (status, body.to_string()).into_response(),
+    }
+}
+
+// Reply in the subscription's chosen format; response_content_type, when set,
+// still names the type.
+fn formatted_response(
+    status: StatusCode,
+    options: &SubscriptionOptions,
+    format: ResponseFormat,
+    ack: HookAck,
+) -> Response {
+    let body = match (format, &options.response_body) {
+        (ResponseFormat::Empty, _) => return status.into_response(),
+        (ResponseFormat::Json, None) => match serde_json::to_string(&ack) {
+            Ok(ack) => ack,
+            Err(_) => return status.into_response(),
+        },
+        (_, body) => body.clone().unwrap_or_default(),
+    };
+    let default_type = match format {
+        ResponseFormat::Json => "application/json",
+        _ => "text/plain; charset=utf-8",
+    };
+    let content_type = options
+        .response_content_type
+        .as_deref()
+        .and_then(|value| HeaderValue::from_str(value).ok())
+        .unwrap_or(HeaderValue::from_static(default_type));
+    (status, [(CONTENT_TYPE, content_type)], body).into_response()
+}
+
+// The challenge of a Slack `url_verification` event.
+fn slack_challenge(body: &[u8], cfg: &Config) -> Option<String> {
+    let value = parse_json_body(body, cfg)?;
+    if value.get("type")?.as_str()? != "url_verification" {
+        return None;
+    }
+    value.get("challenge")?.as_str().map(str::to_string)
+}
+
+fn challenge_response(challenge: &str) -> Response {
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, "text/plain")],
+        challenge.to_string(),
+    )
+        .into_response()
+}
+
+// Reads the request body under the read timeout and body size limit.
+struct BodyReader {
+    stream: BodyDataStream,
+    deadline: tokio::time::Instant,
+    max_body_bytes: usize,
+    total_body_bytes: usize,
+    // Cut the body at the limit instead of failing; the rest is drained.
+    truncate: bool,
+    truncated: bool,
+}
+
+impl BodyReader {
+    fn new(body: Body, max_body_bytes: usize, truncate: bool, read_timeout: Duration) -> Self {
+        Self {
+            stream: body.into_data_stream(),
+            deadline: tokio::time::Instant::now() + read_timeout,
+            max_body_bytes,
+            total_body_bytes: 0,
+            truncate,
+            truncated: false,
+        }
+    }
+
+    async fn next(&mut self) -> Result<Option<Bytes>, AppError> {
+        loop {
+            let Some(bytes) = self.read().await? else {
+                return Ok(None);
+            };
+            let before = self.total_body_bytes;
+            self.total_body_bytes = before.saturating_add(bytes.len());
+            if self.total_body_bytes <= self.max_body_bytes {
+                return Ok(Some(bytes));
+            }
+            if !self.truncate {
+                access_log::record("body_bytes", self.total_body_bytes);
+                return Err(AppError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "payload exceeds limit",
+                )
+                .with_code("payload_too_large"));
+            }
+            self.truncated = true;
+            let room = self.max_body_bytes.saturating_sub(before);
+            if room > 0 {
+                return Ok(Some(bytes.slice(..room)));
+            }
+            // Past the limit: keep draining so the sender sees a clean response.
+        }
+    }
+
+    async fn read(&mut self) -> Result<Option<Bytes>, AppError> {
+        let remaining = self
+            .deadline
+            .saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            return Err(AppError::new(
+                StatusCode::REQUEST_TIMEOUT,
+                "request body timeout",
+            )
+            .with_code("body_timeout"));
+        }
+
+        match timeout(remaining, self.stream.next()).await {
+            Ok(Some(Ok(bytes))) => Ok(Some(bytes)),
+            Ok(Some(Err(_))) => Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid request body",
+            )
+            .with_code("invalid_body")),
+            Ok(None) => Ok(None),
+            Err(_) => Err(AppError::new(
+                StatusCode::REQUEST_TIMEOUT,
+                "request body timeout",
+            )
+            .with_code("body_timeout")),
+        }
+    }
+}
+
+// Splits the relayed payload into envelopes and enqueues them in order.
+struct ChunkWriter<'a> {
+    state: &'a AppState,
+    uuid: &'a str,
+    request_id: &'a str,
+    encoding: Option<String>,
+    push: PushOptions<'a>,
+    chunk_size: usize,
+    // Smaller when the first envelope carries a summary.
+    first_chunk_size: usize,
+    buffer: Vec<u8>,
+    // Running hash of everything written, sent with the last chunk.
+    digest: Sha256,
+    // Announced in the first chunk; the bytes written must add up to it.
+    total_bytes: usize,
+    payload_len: usize,
+    chunk_index: usize,
+    next_send_after_ms: i64,
+    // Envelopes not yet queued, with their send times; see `flush`.
+    batch: Vec<(Vec<u8>, i64)>,
+    batch_bytes: usize,
+    // Set once a batch is queued; the rest of the hook skips the backlog cap.
+    admitted: bool,
+    // Seqs of the batches queued so far, taken back if the hook fails; see Drop.
+    queued: Vec<Range<u64>>,
+}
+
+// A writer dropped before `finish` (a refused batch, a body that fails or
+// comes up short, a client that goes away) removes the chunks it queued that
+// are still pending, so no partial request is left behind; only chunks a
+// worker has already claimed still go out.
+impl Drop for ChunkWriter<'_> {
+    fn drop(&mut self) {
+        if self.queued.is_empty() {
+            return;
+        }
+        if let Err(err) = self.state.push_queue.remove_pending(&self.queued) {
+            warn!("could not remove the chunks of failed hook {}: {err:?}", self.request_id);
+        }
+    }
+}
+
+// Envelopes held before a flush, so a spilled body never sits in memory whole.
+const MAX_BATCH_BYTES: usize = 256 * 1024;
+
+// Per-hook push settings shared by every chunk.
+#[derive(Default)]
+struct PushOptions<'a> {
+    // Shared by coalesced hooks.
+    topic: Option<String>,
+    // From PUSH_TTL_HEADER; PUSH_TTL_SECS when unset.
+    ttl_secs: Option<u32>,
+    // Push each chunk now instead of queueing it.
+    sync: Option<SyncDelivery<'a>>,
+    // Sent with the first chunk.
+    summary: Option<HookSummary>,
+}
+
+// `delivery=sync`: the hook waits for the push service, up to `deadline`.
+#[derive(Clone, Copy)]
+struct SyncDelivery<'a> {
+    subscription: &'a PushSubscription,
+    deadline: tokio::time::Instant,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn new(
+        state: &'a AppState,
+        uuid: &'a str,
+        request_id: &'a str,
+        encoding: Option<String>,
+        mut push: PushOptions<'a>,
+        total_bytes: usize,
+    ) -> Result<Self, AppError> {
+        // Resolve a safe chunk size that fits every envelope.
+        let chunk_size = resolve_chunk_size(
+            request_id,
+            encoding.as_deref(),
+            push.topic.as_deref(),
+            state.cfg().chunk_data_bytes,
+            total_bytes,
+        )?;
+        let resolve_first = |summary| {
+            resolve_first_chunk_size(
+                request_id,
+                encoding.as_deref(),
+                push.topic.as_deref(),
+                summary,
+                state.cfg().chunk_data_bytes,
+                total_bytes,
+            )
+        };
+        let first_chunk_size = match resolve_first(push.summary.as_ref()) {
+            Ok(size) => size,
+            // A summary that leaves no room for data is dropped, not fatal.
+            Err(_) if push.summary.is_some() => {
+                let size = resolve_first(None)?;
+                push.summary = None;
+                size
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            state,
+            uuid,
+            request_id,
+            encoding,
+            push,
+            chunk_size,
+            first_chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            digest: Sha256::new(),
+            total_bytes,
+            payload_len: 0,
+            chunk_index: 0,
+            next_send_after_ms: Utc::now().timestamp_millis(),
+            batch: Vec::new(),
+            batch_bytes: 0,
+            admitted: false,
+            queued: Vec::new(),
+        })
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), AppError> {
+        self.digest.update(bytes);
+        self.payload_len += bytes.len();
+        self.buffer.extend_from_slice(bytes);
+        loop {
+            let size = if self.chunk_index == 0 {
+                self.first_chunk_size
+            } else {
+                self.chunk_size
+            };
+            if self.buffer.len() < size {
+                return Ok(());
+            }
+            let chunk: Vec<u8> = self.buffer.drain(..size).collect();
+            self.enqueue(chunk, false).await?;
+        }
+    }
+
+    // Feeds a buffered body in pieces, so a spilled one is never loaded whole.
+    async fn write_from(&mut self, body: &SpillBuffer) -> Result<(), AppError> {
+        let mut reader = body.reader()?;
+        let mut piece = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut piece)?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.write(&piece[..read]).await?;
+        }
+    }
+
+    async fn finish(mut self) -> Result<usize, AppError> {
+        let final_chunk = std::mem::take(&mut self.buffer);
+        self.enqueue(final_chunk, true).await?;
+        self.flush().await?;
+        // The hook is whole; nothing left for Drop to take back.
+        self.queued.clear();
+        Ok(self.chunk_index)
+    }
+
+    // Queue the envelopes built so far in one write transaction.
+    async fn flush(&mut self) -> Result<(), AppError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let chunks = batch.len() as u64;
+        self.batch_bytes = 0;
+        let seqs = self
+            .state
+            .push_queue
+            .enqueue_batch(self.uuid, batch, self.push.ttl_secs, self.admitted)
+            .await?;
+        self.queued.push(seqs);
+        self.admitted = true;
+        metrics::add(&self.state.metrics.chunks_enqueued, chunks);
+        Ok(())
+    }
+
+    async fn enqueue(&mut self, chunk: Vec<u8>, is_last: bool) -> Result<(), AppError> {
+        self.chunk_index += 1;
+        let envelope = ChunkEnvelope {
+            version: ENVELOPE_VERSION,
+            request_id: self.request_id.to_string(),
+            chunk_index: self.chunk_index,
+            total_chunks: is_last.then_some(self.chunk_index),
+            is_last,
+            encoding: self.encoding.clone(),
+            topic: self.push.topic.clone(),
+            payload_sha256: is_last.then(|| hex::encode(self.digest.clone().finalize())),
+            payload_len: is_last.then_some(self.payload_len),
+            summary: self.push.summary.take(),
+            total_bytes: (self.chunk_index == 1).then_some(self.total_bytes),
+            crc32: crc32fast::hash(&chunk),
+            data: base64_encode(chunk),
+        };
+        let envelope_bytes = serde_json::to_vec(&envelope)?;
+        if let Some(sync) = self.push.sync {
+            return self.push_now(sync, &envelope_bytes).await;
+        }
+        self.batch_bytes += envelope_bytes.len();
+        self.batch.push((envelope_bytes, self.next_send_after_ms));
+        self.next_send_after_ms += self.state.cfg().chunk_delay_ms as i64;
+        if self.batch_bytes >= MAX_BATCH_BYTES {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    // No CHUNK_DELAY_MS spacing or retries: the caller is waiting on the outcome.
+    async fn push_now(&self, sync: SyncDelivery<'_>, envelope: &[u8]) -> Result<(), AppError> {
+        let ttl_secs = self.push.ttl_secs.unwrap_or(self.state.cfg().push_ttl_secs);
+        let send = self
+            .state
+            .push
+            .send(self.uuid, sync.subscription, envelope, ttl_secs);
+        let result = tokio::time::timeout_at(sync.deadline, send).await;
+        if self.state.cfg().record_push_errors
+            && let Ok(result) = &result
+        {
+            let error = result.as_ref().err().map(|err| err.error.message.as_str());
+            db_record_push_result(&self.state.db, self.uuid, error)?;
+        }
+        match result {
+            Ok(Ok(())) => {
+                metrics::inc(&self.state.metrics.pushes_sent);
+                Ok(())
+            }
+            Ok(Err(err)) => {
+                metrics::inc(&self.state.metrics.push_failures);
+                Err(AppError::new(
+                    StatusCode::BAD_GATEWAY,
+                    format!("push rejected: {}", err.error.message),
+                )
+                .with_code("push_rejected"))
+            }
+            Err(_) => Err(AppError::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                "push delivery timed out",
+            )
+            .with_code("push_timeout")),
+        }
+    }
+}
+
+// Gzip of prefix + body when the payload is above the threshold; the service
+// worker inflates after reassembly. None means ship it as-is.
+async fn encode_payload(
+    prefix: &[u8],
+    body: &SpillBuffer,
+    compress_over_bytes: usize,
+    spill_bytes: usize,
+) -> Result<Option<SpillBuffer>, AppError> {
+    let payload_len = prefix.len() + body.len();
+    if compress_over_bytes == 0 || payload_len <= compress_over_bytes {
+        return Ok(None);
+    }
+
+    // Gzip output is moved into the buffer as it is produced, so it can spill.
+    let mut compressed = SpillBuffer::new(spill_bytes);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(prefix)?;
+    let mut reader = body.reader()?;
+    let mut piece = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut piece)?;
+        if read == 0 {
+            break;
+        }
+        encoder.write_all(&piece[..read])?;
+        compressed.write_all(encoder.get_ref()).await?;
+        encoder.get_mut().clear();
+    }
+    compressed.write_all(&encoder.finish()?).await?;
+    // Already-compressed bodies can grow; ship those as-is.
+    if compressed.len() >= payload_len {
+        return Ok(None);
+    }
+
+    Ok(Some(compressed))
 }
 
 // Validate PushSubscription: HTTPS endpoint, allowlisted host, and key sizes.
-fn validate_subscription(
+pub(crate) fn validate_subscription(
     subscription: &PushSubscription,
     allowed_hosts: &[String],
 ) -> Result<(), AppError> {
     let endpoint = subscription.endpoint.trim();
     if endpoint.is_empty() {
-        return Err(AppError::new(StatusCode::BAD_REQUEST, "endpoint required"));
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "endpoint required",
+        )
+        .with_code("invalid_subscription"));
     }
     if endpoint.len() > 2048 {
-        return Err(AppError::new(StatusCode::BAD_REQUEST, "endpoint too long"));
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "endpoint too long",
+        )
+        .with_code("invalid_subscription"));
     }
     let uri: Uri = endpoint
         .parse()
-        .map_err(|_| AppError::new(StatusCode::BAD_REQUEST, "invalid endpoint url"))?;
+        .map_err(|_| {
+            AppError::new(StatusCode::BAD_REQUEST, "invalid endpoint url")
+                .with_code("invalid_subscription")
+        })?;
     let scheme = uri.scheme_str().unwrap_or("");
     if !scheme.eq_ignore_ascii_case("https") {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
             "endpoint must be https",
-        ));
+        )
+        .with_code("invalid_subscription"));
     }
     let host = uri
         .host()
-        .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, "endpoint host missing"))?;
+        .ok_or_else(|| {
+            AppError::new(StatusCode::BAD_REQUEST, "endpoint host missing")
+                .with_code("invalid_subscription")
+        })?;
     if !host_allowed(host, allowed_hosts) {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
             "endpoint host not allowed",
-        ));
+        )
+        .with_code("endpoint_host_not_allowed"));
     }
 
     if subscription.keys.p256dh.len() > 256 || subscription.keys.auth.len() > 128 {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
             "subscription keys too long",
-        ));
+        )
+        .with_code("invalid_subscription"));
     }
 
     let p256dh_bytes = decode_b64url(&subscription.keys.p256dh)
-        .map_err(|_| AppError::new(StatusCode::BAD_REQUEST, "invalid p256dh"))?;
+        .map_err(|_| {
+            AppError::new(StatusCode::BAD_REQUEST, "invalid p256dh")
+                .with_code("invalid_subscription")
+        })?;
     if p256dh_bytes.len() != 65 {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
             "invalid p256dh length",
-        ));
+        )
+        .with_code("invalid_subscription"));
     }
 
     let auth_bytes = decode_b64url(&subscription.keys.auth)
-        .map_err(|_| AppError::new(StatusCode::BAD_REQUEST, "invalid auth"))?;
+        .map_err(|_| {
+            AppError::new(StatusCode::BAD_REQUEST, "invalid auth").with_code("invalid_subscription")
+        })?;
     if auth_bytes.len() != 16 {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
             "invalid auth length",
-        ));
+        )
+        .with_code("invalid_subscription"));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn validate_options(
+    options: &SubscriptionOptions,
+    cfg: &Config,
+) -> Result<(), AppError> {
+    if options.min_body_bytes > cfg.max_body_bytes {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "min_body_bytes exceeds payload limit",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(code) = options.response_status
+        && !(200..=299).contains(&code)
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "response_status must be 200-299",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(body) = &options.response_body
+        && body.len() > MAX_HOOK_RESPONSE_BODY_BYTES
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "response_body too long",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(name) = &options.idempotency_header
+        && HeaderName::from_bytes(name.as_bytes()).is_err()
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid idempotency_header",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(
+        Verification::Github { secret }
+        | Verification::Stripe { secret, .. }
+        | Verification::Hmac { secret, .. },
+    ) = &options.verification
+        && (secret.is_empty() || secret.len() > 256)
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "verification secret must be 1-256 bytes",
+        )
+        .with_code("invalid_options"));
+    }
+    if options.allowed_source_cidrs.len() > 256
+        || options
+            .allowed_source_cidrs
+            .iter()
+            .any(|cidr| parse_cidr(cidr).is_none())
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid allowed_source_cidrs",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(token) = &options.hook_auth_token
+        && (token.is_empty() || token.len() > 256 || HeaderValue::from_str(token).is_err())
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "hook_auth_token must be 1-256 header-safe bytes",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(Verification::Hmac { header, .. }) = &options.verification
+        && HeaderName::from_bytes(header.as_bytes()).is_err()
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid verification header",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(coalesce) = &options.coalesce
+        && (coalesce
+            .header
+            .as_ref()
+            .is_some_and(|name| HeaderName::from_bytes(name.as_bytes()).is_err())
+            || coalesce
+                .window_secs
+                .is_some_and(|secs| secs == 0 || secs > 86_400))
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid coalesce options",
+        )
+        .with_code("invalid_options"));
+    }
+    if options.filters.len() > MAX_FILTERS
+        || !options.filters.iter().all(|filter| valid_filter(filter))
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "filters must be up to 32 JSON pointers such as /repository/full_name",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(Challenge {
+        provider: ChallengeProvider::Query { param: Some(name) },
+        ..
+    }) = &options.challenge
+        && (name.is_empty() || name.len() > 64)
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid challenge param",
+        )
+        .with_code("invalid_options"));
+    }
+    let body_fits_format = match (options.response_format, &options.response_body) {
+        (Some(ResponseFormat::Empty), Some(_)) => false,
+        (Some(ResponseFormat::Json), Some(body)) => {
+            serde_json::from_str::<serde_json::Value>(body).is_ok()
+        }
+        _ => true,
+    };
+    if !body_fits_format {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "response_body doesn't match response_format",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(content_type) = &options.response_content_type
+        && (content_type.len() > 128 || HeaderValue::from_str(content_type).is_err())
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid response_content_type",
+        )
+        .with_code("invalid_options"));
+    }
+    if let Some(names) = &options.capture_headers
+        && (names.len() > 64
+            || names
+                .iter()
+                .any(|name| name != "*" && HeaderName::from_bytes(name.as_bytes()).is_err()))
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid capture_headers",
+        )
+        .with_code("invalid_options"));
+    }
+    if options.labels.len() > MAX_LABELS
+        || options
+            .labels
+            .iter()
+            .any(|(key, value)| key.is_empty() || key.len() > 64 || value.len() > 256)
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "labels must be at most 16 keys of 1-64 bytes with values up to 256 bytes",
+        )
+        .with_code("invalid_options"));
+    }
+    if cfg.require_owner_label
+        && options
+            .labels
+            .get("owner")
+            .is_none_or(|owner| owner.trim().is_empty())
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "an owner label is required",
+        )
+        .with_code("owner_label_required"));
     }
 
     Ok(())
@@ -363,15 +1993,28 @@ fn decode_b64url(value: &str) -> Result<Vec<u8>, base64::DecodeError> {
 
 fn envelope_overhead_bytes(
     request_id: &str,
+    encoding: Option<&str>,
+    topic: Option<&str>,
     chunk_index: usize,
     total_chunks: Option<usize>,
     is_last: bool,
+    summary: Option<&HookSummary>,
 ) -> Result<usize, AppError> {
     let envelope = ChunkEnvelope {
+        version: ENVELOPE_VERSION,
         request_id: request_id.to_string(),
         chunk_index,
         total_chunks,
         is_last,
+        encoding: encoding.map(str::to_string),
+        topic: topic.map(str::to_string),
+        // Worst case: a full digest and a length as wide as the chunk index.
+        payload_sha256: is_last.then(|| "0".repeat(64)),
+        payload_len: is_last.then_some(chunk_index),
+        summary: summary.cloned(),
+        total_bytes: None,
+        // Widest checksum: ten digits.
+        crc32: u32::MAX,
         data: String::new(),
     };
     Ok(serde_json::to_vec(&envelope)?.len())
@@ -380,32 +2023,69 @@ fn envelope_overhead_bytes(
 // Resolve chunk size so every envelope fits within Web Push limits.
 fn resolve_chunk_size(
     request_id: &str,
+    encoding: Option<&str>,
+    topic: Option<&str>,
     configured: usize,
     max_total_bytes: usize,
 ) -> Result<usize, AppError> {
     let worst_index = max_total_bytes.max(1);
     let overhead = envelope_overhead_bytes(
         request_id,
+        encoding,
+        topic,
         worst_index,
         Some(worst_index),
         true,
+        None,
     )?;
     max_chunk_data_bytes(configured, overhead)
 }
 
+// Same as resolve_chunk_size, for the first envelope, which also carries
+// total_bytes and any summary.
+fn resolve_first_chunk_size(
+    request_id: &str,
+    encoding: Option<&str>,
+    topic: Option<&str>,
+    summary: Option<&HookSummary>,
+    configured: usize,
+    max_total_bytes: usize,
+) -> Result<usize, AppError> {
+    let worst_index = max_total_bytes.max(1);
+    let overhead = envelope_overhead_bytes(
+        request_id,
+        encoding,
+        topic,
+        worst_index,
+        Some(worst_index),
+        true,
+        summary,
+    )?;
+    let total_bytes_field = format!(r#","total_bytes":{max_total_bytes}"#).len();
+    max_chunk_data_bytes(configured, overhead + total_bytes_field)
+}
+
+// Budget for one serialized envelope. Push services accept 4096 bytes of
+// encrypted record, and aes128gcm leaves room for about 3990 bytes of plaintext;
+// web-push refuses anything over 3052. The rest is headroom under that cap.
+const MAX_ENVELOPE_BYTES: usize = 3000;
+// Largest encrypted push body push services must accept (RFC 8030 section 7.2).
+#[cfg(test)]
+const PUSH_SERVICE_MAX_BYTES: usize = 4096;
+
 // Compute the maximum raw payload per chunk after base64 + envelope overhead.
 fn max_chunk_data_bytes(configured: usize, overhead: usize) -> Result<usize, AppError> {
-    const MAX_ENVELOPE_BYTES: usize = 3000;
     if overhead >= MAX_ENVELOPE_BYTES {
         return Err(AppError::new(
             StatusCode::PAYLOAD_TOO_LARGE,
             "chunk overhead exceeds push limit",
-        ));
+        )
+        .with_code("payload_too_large"));
     }
 
     let available = MAX_ENVELOPE_BYTES - overhead;
     let mut max_raw = (available / 4) * 3;
-    while 4 * ((max_raw + 2) / 3) > available {
+    while 4 * max_raw.div_ceil(3) > available {
         max_raw = max_raw.saturating_sub(1);
     }
 
@@ -414,31 +2094,169 @@ fn max_chunk_data_bytes(configured: usize, overhead: usize) -> Result<usize, App
         return Err(AppError::new(
             StatusCode::PAYLOAD_TOO_LARGE,
             "chunk size too small",
-        ));
+        )
+        .with_code("payload_too_large"));
     }
 
     Ok(chunk_size)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::{
+        access_log::{
+            tests::{capture_json_logs, logged_lines},
+            HOOK_ACCESS,
+        },
+        cooldown::OversizeCooldown,
+        db::init_db,
+        dedup::init_dedup,
+        history::init_history,
+        hook_lock::HookLocks,
+        content_dedup::ContentDedup,
+        live::LiveStreams,
+        poll::PollWaiters,
+        metrics::Metrics,
+        models::Coalesce,
+        push::{PushError, PushTransport},
+        queue::{init_queue_db, pending_records, pending_ttls, DiskQueue},
+        rate_limiter::RateLimiter,
+    };
+    use arc_swap::ArcSwap;
     use base64::{encode_config, URL_SAFE_NO_PAD};
+    use futures_util::future::BoxFuture;
+    use redb::{backends::InMemoryBackend, Database};
+    use std::sync::Arc;
 
-    fn make_subscription(endpoint: &str, p256dh_bytes: usize, auth_bytes: usize) -> PushSubscription {
-        let p256dh = encode_config(vec![1u8; p256dh_bytes], URL_SAFE_NO_PAD);
-        let auth = encode_config(vec![2u8; auth_bytes], URL_SAFE_NO_PAD);
-        PushSubscription {
-            endpoint: endpoint.to_string(),
-            expiration_time: None,
-            keys: crate::models::PushKeys { p256dh, auth },
+    // Stand-in for the push service.
+    enum FakePush {
+        Accept,
+        Reject,
+        Hang,
+    }
+
+    impl PushTransport for FakePush {
+        fn send<'a>(
+            &'a self,
+            _uuid: &'a str,
+            _subscription: &'a PushSubscription,
+            _payload: &'a [u8],
+            _ttl_secs: u32,
+        ) -> BoxFuture<'a, Result<(), PushError>> {
+            Box::pin(async move {
+                match self {
+                    FakePush::Accept => Ok(()),
+                    FakePush::Reject => Err(PushError::permanent(AppError::new(
+                        StatusCode::BAD_GATEWAY,
+                        "subscription expired",
+                    ))),
+                    FakePush::Hang => std::future::pending().await,
+                }
+            })
         }
     }
 
-    #[test]
-    fn validate_subscription_accepts_valid() {
-        let sub = make_subscription("https://example.com/endpoint", 65, 16);
-        let allowed = vec!["example.com".to_string()];
+    fn memory_db() -> Arc<Database> {
+        Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        )
+    }
+
+    fn test_config(vars: &[(&str, &str)]) -> Config {
+        let mut values: HashMap<String, String> = HashMap::from([
+            ("VAPID_PUBLIC_KEY".to_string(), "test-public".to_string()),
+            ("VAPID_PRIVATE_KEY".to_string(), "test-private".to_string()),
+        ]);
+        for (key, value) in vars {
+            values.insert(key.to_string(), value.to_string());
+        }
+        Config::from_source(&|key| values.get(key).cloned()).unwrap()
+    }
+
+    // State with no queue workers, so enqueued records stay put for inspection.
+    pub(crate) fn test_state(vars: &[(&str, &str)]) -> (AppState, Arc<Database>) {
+        let mut cfg = test_config(vars);
+        cfg.queue_workers = 0;
+        let config = Arc::new(ArcSwap::from_pointee(cfg));
+        let cfg = config.load_full();
+
+        let db = memory_db();
+        init_db(&db).unwrap();
+        init_history(&db).unwrap();
+        init_dedup(&db).unwrap();
+        let queue_db = memory_db();
+        init_queue_db(&queue_db).unwrap();
+        let metrics = Arc::new(Metrics::default());
+        let push: Arc<dyn PushTransport> = Arc::new(FakePush::Accept);
+        let push_queue = DiskQueue::new(
+            queue_db.clone(),
+            db.clone(),
+            config.clone(),
+            push.clone(),
+            metrics.clone(),
+        );
+        let state = AppState {
+            db,
+            config,
+            rate_limiter: Arc::new(RateLimiter::new(cfg.rate_limit_per_minute)),
+            push_queue,
+            push,
+            metrics,
+            uuid_allowlist: None,
+            oversize_cooldown: Arc::new(OversizeCooldown::new(
+                cfg.oversize_strikes,
+                Duration::from_secs(cfg.oversize_cooldown_secs),
+            )),
+            hook_locks: Arc::new(HookLocks::new(cfg.serialize_hooks)),
+            live_streams: Arc::new(LiveStreams::new(cfg.live_streams_per_uuid)),
+            poll_waiters: Arc::new(PollWaiters::default()),
+            content_dedup: Arc::new(ContentDedup::new(Duration::from_secs(
+                cfg.content_dedup_window_secs,
+            ))),
+        };
+        (state, queue_db)
+    }
+
+    fn store_subscription(state: &AppState, options: SubscriptionOptions) -> String {
+        let uuid = generate_uuid(&state.db, &state.cfg()).unwrap();
+        let stored = StoredSubscription {
+            subscription: make_subscription("https://example.com/endpoint", 65, 16),
+            created_at: Utc::now(),
+            delete_token: "token".to_string(),
+            options,
+            last_active_at: None,
+            last_error: None,
+            last_error_at: None,
+        };
+        db_put(&state.db, &uuid, &stored).unwrap();
+        uuid
+    }
+
+    fn hook_request(method: &str, body: &'static str) -> Request {
+        Request::builder()
+            .method(method)
+            .uri("/hook/test")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn make_subscription(endpoint: &str, p256dh_bytes: usize, auth_bytes: usize) -> PushSubscription {
+        let p256dh = encode_config(vec![1u8; p256dh_bytes], URL_SAFE_NO_PAD);
+        let auth = encode_config(vec![2u8; auth_bytes], URL_SAFE_NO_PAD);
+        PushSubscription {
+            endpoint: endpoint.to_string(),
+            expiration_time: None,
+            keys: crate::models::PushKeys { p256dh, auth },
+        }
+    }
+
+    #[test]
+    fn validate_subscription_accepts_valid() {
+        let sub = make_subscription("https://example.com/endpoint", 65, 16);
+        let allowed = vec!["example.com".to_string()];
         assert!(validate_subscription(&sub, &allowed).is_ok());
     }
 
@@ -468,24 +2286,1987 @@ mod tests {
     fn resolve_chunking_keeps_envelope_under_limit() {
         let payload = vec![0u8; 10_000];
         let request_id = "req-1";
-        let chunk_size = resolve_chunk_size(request_id, 2400, payload.len()).unwrap();
+        let chunk_size = resolve_chunk_size(request_id, None, None, 2400, payload.len()).unwrap();
         assert!(chunk_size > 0 && chunk_size <= 2400);
 
         let chunks: Vec<&[u8]> = payload.chunks(chunk_size).collect();
         let total_chunks = chunks.len();
 
-        const MAX_ENVELOPE_BYTES: usize = 3000;
         for (index, chunk) in chunks.iter().enumerate() {
             let is_last = index + 1 == total_chunks;
             let envelope = ChunkEnvelope {
+                version: ENVELOPE_VERSION,
                 request_id: request_id.to_string(),
                 chunk_index: index + 1,
                 total_chunks: if is_last { Some(total_chunks) } else { None },
                 is_last,
+                encoding: None,
+                topic: None,
+                payload_sha256: is_last.then(|| hex::encode(Sha256::digest(&payload))),
+                payload_len: is_last.then_some(payload.len()),
+                summary: None,
+                total_bytes: (index == 0).then_some(payload.len()),
+                crc32: crc32fast::hash(chunk),
                 data: base64_encode(chunk),
             };
             let size = serde_json::to_vec(&envelope).unwrap().len();
             assert!(size <= MAX_ENVELOPE_BYTES);
         }
     }
+
+    #[test]
+    fn worst_case_envelope_fits_the_push_service_limit() {
+        use crate::summary::{
+            MAX_CONTENT_TYPE_CHARS, MAX_METHOD_CHARS, MAX_PATH_CHARS, MAX_PREVIEW_CHARS,
+            MAX_SOURCE_CHARS,
+        };
+        // Four UTF-8 bytes per char, none of them escaped by JSON.
+        let wide = |chars: usize| "\u{1F600}".repeat(chars);
+        let summary = HookSummary {
+            method: wide(MAX_METHOD_CHARS),
+            path: wide(MAX_PATH_CHARS),
+            source: Some(wide(MAX_SOURCE_CHARS)),
+            content_type: Some(wide(MAX_CONTENT_TYPE_CHARS)),
+            preview: Some(wide(MAX_PREVIEW_CHARS)),
+        };
+        let request_id = Uuid::new_v4().to_string();
+        let topic = "f".repeat(32);
+        let max_total_bytes = usize::MAX / 2;
+        let chunk_size = resolve_first_chunk_size(
+            &request_id,
+            Some("gzip"),
+            Some(&topic),
+            Some(&summary),
+            usize::MAX,
+            max_total_bytes,
+        )
+        .unwrap();
+        let envelope = ChunkEnvelope {
+            version: ENVELOPE_VERSION,
+            request_id,
+            chunk_index: max_total_bytes,
+            total_chunks: Some(max_total_bytes),
+            is_last: true,
+            encoding: Some("gzip".to_string()),
+            topic: Some(topic),
+            payload_sha256: Some("0".repeat(64)),
+            payload_len: Some(max_total_bytes),
+            summary: Some(summary),
+            total_bytes: Some(max_total_bytes),
+            crc32: u32::MAX,
+            data: base64_encode(vec![0xff; chunk_size]),
+        };
+        let plaintext = serde_json::to_vec(&envelope).unwrap();
+        assert!(plaintext.len() <= MAX_ENVELOPE_BYTES, "{}", plaintext.len());
+
+        // Encrypt it as a real push would be.
+        let subscription = web_push::SubscriptionInfo::new(
+            "https://fcm.googleapis.com/fcm/send/x",
+            concat!(
+                "BAPds9LdomeoNOiwVyEWvHcp3qoj8OxRPjzM9UffPT36rq6t-",
+                "jjgfuoiRWAiXhsZSProN93WE2sL1egzOWYj8ao"
+            ),
+            "AAAAAAAAAAAAAAAAAAAAAA",
+        );
+        let mut builder = web_push::WebPushMessageBuilder::new(&subscription).unwrap();
+        builder.set_payload(web_push::ContentEncoding::Aes128Gcm, &plaintext);
+        let encrypted = builder.build().unwrap().payload.unwrap().content;
+        assert!(encrypted.len() <= PUSH_SERVICE_MAX_BYTES, "{}", encrypted.len());
+    }
+
+    fn repetitive_json(records: usize) -> Vec<u8> {
+        let items: Vec<String> = (0..records)
+            .map(|index| format!(r#"{{"id":{index},"status":"active","tags":["alpha","beta"]}}"#))
+            .collect();
+        format!("[{}]", items.join(",")).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn encode_payload_compresses_repetitive_json() {
+        let payload = repetitive_json(1500);
+        let raw_len = payload.len();
+        let encoded = encode_payload(&[], &SpillBuffer::from(payload), 4096, 0)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let raw_chunk = resolve_chunk_size("req-1", None, None, 2400, raw_len).unwrap();
+        let gzip_chunk =
+            resolve_chunk_size("req-1", Some("gzip"), None, 2400, encoded.len()).unwrap();
+        let raw_chunks = raw_len.div_ceil(raw_chunk);
+        let gzip_chunks = encoded.len().div_ceil(gzip_chunk);
+        assert!(gzip_chunks * 5 < raw_chunks, "{gzip_chunks} vs {raw_chunks}");
+    }
+
+    #[tokio::test]
+    async fn encode_payload_skips_when_disabled_or_small() {
+        let payload = repetitive_json(1500);
+        let encoded = encode_payload(&[], &SpillBuffer::from(payload), 0, 0).await.unwrap();
+        assert!(encoded.is_none());
+
+        let small = SpillBuffer::from(b"{\"ok\":true}".to_vec());
+        assert!(encode_payload(&[], &small, 4096, 0).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn spilled_body_chunks_like_an_in_memory_one() {
+        let body: Vec<u8> = (0..3_000_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let prefix = payload_prefix(b"{}");
+
+        let mut spilled = SpillBuffer::new(65536);
+        for piece in body.chunks(16 * 1024) {
+            spilled.write_all(piece).await.unwrap();
+        }
+        let in_memory = SpillBuffer::from(body);
+        assert_eq!(spilled.len(), in_memory.len());
+
+        // Gzip output spills too once it passes BODY_SPILL_BYTES.
+        let mut outputs = Vec::new();
+        for compress_over_bytes in ["0", "4096"] {
+            for body in [&in_memory, &spilled] {
+                let (state, queue_db) = test_state(&[
+                    ("BODY_SPILL_BYTES", "65536"),
+                    ("COMPRESS_OVER_BYTES", compress_over_bytes),
+                ]);
+                let push = PushOptions::default();
+                relay_buffered(&state, "abc", "req-1", push, prefix.clone(), body)
+                    .await
+                    .unwrap();
+                outputs.push(pending_records(&queue_db));
+            }
+        }
+        assert!(outputs[0].len() > 1000);
+        assert_eq!(outputs[0], outputs[1]);
+        assert!(outputs[2].len() < outputs[0].len());
+        assert_eq!(outputs[2], outputs[3]);
+    }
+
+    #[tokio::test]
+    async fn worst_case_summary_fits_the_first_envelope() {
+        // Four-byte characters in every field, each far past its clip length.
+        let wide = "\u{1F600}".repeat(1000);
+        let headers = HashMap::from([
+            ("user-agent".to_string(), wide.clone()),
+            ("content-type".to_string(), format!("text/{wide}")),
+        ]);
+        let path = format!("/{wide}");
+        let summary = hook_summary(&wide, &path, &headers, Some(wide.as_bytes()));
+        assert_eq!(summary.preview.as_ref().unwrap().chars().count(), 200);
+
+        let (state, queue_db) = test_state(&[]);
+        let request_id = Uuid::new_v4().to_string();
+        let payload = vec![b'a'; 20_000];
+        let push = PushOptions {
+            topic: Some("t".repeat(22)),
+            summary: Some(summary.clone()),
+            ..Default::default()
+        };
+        let mut writer =
+            ChunkWriter::new(&state, "abc", &request_id, None, push, payload.len()).unwrap();
+        writer.write(&payload).await.unwrap();
+        writer.finish().await.unwrap();
+
+        let records = pending_records(&queue_db);
+        assert!(records.iter().all(|(_, envelope)| envelope.len() <= 3000));
+        let first: serde_json::Value = serde_json::from_slice(&records[0].1).unwrap();
+        assert_eq!(first["summary"]["path"].as_str(), Some(summary.path.as_str()));
+        let second: serde_json::Value = serde_json::from_slice(&records[1].1).unwrap();
+        assert!(second.get("summary").is_none());
+    }
+
+    #[tokio::test]
+    async fn hook_accepts_tiny_ping_without_enqueueing() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                min_body_bytes: 16,
+                ..Default::default()
+            },
+        );
+
+        let response = hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", "ping"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(pending_records(&queue_db).is_empty());
+
+        let response = hook(
+            State(state),
+            Path(uuid),
+            hook_request("POST", r#"{"event":"deploy.finished"}"#),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+    }
+
+    async fn body_bytes(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn hook_response_defaults_to_ack_json() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let response = hook(State(state), Path(uuid), hook_request("POST", "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "application/json");
+        let ack: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        let request_id = ack["request_id"].as_str().unwrap();
+        assert!(!relayed_bytes(&queue_db, request_id).is_empty());
+        assert_eq!(ack["total_chunks"], 1);
+        assert_eq!(ack["received_bytes"], 2);
+    }
+
+    #[tokio::test]
+    async fn hook_ack_body_can_be_disabled() {
+        let (state, _queue_db) = test_state(&[("HOOK_ACK_BODY", "false")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let response = hook(State(state), Path(uuid), hook_request("POST", "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(body_bytes(response).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn hook_response_uses_server_default() {
+        let (state, _queue_db) = test_state(&[
+            ("HOOK_RESPONSE_STATUS", "200"),
+            ("HOOK_RESPONSE_BODY", "OK"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let response = hook(State(state), Path(uuid), hook_request("POST", "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(body_bytes(response).await, b"OK");
+    }
+
+    #[tokio::test]
+    async fn hook_response_uses_subscription_override() {
+        let (state, _queue_db) = test_state(&[("HOOK_RESPONSE_BODY", "OK")]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                response_status: Some(201),
+                response_body: Some(r#"{"received":true}"#.to_string()),
+                response_content_type: Some("application/json".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let response = hook(State(state), Path(uuid), hook_request("POST", "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(body_bytes(response).await, br#"{"received":true}"#);
+    }
+
+    #[tokio::test]
+    async fn hook_response_follows_each_response_format() {
+        // Server defaults that every format overrides.
+        let (state, _queue_db) = test_state(&[("HOOK_RESPONSE_BODY", "OK")]);
+        let respond = |format, body: Option<&str>| {
+            let uuid = store_subscription(
+                &state,
+                SubscriptionOptions {
+                    response_format: Some(format),
+                    response_body: body.map(str::to_string),
+                    ..Default::default()
+                },
+            );
+            hook(State(state.clone()), Path(uuid), hook_request("POST", "{}"))
+        };
+
+        let response = respond(ResponseFormat::Json, None).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let ack: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(ack["total_chunks"], 1);
+
+        let response = respond(ResponseFormat::Json, Some(r#"{"ok":1}"#)).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(body_bytes(response).await, br#"{"ok":1}"#);
+
+        let response = respond(ResponseFormat::Text, Some("thanks")).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(body_bytes(response).await, b"thanks");
+
+        let response = respond(ResponseFormat::Empty, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(response.headers().get(CONTENT_TYPE).is_none());
+        assert!(body_bytes(response).await.is_empty());
+
+        let cfg = test_config(&[]);
+        for (format, body) in [(ResponseFormat::Json, "not json"), (ResponseFormat::Empty, "x")] {
+            let options = SubscriptionOptions {
+                response_format: Some(format),
+                response_body: Some(body.to_string()),
+                ..Default::default()
+            };
+            assert!(validate_options(&options, &cfg).is_err());
+        }
+    }
+
+    #[test]
+    fn validate_options_rejects_non_2xx_status() {
+        let cfg = test_config(&[]);
+        let options = SubscriptionOptions {
+            response_status: Some(302),
+            ..Default::default()
+        };
+        assert!(validate_options(&options, &cfg).is_err());
+        let options = SubscriptionOptions {
+            response_body: Some("x".repeat(MAX_HOOK_RESPONSE_BODY_BYTES + 1)),
+            ..Default::default()
+        };
+        assert!(validate_options(&options, &cfg).is_err());
+    }
+
+    #[tokio::test]
+    async fn hook_records_history_newest_first() {
+        let (state, _queue_db) = test_state(&[("HISTORY_ENABLED", "true")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        for body in ["first", "second"] {
+            hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", body))
+                .await
+                .unwrap();
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        let Json(response) = history(
+            State(state),
+            Path(uuid),
+            Query(HistoryQuery {
+                before: None,
+                limit: None,
+            }),
+            headers,
+        )
+        .await
+        .unwrap();
+        let bodies: Vec<String> = response
+            .requests
+            .iter()
+            .map(|entry| String::from_utf8(decode(&entry.request.body).unwrap()).unwrap())
+            .collect();
+        assert_eq!(bodies, vec!["second", "first"]);
+        assert!(response.next_before.is_none());
+    }
+
+    // Concatenated chunk data for one request id, in enqueue order.
+    fn relayed_bytes(queue_db: &Database, request_id: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (_, payload) in pending_records(queue_db) {
+            let envelope: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+            if envelope["request_id"] == request_id {
+                data.extend(decode(envelope["data"].as_str().unwrap()).unwrap());
+            }
+        }
+        data
+    }
+
+    // Metadata and body of a relayed request, split at the WHP1 prefix.
+    async fn relayed_meta_and_body(queue_db: &Database, response: Response) -> (HookMeta, Vec<u8>) {
+        let ack: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        let payload = relayed_bytes(queue_db, ack["request_id"].as_str().unwrap());
+        assert_eq!(&payload[..4], b"WHP1");
+        let meta_len = u32::from_be_bytes(payload[4..8].try_into().unwrap()) as usize;
+        let meta = serde_json::from_slice(&payload[8..8 + meta_len]).unwrap();
+        (meta, payload[8 + meta_len..].to_vec())
+    }
+
+    #[tokio::test]
+    async fn meta_reports_content_type_and_delivered_length() {
+        let (state, queue_db) = test_state(&[
+            ("COMPRESS_OVER_BYTES", "0"),
+            ("CAPTURE_HEADERS", "user-agent"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        // Streamed: the length is declared up front.
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_LENGTH, "17")
+            .body(Body::from(r#"{"event":"ping"}"#.to_string() + " "))
+            .unwrap();
+        let response = hook(State(state.clone()), Path(uuid.clone()), request)
+            .await
+            .unwrap();
+        let (meta, body) = relayed_meta_and_body(&queue_db, response).await;
+        assert_eq!(meta.content_type.as_deref(), Some("application/json"));
+        assert!(!meta.headers.contains_key("content-type"));
+        assert_eq!(meta.content_length, body.len());
+        assert_eq!(body.len(), 17);
+
+        // Buffered: no Content-Length, so it is counted once the body ends.
+        let response = hook(State(state.clone()), Path(uuid.clone()), hook_request("PUT", "hello"))
+            .await
+            .unwrap();
+        let (meta, body) = relayed_meta_and_body(&queue_db, response).await;
+        assert_eq!(meta.content_type, None);
+        assert_eq!((meta.content_length, body.as_slice()), (5, &b"hello"[..]));
+
+        // Truncated bodies report what was relayed, not what was sent.
+        let (state, queue_db) = test_state(&[
+            ("MAX_BODY_BYTES", "4"),
+            ("OVERSIZE_MODE", "truncate"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let response = hook(State(state), Path(uuid), hook_request("POST", "truncated"))
+            .await
+            .unwrap();
+        let (meta, body) = relayed_meta_and_body(&queue_db, response).await;
+        assert_eq!((meta.content_length, body.as_slice()), (4, &b"trun"[..]));
+        assert_eq!(meta.original_content_length, Some(9));
+    }
+
+    #[tokio::test]
+    async fn replay_matches_original_capture() {
+        let (state, queue_db) = test_state(&[("HISTORY_ENABLED", "true")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", r#"{"event":"deploy.finished"}"#),
+        )
+        .await
+        .unwrap();
+        let original = history_list(&state.db, &uuid, None, 1).unwrap().remove(0).1;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        let (status, Json(response)) = replay(
+            State(state.clone()),
+            Path((uuid.clone(), original.request_id.clone())),
+            headers.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_ne!(response.request_id, original.request_id);
+
+        let captured = relayed_bytes(&queue_db, &original.request_id);
+        assert!(!captured.is_empty());
+        assert_eq!(relayed_bytes(&queue_db, &response.request_id), captured);
+        let summary = |request_id: &str| {
+            pending_records(&queue_db)
+                .into_iter()
+                .map(|(_, payload)| serde_json::from_slice::<serde_json::Value>(&payload).unwrap())
+                .find(|envelope| envelope["request_id"] == request_id)
+                .unwrap()["summary"]
+                .clone()
+        };
+        assert!(summary(&original.request_id).is_object());
+        assert_eq!(summary(&response.request_id), summary(&original.request_id));
+
+        let missing = replay(State(state), Path((uuid, "missing".to_string())), headers).await;
+        assert!(matches!(missing, Err(err) if err.status == StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn preflight_and_head_are_answered_without_relaying() {
+        let (state, queue_db) = test_state(&[("RATE_LIMIT_PER_MINUTE", "1")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let request = Request::builder()
+            .method("OPTIONS")
+            .uri("/hook/test")
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap();
+        let response = hook(State(state.clone()), Path(uuid.clone()), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers().get(ALLOW).unwrap().to_str().unwrap().contains("POST"));
+        assert_eq!(response.headers().get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "content-type");
+
+        let response = hook(State(state.clone()), Path(uuid.clone()), hook_request("HEAD", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(pending_records(&queue_db).is_empty());
+
+        // Neither probe used the single allowed request.
+        let response = hook(State(state), Path(uuid), hook_request("GET", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn relay_preflight_relays_options_and_head() {
+        let (state, queue_db) = test_state(&[("RELAY_PREFLIGHT", "true")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        for method in ["OPTIONS", "HEAD"] {
+            let response = hook(State(state.clone()), Path(uuid.clone()), hook_request(method, ""))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+        assert_eq!(pending_records(&queue_db).len(), 2);
+    }
+
+    #[test]
+    fn effective_chunk_bytes_matches_chunking() {
+        let cfg = test_config(&[("CHUNK_DATA_BYTES", "5000")]);
+        let request_id = Uuid::new_v4().to_string();
+        let max_total_bytes = cfg.max_body_bytes + cfg.max_meta_bytes;
+        let expected =
+            resolve_chunk_size(&request_id, None, None, cfg.chunk_data_bytes, max_total_bytes)
+                .unwrap();
+        assert_eq!(effective_chunk_bytes(&cfg), Some(expected));
+        assert!(expected < cfg.chunk_data_bytes);
+
+        let cfg = test_config(&[("CHUNK_DATA_BYTES", "100")]);
+        assert_eq!(effective_chunk_bytes(&cfg), Some(100));
+    }
+
+    async fn captured_query_string(state: &AppState, uuid: &str) -> String {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hook/test?token=secret")
+            .body(Body::from("{}"))
+            .unwrap();
+        hook(State(state.clone()), Path(uuid.to_string()), request)
+            .await
+            .unwrap();
+        let (_, stored) = history_list(&state.db, uuid, None, 1).unwrap().remove(0);
+        stored.meta.query_string
+    }
+
+    #[tokio::test]
+    async fn query_string_dropped_when_disabled() {
+        let (state, _queue_db) = test_state(&[
+            ("HISTORY_ENABLED", "true"),
+            ("FORWARD_QUERY_STRING", "false"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        assert_eq!(captured_query_string(&state, &uuid).await, "");
+
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                forward_query_string: Some(true),
+                ..Default::default()
+            },
+        );
+        assert_eq!(captured_query_string(&state, &uuid).await, "token=secret");
+    }
+
+    fn keyed_request(header: &str, key: Option<&str>) -> Request {
+        let mut builder = Request::builder().method("POST").uri("/hook/test");
+        if let Some(key) = key {
+            builder = builder.header(header, key);
+        }
+        builder.body(Body::from(r#"{"type":"charge.succeeded"}"#)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn repeated_idempotency_key_is_not_relayed() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            let request = keyed_request("Idempotency-Key", Some("evt_1"));
+            let response = hook(State(state.clone()), Path(uuid.clone()), request)
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        assert_eq!(statuses, vec![StatusCode::ACCEPTED, StatusCode::OK, StatusCode::OK]);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+
+        // Keys are scoped per subscription.
+        let other = store_subscription(&state, SubscriptionOptions::default());
+        let request = keyed_request("Idempotency-Key", Some("evt_1"));
+        let response = hook(State(state), Path(other), request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn hooks_without_idempotency_key_are_always_relayed() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                idempotency_header: Some("X-GitHub-Delivery".to_string()),
+                ..Default::default()
+            },
+        );
+        // The default header is ignored once a custom one is configured.
+        for request in [
+            keyed_request("Idempotency-Key", Some("evt_1")),
+            keyed_request("Idempotency-Key", Some("evt_1")),
+            keyed_request("X-GitHub-Delivery", None),
+            keyed_request("X-GitHub-Delivery", None),
+        ] {
+            let response = hook(State(state.clone()), Path(uuid.clone()), request)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+        assert_eq!(pending_records(&queue_db).len(), 4);
+
+        let (state, queue_db) = test_state(&[("DEDUP_WINDOW_SECS", "0")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        for _ in 0..2 {
+            let request = keyed_request("Idempotency-Key", Some("evt_1"));
+            hook(State(state.clone()), Path(uuid.clone()), request)
+                .await
+                .unwrap();
+        }
+        assert_eq!(pending_records(&queue_db).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn github_signature_is_checked_before_enqueueing() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                verification: Some(Verification::Github {
+                    secret: "It's a Secret to Everybody".to_string(),
+                }),
+                ..Default::default()
+            },
+        );
+        let signed = |body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test")
+                .header(
+                    "X-Hub-Signature-256",
+                    "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+                )
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let forged = hook(State(state.clone()), Path(uuid.clone()), signed("Hello, World?")).await;
+        assert!(matches!(forged, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+        let unsigned = hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", "Hello, World!"),
+        )
+        .await;
+        assert!(matches!(unsigned, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+        assert!(pending_records(&queue_db).is_empty());
+
+        let keyed = |body| {
+            let mut request = signed(body);
+            let key = HeaderValue::from_static("evt_1");
+            request.headers_mut().insert("Idempotency-Key", key);
+            request
+        };
+        let response = hook(State(state.clone()), Path(uuid.clone()), keyed("Hello, World!"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+
+        // A seen key is only acknowledged for a request that verifies.
+        let replayed = hook(State(state.clone()), Path(uuid.clone()), keyed("Hello, World?")).await;
+        assert!(matches!(replayed, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+        let response = hook(State(state), Path(uuid), keyed("Hello, World!"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn client_cert_subject_is_forwarded_when_configured() {
+        let (state, _queue_db) = test_state(&[
+            ("HISTORY_ENABLED", "true"),
+            ("CLIENT_CERT_HEADER", "X-Client-Cert-Subject"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .header("X-Client-Cert-Subject", "CN=billing.example.com,O=Example")
+            .body(Body::from("{}"))
+            .unwrap();
+        hook(State(state.clone()), Path(uuid.clone()), request)
+            .await
+            .unwrap();
+
+        let (_, stored) = history_list(&state.db, &uuid, None, 1).unwrap().remove(0);
+        assert_eq!(
+            stored.meta.client_cert_subject.as_deref(),
+            Some("CN=billing.example.com,O=Example")
+        );
+        let meta = serde_json::to_value(&stored.meta).unwrap();
+        assert_eq!(meta["client_cert_subject"], "CN=billing.example.com,O=Example");
+    }
+
+    #[tokio::test]
+    async fn delete_token_is_hashed_at_rest() {
+        let (state, _queue_db) = test_state(&[("ALLOWED_PUSH_HOSTS", "example.com")]);
+        let Json(response) = subscribe(State(state.clone()), subscribe_request(&[]))
+            .await
+            .unwrap();
+        let stored = db_get(&state.db, &response.uuid).unwrap().unwrap();
+        assert_ne!(stored.delete_token, response.delete_token);
+        assert!(!stored.delete_token.contains(&response.delete_token));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-delete-token",
+            HeaderValue::from_str(&response.delete_token).unwrap(),
+        );
+        assert!(authorize_subscription(&state, &response.uuid, &headers).is_ok());
+        // The stored hash itself is not a valid token.
+        headers.insert(
+            "x-delete-token",
+            HeaderValue::from_str(&stored.delete_token).unwrap(),
+        );
+        assert!(authorize_subscription(&state, &response.uuid, &headers).is_err());
+    }
+
+    #[tokio::test]
+    async fn legacy_plaintext_token_is_rehashed_on_use() {
+        let (state, _queue_db) = test_state(&[]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        assert!(authorize_subscription(&state, &uuid, &headers).is_ok());
+
+        let stored = db_get(&state.db, &uuid).unwrap().unwrap();
+        assert_eq!(stored.delete_token, hash_delete_token(&uuid, "token"));
+        assert!(authorize_subscription(&state, &uuid, &headers).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rotate_token_invalidates_old_token() {
+        let (state, _queue_db) = test_state(&[]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let token_headers = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-delete-token", HeaderValue::from_str(token).unwrap());
+            headers
+        };
+
+        let Json(response) =
+            rotate_token(State(state.clone()), Path(uuid.clone()), token_headers("token"))
+                .await
+                .unwrap();
+        assert_eq!(response.delete_token.len(), 32);
+
+        let old = token_headers("token");
+        let old = rotate_token(State(state.clone()), Path(uuid.clone()), old).await;
+        assert!(matches!(old, Err(err) if err.status == StatusCode::FORBIDDEN));
+        let status = unsubscribe(
+            State(state.clone()),
+            Path(uuid.clone()),
+            token_headers(&response.delete_token),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_checks_delete_token() {
+        let (state, _queue_db) = test_state(&[]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("tokem"));
+        let wrong = unsubscribe(State(state.clone()), Path(uuid.clone()), headers).await;
+        assert!(matches!(wrong, Err(err) if err.status == StatusCode::FORBIDDEN));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        let status = unsubscribe(State(state.clone()), Path(uuid.clone()), headers)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(db_get(&state.db, &uuid).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_require_configured_token() {
+        let (state, _queue_db) = test_state(&[]);
+        let hidden = clear_dead_letters(State(state), HeaderMap::new()).await;
+        assert!(matches!(hidden, Err(err) if err.status == StatusCode::NOT_FOUND));
+
+        let (state, _queue_db) = test_state(&[("ADMIN_TOKEN", "s3cret")]);
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer wrong"));
+        let denied = clear_dead_letters(State(state.clone()), headers).await;
+        assert!(matches!(denied, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer s3cret"));
+        let Json(response) = clear_dead_letters(State(state), headers).await.unwrap();
+        assert_eq!(response.purged, 0);
+    }
+
+    fn subscribe_request(labels: &[(&str, &str)]) -> Json<SubscribeRequest> {
+        Json(SubscribeRequest {
+            subscription: make_subscription("https://example.com/endpoint", 65, 16),
+            options: SubscriptionOptions {
+                labels: labels
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                ..Default::default()
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn subscribe_requires_owner_label_when_configured() {
+        let (state, _queue_db) = test_state(&[
+            ("ALLOWED_PUSH_HOSTS", "example.com"),
+            ("REQUIRE_OWNER_LABEL", "true"),
+        ]);
+        let missing = subscribe(State(state.clone()), subscribe_request(&[("team", "ops")])).await;
+        assert!(matches!(missing, Err(err) if err.status == StatusCode::BAD_REQUEST));
+        let blank = subscribe(State(state.clone()), subscribe_request(&[("owner", " ")])).await;
+        assert!(matches!(blank, Err(err) if err.status == StatusCode::BAD_REQUEST));
+
+        let request = subscribe_request(&[("owner", "alice")]);
+        let Json(response) = subscribe(State(state.clone()), request).await.unwrap();
+        let stored = db_get(&state.db, &response.uuid).unwrap().unwrap();
+        assert_eq!(stored.options.labels["owner"], "alice");
+
+        let (state, _queue_db) = test_state(&[("ALLOWED_PUSH_HOSTS", "example.com")]);
+        assert!(subscribe(State(state), subscribe_request(&[])).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn subscribe_is_undone_when_its_heartbeat_cannot_be_queued() {
+        let (state, _queue_db) = test_state(&[
+            ("ALLOWED_PUSH_HOSTS", "example.com"),
+            ("MAX_SUBSCRIPTIONS", "1"),
+        ]);
+        state.push_queue.shutdown(Duration::ZERO).await.unwrap();
+        let mut request = subscribe_request(&[]);
+        request.options.heartbeat = true;
+        let refused = subscribe(State(state.clone()), request).await;
+        assert!(matches!(refused, Err(err) if err.code == Some("shutting_down")));
+
+        // Its slot under MAX_SUBSCRIPTIONS is free again.
+        assert!(subscribe(State(state), subscribe_request(&[])).await.is_ok());
+    }
+
+    // Serialized metadata for a hook carrying typical load balancer headers.
+    async fn captured_meta(state: &AppState, uuid: &str) -> HookMeta {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .header("content-type", "application/json")
+            .header("x-github-event", "push")
+            .header("x-amzn-trace-id", "Root=1-67891233-abcdef012345678912345678")
+            .header("cf-ray", "8a1b2c3d4e5f6789-AMS")
+            .header("cf-connecting-ip", "203.0.113.7")
+            .header("cf-ipcountry", "NL")
+            .body(Body::from("{}"))
+            .unwrap();
+        hook(State(state.clone()), Path(uuid.to_string()), request)
+            .await
+            .unwrap();
+        history_list(&state.db, uuid, None, 1).unwrap().remove(0).1.meta
+    }
+
+    #[tokio::test]
+    async fn capture_headers_allowlist_shrinks_meta() {
+        let (state, _queue_db) = test_state(&[("HISTORY_ENABLED", "true")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let trimmed = captured_meta(&state, &uuid).await;
+        let mut kept: Vec<&str> = trimmed.headers.keys().map(String::as_str).collect();
+        kept.sort_unstable();
+        assert_eq!(kept, ["content-type", "x-github-event"]);
+        assert_eq!(trimmed.headers_omitted, 4);
+
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                capture_headers: Some(vec!["*".to_string()]),
+                ..Default::default()
+            },
+        );
+        let full = captured_meta(&state, &uuid).await;
+        assert_eq!(full.headers.len(), 6);
+        assert_eq!(full.headers_omitted, 0);
+        let size = |meta: &HookMeta| serde_json::to_vec(meta).unwrap().len();
+        assert!(size(&trimmed) + 100 < size(&full));
+    }
+
+    #[tokio::test]
+    async fn truncate_mode_cuts_body_mid_chunk() {
+        let (state, queue_db) = test_state(&[
+            ("HISTORY_ENABLED", "true"),
+            ("COMPRESS_OVER_BYTES", "0"),
+            ("MAX_BODY_BYTES", "2048"),
+            ("OVERSIZE_MODE", "truncate"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let original: Vec<u8> = (0..6000).map(|i| (i % 251) as u8).collect();
+        // The limit falls inside the first frame; the second is drained.
+        let frames = vec![
+            Ok::<_, std::io::Error>(Bytes::copy_from_slice(&original[..5000])),
+            Ok(Bytes::copy_from_slice(&original[5000..])),
+        ];
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .body(Body::from_stream(futures_util::stream::iter(frames)))
+            .unwrap();
+        let response = hook(State(state.clone()), Path(uuid.clone()), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let request_id = history_list(&state.db, &uuid, None, 1).unwrap().remove(0).1.request_id;
+        let payload = relayed_bytes(&queue_db, &request_id);
+        assert_eq!(&payload[..4], b"WHP1");
+        let meta_len = u32::from_be_bytes(payload[4..8].try_into().unwrap()) as usize;
+        let meta: HookMeta = serde_json::from_slice(&payload[8..8 + meta_len]).unwrap();
+        assert!(meta.truncated);
+        assert_eq!(meta.original_content_length, Some(6000));
+        let body = &payload[8 + meta_len..];
+        // The whole body budget, regardless of the metadata's size.
+        assert_eq!(body.len(), 2048);
+        assert_eq!(body, &original[..body.len()]);
+    }
+
+    #[tokio::test]
+    async fn reject_mode_still_refuses_oversized_body() {
+        let (state, _queue_db) = test_state(&[("MAX_BODY_BYTES", "2048")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let oversized = || {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test")
+                .body(Body::from("x".repeat(4096)))
+                .unwrap()
+        };
+        let result = hook(State(state.clone()), Path(uuid), oversized()).await;
+        assert!(matches!(result, Err(err) if err.status == StatusCode::PAYLOAD_TOO_LARGE));
+
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                oversize_mode: Some(OversizeMode::Truncate),
+                ..Default::default()
+            },
+        );
+        let response = hook(State(state), Path(uuid), oversized()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn repeated_oversized_hooks_are_refused_early() {
+        let (state, _queue_db) = test_state(&[
+            ("MAX_BODY_BYTES", "2048"),
+            ("OVERSIZE_STRIKES", "2"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let oversized = || {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test")
+                .header(CONTENT_LENGTH, "4096")
+                .body(Body::from("x".repeat(4096)))
+                .unwrap()
+        };
+        for _ in 0..2 {
+            let result = hook(State(state.clone()), Path(uuid.clone()), oversized()).await;
+            assert!(matches!(result, Err(err) if err.status == StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        assert_eq!(state.metrics.hooks_received.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        // Refused before the lookup, so it never counts as a received hook.
+        let result = hook(State(state.clone()), Path(uuid.clone()), oversized()).await;
+        assert!(matches!(result, Err(err) if err.message.contains("cooldown")));
+        assert_eq!(state.metrics.hooks_received.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        // Bodies within the limit still go through.
+        let response = hook(State(state), Path(uuid), hook_request("POST", "ok"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn slack_challenge_is_echoed_not_pushed() {
+        let (state, queue_db) = test_state(&[("HISTORY_ENABLED", "true")]);
+        let challenge = |push| Challenge {
+            provider: ChallengeProvider::Slack,
+            push,
+        };
+        let options = |push| SubscriptionOptions {
+            challenge: Some(challenge(push)),
+            ..Default::default()
+        };
+        let fixture = concat!(
+            r#"{"token":"Jhj5dZrVaK7ZwHHjRyZWjbDl","#,
+            r#""challenge":"3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P","#,
+            r#""type":"url_verification"}"#,
+        );
+
+        let uuid = store_subscription(&state, options(false));
+        let response = hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", fixture))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+        assert_eq!(&body[..], b"3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P");
+        assert!(pending_records(&queue_db).is_empty());
+
+        // Ordinary events still relay.
+        let event = r#"{"type":"event_callback","event":{"type":"app_mention"}}"#;
+        let response = hook(State(state.clone()), Path(uuid), hook_request("POST", event))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+
+        let uuid = store_subscription(&state, options(true));
+        let response = hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", fixture))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(pending_records(&queue_db).len(), 2);
+        let stored = history_list(&state.db, &uuid, None, 1).unwrap().remove(0).1;
+        assert!(stored.meta.verification);
+    }
+
+    #[tokio::test]
+    async fn query_challenge_is_echoed() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                challenge: Some(Challenge {
+                    provider: ChallengeProvider::Query { param: None },
+                    push: false,
+                }),
+                ..Default::default()
+            },
+        );
+        let request = Request::builder()
+            .method("GET")
+            .uri("/hook/test?hub.mode=subscribe&hub.challenge=1158201444&hub.verify_token=t")
+            .body(Body::empty())
+            .unwrap();
+        let response = hook(State(state), Path(uuid), request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+        assert_eq!(&body[..], b"1158201444");
+        assert!(pending_records(&queue_db).is_empty());
+    }
+
+    #[tokio::test]
+    async fn last_chunk_carries_payload_digest() {
+        let (state, queue_db) = test_state(&[
+            ("HISTORY_ENABLED", "true"),
+            ("CHUNK_DATA_BYTES", "64"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", r#"{"event":"deploy.finished","ok":true}"#),
+        )
+        .await
+        .unwrap();
+        let request_id = history_list(&state.db, &uuid, None, 1).unwrap().remove(0).1.request_id;
+
+        let envelopes: Vec<serde_json::Value> = pending_records(&queue_db)
+            .into_iter()
+            .map(|(_, payload)| serde_json::from_slice(&payload).unwrap())
+            .collect();
+        assert!(envelopes.len() > 1);
+        let (last, rest) = envelopes.split_last().unwrap();
+        assert!(rest.iter().all(|envelope| envelope.get("payload_sha256").is_none()));
+        assert_eq!(last["version"], ENVELOPE_VERSION);
+        for envelope in &envelopes {
+            let chunk = decode(envelope["data"].as_str().unwrap()).unwrap();
+            assert_eq!(envelope["crc32"], crc32fast::hash(&chunk));
+        }
+
+        let reassembled = relayed_bytes(&queue_db, &request_id);
+        assert_eq!(last["payload_len"], reassembled.len());
+        assert_eq!(last["payload_sha256"], hex::encode(Sha256::digest(&reassembled)));
+    }
+
+    #[tokio::test]
+    async fn hook_auth_token_requires_bearer_header() {
+        let (state, _queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                hook_auth_token: Some("s3cret".to_string()),
+                ..Default::default()
+            },
+        );
+        let with_auth = |value: Option<&'static str>| {
+            let mut request = hook_request("POST", "{}");
+            if let Some(value) = value {
+                request
+                    .headers_mut()
+                    .insert(AUTHORIZATION, HeaderValue::from_static(value));
+            }
+            request
+        };
+
+        for value in [None, Some("Bearer wrong"), Some("s3cret")] {
+            let result = hook(State(state.clone()), Path(uuid.clone()), with_auth(value)).await;
+            assert!(matches!(result, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+        }
+        let keyed = |value| {
+            let mut request = with_auth(value);
+            let key = HeaderValue::from_static("evt_1");
+            request.headers_mut().insert("Idempotency-Key", key);
+            request
+        };
+        let response = hook(State(state.clone()), Path(uuid.clone()), keyed(Some("Bearer s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        // Replaying a seen key doesn't get past the token check.
+        let replayed = hook(State(state), Path(uuid), keyed(None)).await;
+        assert!(matches!(replayed, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn webhook_age_checks_date_header() {
+        let cfg = test_config(&[("MAX_WEBHOOK_AGE_SECONDS", "300")]);
+        let now = httpdate::parse_http_date("Tue, 15 Nov 1994 08:12:31 GMT").unwrap();
+        let dated = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(DATE, HeaderValue::from_static(value));
+            headers
+        };
+
+        // Fresh, within skew of the limit, and a legacy RFC 850 date.
+        assert!(check_webhook_age(&dated("Tue, 15 Nov 1994 08:10:31 GMT"), &cfg, now).is_ok());
+        assert!(check_webhook_age(&dated("Tue, 15 Nov 1994 08:06:31 GMT"), &cfg, now).is_ok());
+        assert!(check_webhook_age(&dated("Tuesday, 15-Nov-94 08:12:00 GMT"), &cfg, now).is_ok());
+        assert!(check_webhook_age(&HeaderMap::new(), &cfg, now).is_ok());
+
+        let stale = check_webhook_age(&dated("Tue, 15 Nov 1994 08:00:00 GMT"), &cfg, now);
+        assert!(matches!(stale, Err(err) if err.status == StatusCode::BAD_REQUEST));
+        let future = check_webhook_age(&dated("Tue, 15 Nov 1994 09:00:00 GMT"), &cfg, now);
+        assert!(future.is_err());
+        assert!(check_webhook_age(&dated("yesterday"), &cfg, now).is_err());
+
+        let disabled = test_config(&[]);
+        assert!(check_webhook_age(&dated("Tue, 15 Nov 1994 08:00:00 GMT"), &disabled, now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn hook_rejects_sources_outside_allowlist() {
+        let (state, _queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                allowed_source_cidrs: vec![
+                    "203.0.113.0/24".to_string(),
+                    "2001:db8::/32".to_string(),
+                ],
+                ..Default::default()
+            },
+        );
+        let from = |peer: &str| {
+            let mut request = hook_request("POST", "{}");
+            let addr = SocketAddr::new(peer.parse().unwrap(), 443);
+            request.extensions_mut().insert(ConnectInfo(addr));
+            request
+        };
+
+        for peer in ["203.0.113.50", "2001:db8::5"] {
+            let response = hook(State(state.clone()), Path(uuid.clone()), from(peer))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+        let denied = hook(State(state), Path(uuid), from("198.51.100.1")).await;
+        assert!(matches!(denied, Err(err) if err.status == StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn multipart_body_is_summarized() {
+        use crate::multipart::tests::{fixture, BOUNDARY};
+
+        let (state, queue_db) = test_state(&[
+            ("HISTORY_ENABLED", "true"),
+            ("COMPRESS_OVER_BYTES", "0"),
+            ("MAX_BODY_BYTES", "2048"),
+            ("MULTIPART_MODE", "summarize"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let multipart = || {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test")
+                .header(CONTENT_TYPE, format!("multipart/form-data; boundary={BOUNDARY}"))
+                .body(Body::from(fixture()))
+                .unwrap()
+        };
+        // The raw body is over the limit; its summary is not.
+        assert!(fixture().len() > 2048);
+        let response = hook(State(state.clone()), Path(uuid.clone()), multipart())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let request_id = history_list(&state.db, &uuid, None, 1).unwrap().remove(0).1.request_id;
+        let payload = relayed_bytes(&queue_db, &request_id);
+        let meta_len = u32::from_be_bytes(payload[4..8].try_into().unwrap()) as usize;
+        assert_eq!(payload.len(), 8 + meta_len);
+        let meta: HookMeta = serde_json::from_slice(&payload[8..]).unwrap();
+        let form = meta.form.unwrap();
+        assert_eq!(form.fields[0].value, "Invoice #42");
+        assert_eq!(form.files[0].filename, "scan.png");
+        assert_eq!(form.files[0].size, 8 + 4096);
+
+        // Raw mode keeps today's limit.
+        let (state, _queue_db) = test_state(&[("MAX_BODY_BYTES", "2048")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let result = hook(State(state), Path(uuid), multipart()).await;
+        assert!(matches!(result, Err(err) if err.status == StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn coalesced_hooks_share_a_push_topic() {
+        let (state, queue_db) = test_state(&[]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                coalesce: Some(Coalesce {
+                    header: Some("x-alert-name".to_string()),
+                    window_secs: None,
+                }),
+                ..Default::default()
+            },
+        );
+        for (alert, body) in [("cpu-high", "92%"), ("cpu-high", "97%"), ("disk-full", "99%")] {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/hook/test")
+                .header("x-alert-name", alert)
+                .body(Body::from(body))
+                .unwrap();
+            hook(State(state.clone()), Path(uuid.clone()), request)
+                .await
+                .unwrap();
+        }
+
+        let topics: Vec<String> = pending_records(&queue_db)
+            .into_iter()
+            .map(|(_, payload)| {
+                let envelope: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+                envelope["topic"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(topics.len(), 3);
+        assert_eq!(topics[0], topics[1]);
+        assert_ne!(topics[0], topics[2]);
+        assert_eq!(topics[0].len(), 32);
+    }
+
+    #[tokio::test]
+    async fn hook_lookup_requires_the_instance_prefix() {
+        let (state, _queue_db) = test_state(&[("UUID_PREFIX", "eu-")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        assert!(uuid.starts_with("eu-"));
+        let response = hook(State(state.clone()), Path(uuid), hook_request("POST", "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        // Minted before the prefix was set, or by another instance.
+        let stored = db_get(&state.db, &store_subscription(&state, Default::default()))
+            .unwrap()
+            .unwrap();
+        db_put(&state.db, "a1b2c3d4e5f6", &stored).unwrap();
+        let result = hook(
+            State(state),
+            Path("a1b2c3d4e5f6".to_string()),
+            hook_request("POST", "{}"),
+        )
+        .await;
+        assert!(matches!(result, Err(err) if err.status == StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn serialized_hooks_do_not_interleave_chunks() {
+        // A streamed hook whose body arrives when the test sends it.
+        let streamed = |len: usize| {
+            let (sender, receiver) = tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+            let stream = futures_util::stream::unfold(receiver, |mut receiver| async {
+                receiver.recv().await.map(|item| (item, receiver))
+            });
+            let request = Request::builder()
+                .method("POST")
+                .uri("/hook/test")
+                .header(CONTENT_LENGTH, len)
+                .body(Body::from_stream(stream))
+                .unwrap();
+            (sender, request)
+        };
+        let (state, queue_db) = test_state(&[
+            ("COMPRESS_OVER_BYTES", "0"),
+            ("CHUNK_DATA_BYTES", "256"),
+            ("SERIALIZE_HOOKS", "true"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let settle = || tokio::time::sleep(Duration::from_millis(50));
+
+        let (first_body, first) = streamed(2000);
+        let first = tokio::spawn(hook(State(state.clone()), Path(uuid.clone()), first));
+        first_body.send(Ok(Bytes::from(vec![b'a'; 1000]))).await.unwrap();
+        settle().await;
+
+        // Arrives whole while the first is still mid-body.
+        let (second_body, second) = streamed(2000);
+        let second = tokio::spawn(hook(State(state.clone()), Path(uuid.clone()), second));
+        second_body.send(Ok(Bytes::from(vec![b'b'; 2000]))).await.unwrap();
+        drop(second_body);
+        settle().await;
+        assert!(!second.is_finished());
+
+        first_body.send(Ok(Bytes::from(vec![b'a'; 1000]))).await.unwrap();
+        drop(first_body);
+        first.await.unwrap().unwrap();
+        second.await.unwrap().unwrap();
+
+        let request_ids: Vec<String> = pending_records(&queue_db)
+            .iter()
+            .map(|(_, payload)| {
+                let envelope: serde_json::Value = serde_json::from_slice(payload).unwrap();
+                envelope["request_id"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert!(request_ids.len() > 4);
+        let switches = request_ids.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        assert_eq!(switches, 1, "{request_ids:?}");
+    }
+
+    #[tokio::test]
+    async fn hook_access_log_carries_request_fields() {
+        let (logs, _guard) = capture_json_logs();
+        let (state, _queue_db) = test_state(&[("MAX_BODY_BYTES", "8")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", "small"))
+            .await
+            .unwrap();
+        let err = hook(State(state), Path(uuid.clone()), hook_request("PUT", "far too large"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let lines = logged_lines(&logs, HOOK_ACCESS);
+        assert_eq!(lines.len(), 2);
+        for (line, method, status) in [(&lines[0], "POST", 202), (&lines[1], "PUT", 413)] {
+            let span = &line["span"];
+            assert_eq!(span["uuid"], uuid.as_str());
+            assert_eq!(span["method"], method);
+            assert!(span["request_id"].is_string() && span["source_ip"].is_string());
+            assert_eq!(line["fields"]["status"], status);
+            assert!(line["fields"]["duration_ms"].is_u64());
+        }
+        assert_eq!(lines[0]["fields"]["outcome"], "ok");
+        assert_eq!(lines[0]["span"]["body_bytes"], 5);
+        assert_eq!(lines[0]["span"]["chunks"], 1);
+        assert_eq!(lines[1]["fields"]["outcome"], "payload_too_large");
+        assert!(lines[1]["span"]["body_bytes"].as_u64().unwrap() > 8);
+    }
+
+    #[tokio::test]
+    async fn status_shows_last_push_error_until_a_push_succeeds() {
+        let sync_request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test?delivery=sync")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+        let status = |state: &AppState, uuid: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-delete-token", HeaderValue::from_static("token"));
+            subscription_status(State(state.clone()), Path(uuid.to_string()), headers)
+        };
+        let (mut state, _queue_db) = test_state(&[]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let Json(fresh) = status(&state, &uuid).await.unwrap();
+        assert!(fresh.last_error.is_none() && fresh.last_error_at.is_none());
+
+        state.push = Arc::new(FakePush::Reject);
+        hook(State(state.clone()), Path(uuid.clone()), sync_request())
+            .await
+            .unwrap_err();
+        let Json(failed) = status(&state, &uuid).await.unwrap();
+        assert_eq!(failed.last_error.as_deref(), Some("subscription expired"));
+        assert!(failed.last_error_at.is_some());
+
+        state.push = Arc::new(FakePush::Accept);
+        hook(State(state.clone()), Path(uuid.clone()), sync_request())
+            .await
+            .unwrap();
+        let Json(recovered) = status(&state, &uuid).await.unwrap();
+        assert!(recovered.last_error.is_none() && recovered.last_error_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn sync_delivery_reports_push_outcome() {
+        let sync_request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test?delivery=sync")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+        let (mut state, queue_db) = test_state(&[("SYNC_DELIVERY_TIMEOUT_MS", "50")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let response = hook(State(state.clone()), Path(uuid.clone()), sync_request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(pending_records(&queue_db).is_empty());
+
+        state.push = Arc::new(FakePush::Reject);
+        let result = hook(State(state.clone()), Path(uuid.clone()), sync_request()).await;
+        assert!(matches!(&result, Err(err) if err.status == StatusCode::BAD_GATEWAY
+            && err.message.contains("subscription expired")));
+
+        state.push = Arc::new(FakePush::Hang);
+        let result = hook(State(state.clone()), Path(uuid.clone()), sync_request()).await;
+        assert!(matches!(result, Err(err) if err.status == StatusCode::GATEWAY_TIMEOUT));
+
+        // The async default still queues even though the push service hangs.
+        let response = hook(State(state), Path(uuid), hook_request("POST", "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn push_ttl_comes_from_header_capped_at_server_max() {
+        let (state, queue_db) =
+            test_state(&[("PUSH_TTL_HEADER", "X-Expires-In"), ("PUSH_TTL_SECS", "120")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        for expires_in in [Some("30"), Some("3600"), Some("soon"), None] {
+            let mut builder = Request::builder().method("POST").uri("/hook/test");
+            if let Some(value) = expires_in {
+                builder = builder.header("x-expires-in", value);
+            }
+            let request = builder.body(Body::from("{}")).unwrap();
+            hook(State(state.clone()), Path(uuid.clone()), request)
+                .await
+                .unwrap();
+        }
+        assert_eq!(pending_ttls(&queue_db), vec![Some(30), Some(120), None, None]);
+    }
+
+    #[tokio::test]
+    async fn junk_paths_are_refused_before_lookup() {
+        let (state, _queue_db) = test_state(&[("RATE_LIMIT_PER_MINUTE", "1")]);
+        for path in ["favicon.ico", "robots.txt", ".well-known", "health"] {
+            let result = hook(
+                State(state.clone()),
+                Path(path.to_string()),
+                hook_request("GET", ""),
+            )
+            .await;
+            assert!(matches!(result, Err(err) if err.status == StatusCode::NOT_FOUND));
+        }
+        let received = state.metrics.hooks_received.load(std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(received, 0);
+    }
+
+    #[tokio::test]
+    async fn closed_instance_serves_only_allowlisted_ids() {
+        let (mut state, _queue_db) = test_state(&[("ALLOWED_PUSH_HOSTS", "example.com")]);
+        let unlisted = store_subscription(&state, SubscriptionOptions::default());
+        let allowlist = r#"{"team-alerts": {
+            "endpoint": "https://example.com/endpoint",
+            "keys": {"p256dh": "BAEB", "auth": "AgIC"}
+        }}"#;
+        let result = crate::allowlist::load_allowlist(&state.db, &state.cfg(), allowlist);
+        assert!(result.is_err(), "p256dh must be a full P-256 key");
+
+        let keys = make_subscription("https://example.com/endpoint", 65, 16).keys;
+        let allowlist = serde_json::json!({
+            "team-alerts": {
+                "endpoint": "https://example.com/endpoint",
+                "keys": {"p256dh": keys.p256dh, "auth": keys.auth},
+            }
+        });
+        let ids =
+            crate::allowlist::load_allowlist(&state.db, &state.cfg(), &allowlist.to_string())
+                .unwrap();
+        state.uuid_allowlist = Some(Arc::new(ids));
+
+        let response = hook(
+            State(state.clone()),
+            Path("team-alerts".to_string()),
+            hook_request("POST", "{}"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let result = hook(State(state.clone()), Path(unlisted), hook_request("POST", "{}")).await;
+        assert!(matches!(result, Err(err) if err.status == StatusCode::NOT_FOUND));
+
+        let result = subscribe(State(state), subscribe_request(&[])).await;
+        assert!(matches!(result, Err(err) if err.status == StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn filters_reduce_json_and_pass_other_bodies_through() {
+        let (state, _queue_db) = test_state(&[("HISTORY_ENABLED", "true")]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                filters: vec![
+                    "/repository/full_name".to_string(),
+                    "/commits/*/message".to_string(),
+                ],
+                ..Default::default()
+            },
+        );
+        let push = r#"{"repository":{"full_name":"octo/hello","size":9},
+            "commits":[{"message":"fix","id":"1"},{"message":"docs","id":"2"}]}"#;
+        for body in [push, r#"{"repository": {"full_name": "#] {
+            hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", body))
+                .await
+                .unwrap();
+        }
+
+        let entries = history_list(&state.db, &uuid, None, 2).unwrap();
+        let (malformed, filtered) = (&entries[0].1, &entries[1].1);
+        assert!(filtered.meta.filtered);
+        let reduced: serde_json::Value =
+            serde_json::from_slice(&decode(&filtered.body).unwrap()).unwrap();
+        assert_eq!(
+            reduced,
+            serde_json::json!({
+                "/repository/full_name": "octo/hello",
+                "/commits/*/message": ["fix", "docs"]
+            })
+        );
+        assert!(!malformed.meta.filtered);
+        assert_eq!(decode(&malformed.body).unwrap(), br#"{"repository": {"full_name": "#);
+    }
+
+    #[tokio::test]
+    async fn header_heavy_hooks_keep_the_full_body_budget() {
+        let (state, queue_db) = test_state(&[
+            ("MAX_BODY_BYTES", "2048"),
+            ("MAX_META_BYTES", "8192"),
+            ("COMPRESS_OVER_BYTES", "0"),
+        ]);
+        let uuid = store_subscription(
+            &state,
+            SubscriptionOptions {
+                capture_headers: Some(vec!["*".to_string()]),
+                ..Default::default()
+            },
+        );
+        let request = |value_len: usize| {
+            let mut builder = Request::builder().method("POST").uri("/hook/test");
+            for i in 0..50 {
+                builder = builder.header(format!("x-header-{i}"), "v".repeat(value_len));
+            }
+            builder.body(Body::from("x".repeat(2048))).unwrap()
+        };
+
+        // About 5 KB of metadata no longer eats into a full-size body.
+        let response = hook(State(state.clone()), Path(uuid.clone()), request(80))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(!pending_records(&queue_db).is_empty());
+
+        // Past MAX_META_BYTES it is the headers that are refused.
+        let err = hook(State(state), Path(uuid), request(200)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        assert_eq!(err.message, "too many headers");
+    }
+
+    #[tokio::test]
+    async fn hook_responses_carry_rate_limit_headers() {
+        let (state, _queue_db) = test_state(&[("RATE_LIMIT_PER_MINUTE", "1")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let response = hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", "a"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-ratelimit-limit"], "1");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        assert_eq!(response.headers()["x-ratelimit-reset"], "60");
+        assert!(response.headers().get("retry-after").is_none());
+
+        let err = hook(State(state), Path(uuid), hook_request("POST", "b"))
+            .await
+            .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after));
+    }
+
+    #[tokio::test]
+    async fn first_chunk_announces_total_bytes() {
+        let (state, queue_db) = test_state(&[
+            ("COMPRESS_OVER_BYTES", "0"),
+            ("CHUNK_DATA_BYTES", "256"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let body = vec![b'x'; 1500];
+        // Streamed (Content-Length known) and buffered (chunked encoding).
+        let streamed = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .header(CONTENT_LENGTH, body.len())
+            .body(Body::from(body.clone()))
+            .unwrap();
+        let buffered = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .body(Body::from(body))
+            .unwrap();
+        let mut seen = 0;
+        for request in [streamed, buffered] {
+            hook(State(state.clone()), Path(uuid.clone()), request).await.unwrap();
+            let envelopes: Vec<serde_json::Value> = pending_records(&queue_db)
+                .into_iter()
+                .skip(seen)
+                .map(|(_, payload)| serde_json::from_slice(&payload).unwrap())
+                .collect();
+            seen += envelopes.len();
+            let (first, rest) = envelopes.split_first().unwrap();
+            assert!(rest.iter().all(|envelope| envelope.get("total_bytes").is_none()));
+            assert_eq!(first["total_bytes"], envelopes.last().unwrap()["payload_len"]);
+            let written: usize = envelopes
+                .iter()
+                .map(|envelope| decode(envelope["data"].as_str().unwrap()).unwrap().len())
+                .sum();
+            assert_eq!(first["total_bytes"], written);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_emits_relayed_hooks() {
+        use futures_util::StreamExt;
+
+        let (state, _queue_db) = test_state(&[("LIVE_STREAMS_PER_UUID", "1")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        let response = stream(State(state.clone()), Path(uuid.clone()), headers.clone())
+            .await
+            .unwrap();
+        let second = stream(State(state.clone()), Path(uuid.clone()), headers).await;
+        assert!(matches!(second, Err(err) if err.status == StatusCode::TOO_MANY_REQUESTS));
+
+        let ack = hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", r#"{"live":true}"#),
+        )
+        .await
+        .unwrap();
+        let ack: serde_json::Value = serde_json::from_slice(&body_bytes(ack).await).unwrap();
+
+        let mut frames = response.into_body().into_data_stream();
+        let frame = frames.next().await.unwrap().unwrap();
+        let frame = String::from_utf8(frame.to_vec()).unwrap();
+        assert!(frame.starts_with("event: hook\n"), "{frame}");
+        let data = frame
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(event["request_id"], ack["request_id"]);
+        assert_eq!(decode(event["body"].as_str().unwrap()).unwrap(), br#"{"live":true}"#);
+
+        // A disconnect releases the watcher slot.
+        drop(frames);
+        assert!(!state.live_streams.is_watched(&uuid));
+    }
+
+    #[tokio::test]
+    async fn poll_waits_for_the_next_hook() {
+        let (state, _queue_db) = test_state(&[
+            ("HISTORY_ENABLED", "true"),
+            ("LONG_POLL_TIMEOUT_MS", "50"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        let poll_after = |after: Option<&str>| {
+            poll(
+                State(state.clone()),
+                Path(uuid.clone()),
+                Query(PollQuery {
+                    after: after.map(str::to_string),
+                }),
+                headers.clone(),
+            )
+        };
+
+        let waiting = tokio::spawn(poll_after(None));
+        tokio::task::yield_now().await;
+        let ack = hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", r#"{"n":1}"#),
+        )
+        .await
+        .unwrap();
+        let ack: serde_json::Value = serde_json::from_slice(&body_bytes(ack).await).unwrap();
+        let response = waiting.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let entry: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(entry["request_id"], ack["request_id"]);
+        assert_eq!(decode(entry["body"].as_str().unwrap()).unwrap(), br#"{"n":1}"#);
+
+        let first = ack["request_id"].as_str().unwrap().to_string();
+        let idle = poll_after(Some(&first)).await.unwrap();
+        assert_eq!(idle.status(), StatusCode::NO_CONTENT);
+
+        // Hooks stored before the poll are returned without waiting.
+        hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", r#"{"n":2}"#),
+        )
+        .await
+        .unwrap();
+        let next = poll_after(Some(&first)).await.unwrap();
+        let entry: serde_json::Value = serde_json::from_slice(&body_bytes(next).await).unwrap();
+        assert_eq!(decode(entry["body"].as_str().unwrap()).unwrap(), br#"{"n":2}"#);
+
+        let unknown = poll_after(Some("missing")).await;
+        assert!(matches!(unknown, Err(err) if err.status == StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn x_push_ttl_overrides_the_default_within_limits() {
+        let (state, queue_db) =
+            test_state(&[("PUSH_TTL_HEADER", "X-Expires-In"), ("PUSH_TTL_SECS", "120")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let cases = [
+            (Some("0"), None),
+            (Some("86400"), None),
+            (Some("99999999999"), None),
+            (Some("-5"), None),
+            (Some("3600"), Some("30")),
+            (Some("later"), Some("30")),
+        ];
+        for (push_ttl, expires_in) in cases {
+            let mut builder = Request::builder().method("POST").uri("/hook/test");
+            if let Some(value) = push_ttl {
+                builder = builder.header("x-push-ttl", value);
+            }
+            if let Some(value) = expires_in {
+                builder = builder.header("x-expires-in", value);
+            }
+            let request = builder.body(Body::from("{}")).unwrap();
+            hook(State(state.clone()), Path(uuid.clone()), request)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            pending_ttls(&queue_db),
+            vec![Some(0), Some(86400), Some(2_419_200), None, Some(3600), Some(30)]
+        );
+    }
+
+    #[tokio::test]
+    async fn hooks_that_overflow_the_queue_enqueue_no_chunks() {
+        let (state, queue_db) = test_state(&[
+            ("QUEUE_MAX_BYTES", "8000"),
+            ("CHUNK_DATA_BYTES", "1000"),
+            ("COMPRESS_OVER_BYTES", "0"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        let small = hook(
+            State(state.clone()),
+            Path(uuid.clone()),
+            hook_request("POST", "small"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(small.status(), StatusCode::ACCEPTED);
+        let queued = pending_records(&queue_db).len();
+
+        // About ten chunks, more than the queue has room for.
+        let body: &'static str = "x".repeat(10_000).leak();
+        let result = hook(State(state.clone()), Path(uuid), hook_request("POST", body)).await;
+        assert!(matches!(result, Err(err) if err.code == Some("queue_full")));
+        assert_eq!(pending_records(&queue_db).len(), queued);
+    }
+
+    #[tokio::test]
+    async fn hooks_refused_after_their_first_batch_leave_no_chunks() {
+        let (state, queue_db) = test_state(&[
+            ("QUEUE_MAX_BYTES", "350000"),
+            ("MAX_BODY_BYTES", "1000000"),
+            ("COMPRESS_OVER_BYTES", "0"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        // Its first batch fits; the envelopes after MAX_BATCH_BYTES don't.
+        let body: &'static str = "x".repeat(300_000).leak();
+        let request = hook_request("POST", body);
+        let result = hook(State(state.clone()), Path(uuid.clone()), request).await;
+        assert!(matches!(result, Err(err) if err.code == Some("queue_full")));
+        assert!(pending_records(&queue_db).is_empty());
+        assert_eq!(state.push_queue.stats().unwrap().queue_bytes, 0);
+
+        // The space is free again for a hook that fits.
+        let response = hook(State(state), Path(uuid), hook_request("POST", "small"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn streamed_hooks_that_come_up_short_leave_no_chunks() {
+        let (state, queue_db) = test_state(&[
+            ("MAX_BODY_BYTES", "1000000"),
+            ("COMPRESS_OVER_BYTES", "0"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        // Batches are queued while it streams; the body then ends early.
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hook/test")
+            .header(CONTENT_LENGTH, "700000")
+            .body(Body::from("x".repeat(600_000)))
+            .unwrap();
+        let result = hook(State(state.clone()), Path(uuid), request).await;
+        assert!(matches!(result, Err(err) if err.code == Some("content_length_mismatch")));
+        assert!(pending_records(&queue_db).is_empty());
+        assert_eq!(state.push_queue.stats().unwrap().queue_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn identical_hooks_are_relayed_once() {
+        let (state, queue_db) = test_state(&[("CONTENT_DEDUP", "true")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let mut statuses = Vec::new();
+        let hooks = [("POST", "same"), ("POST", "same"), ("POST", "new"), ("PUT", "same")];
+        for (method, body) in hooks {
+            let response = hook(
+                State(state.clone()),
+                Path(uuid.clone()),
+                hook_request(method, body),
+            )
+            .await
+            .unwrap();
+            statuses.push(response.status());
+        }
+        let accepted = StatusCode::ACCEPTED;
+        assert_eq!(statuses, vec![accepted, StatusCode::OK, accepted, accepted]);
+        assert_eq!(pending_records(&queue_db).len(), 3);
+
+        // Deleting the subscription forgets what it was sent.
+        let hash = content_hash("POST", "/hook/test", &b"same"[..]).unwrap();
+        assert!(state.content_dedup.check_and_insert(&uuid, hash).is_none());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        unsubscribe(State(state.clone()), Path(uuid.clone()), headers)
+            .await
+            .unwrap();
+        assert!(state.content_dedup.check_and_insert(&uuid, hash).is_some());
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_hooks_are_relayed_once() {
+        let sync_request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/hook/test?delivery=sync")
+                .body(Body::from("same"))
+                .unwrap()
+        };
+        let (mut state, queue_db) = test_state(&[
+            ("CONTENT_DEDUP", "true"),
+            ("SYNC_DELIVERY_TIMEOUT_MS", "300"),
+        ]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+        state.push = Arc::new(FakePush::Hang);
+
+        // The retry arrives while the first is still waiting on its push.
+        let first = tokio::spawn(hook(State(state.clone()), Path(uuid.clone()), sync_request()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let retry = hook(State(state.clone()), Path(uuid.clone()), sync_request())
+            .await
+            .unwrap();
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert!(!first.is_finished());
+
+        // The first times out, so the hash is released for the next retry.
+        let result = first.await.unwrap();
+        assert!(matches!(result, Err(err) if err.status == StatusCode::GATEWAY_TIMEOUT));
+        let response = hook(State(state), Path(uuid), hook_request("POST", "same"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(pending_records(&queue_db).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn full_subscriber_backlog_gets_429_until_unsubscribed() {
+        let (state, queue_db) = test_state(&[("QUEUE_MAX_BYTES_PER_UUID", "1")]);
+        let uuid = store_subscription(&state, SubscriptionOptions::default());
+
+        let first = hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", "one"))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::ACCEPTED);
+        let err = hook(State(state.clone()), Path(uuid.clone()), hook_request("POST", "two"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some("subscriber_backlog_full"));
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-delete-token", HeaderValue::from_static("token"));
+        unsubscribe(State(state.clone()), Path(uuid.clone()), headers)
+            .await
+            .unwrap();
+        assert!(pending_records(&queue_db).is_empty());
+    }
 }
@@ -0,0 +1,25 @@
+use crate::{
+    config::Config,
+    error::AppError,
+    models::{HeartbeatEnvelope, SubscriptionOptions, ENVELOPE_VERSION},
+    queue::DiskQueue,
+};
+
+// Opted-in subscriptions get a tiny push every HEARTBEAT_INTERVAL_MINUTES.
+pub fn heartbeat_enabled(cfg: &Config, options: &SubscriptionOptions) -> bool {
+    options.heartbeat && cfg.heartbeat_interval_minutes > 0
+}
+
+// Queue the recurring record; the queue reschedules it after each delivery.
+pub async fn schedule_heartbeat(queue: &DiskQueue, cfg: &Config, uuid: &str) -> Result<(), AppError> {
+    let every_secs = cfg.heartbeat_interval_minutes.saturating_mul(60);
+    let payload = serde_json::to_vec(&HeartbeatEnvelope {
+        version: ENVELOPE_VERSION,
+        heartbeat: true,
+    })?;
+    // A missed heartbeat is worthless once the next one is due.
+    let ttl_secs = u32::try_from(every_secs).unwrap_or(u32::MAX);
+    queue
+        .enqueue_recurring(uuid, payload, every_secs.saturating_mul(1000), ttl_secs)
+        .await
+}
@@ -0,0 +1,222 @@
+use redb::{Database, ReadableTable, TableDefinition, WriteTransaction};
+
+use crate::{error::AppError, models::StoredRequest};
+
+// Keyed by (uuid, seq) so one uuid's entries are contiguous and ordered.
+const HISTORY: TableDefinition<(&str, u64), &str> = TableDefinition::new("request_history");
+const HISTORY_META: TableDefinition<&str, u64> = TableDefinition::new("request_history_meta");
+
+const META_NEXT_SEQ: &str = "next_seq";
+
+pub fn init_history(db: &Database) -> Result<(), AppError> {
+    let write_txn = db.begin_write()?;
+    {
+        write_txn.open_table(HISTORY)?;
+        let mut meta = write_txn.open_table(HISTORY_META)?;
+        if meta.get(META_NEXT_SEQ)?.is_none() {
+            meta.insert(META_NEXT_SEQ, 0)?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Store a request and evict the oldest entries beyond `keep` for this uuid.
+pub fn history_put(
+    db: &Database,
+    uuid: &str,
+    request: &StoredRequest,
+    keep: usize,
+) -> Result<u64, AppError> {
+    let value = serde_json::to_string(request)?;
+    let write_txn = db.begin_write()?;
+    let seq = {
+        let mut table = write_txn.open_table(HISTORY)?;
+        let mut meta = write_txn.open_table(HISTORY_META)?;
+        let seq = meta
+            .get(META_NEXT_SEQ)?
+            .map(|value| value.value())
+            .unwrap_or(0);
+        table.insert((uuid, seq), value.as_str())?;
+        meta.insert(META_NEXT_SEQ, seq + 1)?;
+
+        let stored = table.range((uuid, 0)..=(uuid, u64::MAX))?.count();
+        let excess = stored.saturating_sub(keep);
+        if excess > 0 {
+            let mut evict = Vec::with_capacity(excess);
+            for entry in table.range((uuid, 0)..=(uuid, u64::MAX))?.take(excess) {
+                let (key, _) = entry?;
+                evict.push(key.value().1);
+            }
+            for old in evict {
+                table.remove((uuid, old))?;
+            }
+        }
+        seq
+    };
+    write_txn.commit()?;
+    Ok(seq)
+}
+
+// Newest-first page of entries strictly older than `before` (if given).
+pub fn history_list(
+    db: &Database,
+    uuid: &str,
+    before: Option<u64>,
+    limit: usize,
+) -> Result<Vec<(u64, StoredRequest)>, AppError> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(HISTORY)?;
+    let upper = match before {
+        Some(0) => return Ok(Vec::new()),
+        Some(before) => before - 1,
+        None => u64::MAX,
+    };
+
+    let mut out = Vec::new();
+    for entry in table.range((uuid, 0)..=(uuid, upper))?.rev().take(limit) {
+        let (key, value) = entry?;
+        let request: StoredRequest = serde_json::from_str(value.value())?;
+        out.push((key.value().1, request));
+    }
+    Ok(out)
+}
+
+// Look up a stored request by its request id.
+pub fn history_get(
+    db: &Database,
+    uuid: &str,
+    request_id: &str,
+) -> Result<Option<StoredRequest>, AppError> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(HISTORY)?;
+    for entry in table.range((uuid, 0)..=(uuid, u64::MAX))? {
+        let (_, value) = entry?;
+        let request: StoredRequest = serde_json::from_str(value.value())?;
+        if request.request_id == request_id {
+            return Ok(Some(request));
+        }
+    }
+    Ok(None)
+}
+
+// Seq of a stored request, so it can serve as a cursor.
+pub fn history_seq(db: &Database, uuid: &str, request_id: &str) -> Result<Option<u64>, AppError> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(HISTORY)?;
+    for entry in table.range((uuid, 0)..=(uuid, u64::MAX))? {
+        let (key, value) = entry?;
+        let request: StoredRequest = serde_json::from_str(value.value())?;
+        if request.request_id == request_id {
+            return Ok(Some(key.value().1));
+        }
+    }
+    Ok(None)
+}
+
+pub fn history_latest_seq(db: &Database, uuid: &str) -> Result<Option<u64>, AppError> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(HISTORY)?;
+    let last = table.range((uuid, 0)..=(uuid, u64::MAX))?.next_back();
+    match last {
+        Some(entry) => Ok(Some(entry?.0.value().1)),
+        None => Ok(None),
+    }
+}
+
+// Oldest entry newer than `after` (if given).
+pub fn history_next(
+    db: &Database,
+    uuid: &str,
+    after: Option<u64>,
+) -> Result<Option<(u64, StoredRequest)>, AppError> {
+    let lower = match after {
+        Some(u64::MAX) => return Ok(None),
+        Some(after) => after + 1,
+        None => 0,
+    };
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(HISTORY)?;
+    let first = table.range((uuid, lower)..=(uuid, u64::MAX))?.next();
+    match first {
+        Some(entry) => {
+            let (key, value) = entry?;
+            let request: StoredRequest = serde_json::from_str(value.value())?;
+            Ok(Some((key.value().1, request)))
+        }
+        None => Ok(None),
+    }
+}
+
+// Remove every entry for a uuid inside the caller's transaction.
+pub fn history_purge(write_txn: &WriteTransaction, uuid: &str) -> Result<(), AppError> {
+    let mut table = write_txn.open_table(HISTORY)?;
+    table.retain_in((uuid, 0)..=(uuid, u64::MAX), |_, _| false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::HookMeta;
+    use redb::backends::InMemoryBackend;
+    use std::collections::HashMap;
+
+    fn request(id: &str) -> StoredRequest {
+        StoredRequest {
+            request_id: id.to_string(),
+            meta: HookMeta {
+                timestamp: "2026-01-01T00:00:00Z".to_string(),
+                method: "POST".to_string(),
+                path: "/abc".to_string(),
+                query_string: String::new(),
+                content_type: None,
+                content_length: 0,
+                headers: HashMap::new(),
+                headers_omitted: 0,
+                truncated: false,
+                original_content_length: None,
+                form: None,
+                source_ip: "127.0.0.1".to_string(),
+                client_cert_subject: None,
+                filtered: false,
+                verification: false,
+            },
+            body: String::new(),
+        }
+    }
+
+    fn ids(entries: &[(u64, StoredRequest)]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|(_, request)| request.request_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn history_evicts_oldest_beyond_keep() {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_history(&db).unwrap();
+
+        for id in ["r1", "r2", "r3", "r4"] {
+            history_put(&db, "abc", &request(id), 3).unwrap();
+        }
+        history_put(&db, "other", &request("x1"), 3).unwrap();
+
+        let entries = history_list(&db, "abc", None, 10).unwrap();
+        assert_eq!(ids(&entries), vec!["r4", "r3", "r2"]);
+
+        let page = history_list(&db, "abc", None, 2).unwrap();
+        assert_eq!(ids(&page), vec!["r4", "r3"]);
+        let next = history_list(&db, "abc", Some(page[1].0), 2).unwrap();
+        assert_eq!(ids(&next), vec!["r2"]);
+
+        let write_txn = db.begin_write().unwrap();
+        history_purge(&write_txn, "abc").unwrap();
+        write_txn.commit().unwrap();
+        assert!(history_list(&db, "abc", None, 10).unwrap().is_empty());
+        assert_eq!(ids(&history_list(&db, "other", None, 10).unwrap()), vec!["x1"]);
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+// One async lock per subscription, so its hooks are enqueued one at a time
+// and their chunks never interleave. Entries live only while a hook holds or
+// waits for them.
+pub struct HookLocks {
+    enabled: bool,
+    inner: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+pub struct HookGuard<'a> {
+    locks: &'a HookLocks,
+    uuid: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl HookLocks {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Waits for earlier hooks to the same uuid; None when serialization is off.
+    pub async fn lock(&self, uuid: &str) -> Option<HookGuard<'_>> {
+        if !self.enabled {
+            return None;
+        }
+        let mutex = self
+            .inner
+            .lock()
+            .unwrap()
+            .entry(uuid.to_string())
+            .or_default()
+            .clone();
+        let guard = mutex.lock_owned().await;
+        Some(HookGuard {
+            locks: self,
+            uuid: uuid.to_string(),
+            guard: Some(guard),
+        })
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+}
+
+impl Drop for HookGuard<'_> {
+    fn drop(&mut self) {
+        // Release under the map lock, so no waiter can clone the entry
+        // between the count check and its removal.
+        let mut map = self.locks.inner.lock().unwrap();
+        drop(self.guard.take());
+        if map
+            .get(&self.uuid)
+            .is_some_and(|mutex| Arc::strong_count(mutex) == 1)
+        {
+            map.remove(&self.uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_are_dropped_with_the_last_holder() {
+        let locks = HookLocks::new(true);
+        let first = locks.lock("abc").await.unwrap();
+        let waiter = locks.lock("abc");
+        tokio::pin!(waiter);
+        // Held, so the second hook waits.
+        assert!(futures_util::poll!(waiter.as_mut()).is_pending());
+        drop(first);
+        let second = waiter.await.unwrap();
+        assert_eq!(locks.len(), 1);
+        drop(second);
+        assert_eq!(locks.len(), 0);
+
+        assert!(HookLocks::new(false).lock("abc").await.is_none());
+    }
+}
@@ -0,0 +1,97 @@
+use serde_json::Value;
+use tracing::warn;
+
+use crate::config::Config;
+
+// Why a body was not parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum JsonGuardError {
+    TooDeep,
+    TooManyElements,
+    Invalid,
+}
+
+// Parse a body for inspection, refusing pathological nesting or element
+// counts before serde_json allocates anything. Callers skip their
+// transformation on `None` and relay the body unchanged.
+pub fn parse_json_body(body: &[u8], cfg: &Config) -> Option<Value> {
+    match parse_guarded(body, cfg.max_json_depth, cfg.max_json_elements) {
+        Ok(value) => Some(value),
+        Err(JsonGuardError::Invalid) => None,
+        Err(err) => {
+            warn!("skipping body transform: {err:?}");
+            None
+        }
+    }
+}
+
+pub fn parse_guarded(
+    body: &[u8],
+    max_depth: usize,
+    max_elements: usize,
+) -> Result<Value, JsonGuardError> {
+    check_shape(body, max_depth, max_elements)?;
+    serde_json::from_slice(body).map_err(|_| JsonGuardError::Invalid)
+}
+
+// Single pass over the raw bytes: tracks container depth and counts values
+// (each container plus each separator) without recursing.
+fn check_shape(body: &[u8], max_depth: usize, max_elements: usize) -> Result<(), JsonGuardError> {
+    let mut depth = 0usize;
+    let mut elements = 1usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in body {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                elements += 1;
+                if depth > max_depth {
+                    return Err(JsonGuardError::TooDeep);
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            b',' => elements += 1,
+            _ => continue,
+        }
+        if elements > max_elements {
+            return Err(JsonGuardError::TooManyElements);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deeply_nested_json_hits_depth_guard() {
+        let nested = format!("{}1{}", "[".repeat(10_000), "]".repeat(10_000));
+        assert_eq!(
+            parse_guarded(nested.as_bytes(), 32, 10_000),
+            Err(JsonGuardError::TooDeep)
+        );
+
+        let shallow = format!("{}1{}", "[".repeat(32), "]".repeat(32));
+        assert!(parse_guarded(shallow.as_bytes(), 32, 10_000).is_ok());
+        // Brackets inside strings don't count.
+        assert!(parse_guarded(br#"{"a":"[[[[\"[["}"#, 1, 10).is_ok());
+
+        let wide = format!("[{}]", vec!["0"; 100].join(","));
+        assert_eq!(
+            parse_guarded(wide.as_bytes(), 32, 50),
+            Err(JsonGuardError::TooManyElements)
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::http::StatusCode;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{error::AppError, models::StoredRequest};
+
+// Hooks a watcher may fall behind by before it misses some.
+const STREAM_BUFFER: usize = 16;
+
+// Captured hooks broadcast to GET /api/subscribe/:uuid/stream watchers. A
+// uuid has a channel only while someone watches it, so unwatched hooks
+// publish nothing.
+pub struct LiveStreams {
+    max_per_uuid: usize,
+    inner: Mutex<LiveInner>,
+}
+
+#[derive(Default)]
+struct LiveInner {
+    channels: HashMap<String, Channel>,
+    next_id: u64,
+}
+
+struct Channel {
+    // Tells a closed channel from a later one for the same uuid.
+    id: u64,
+    sender: broadcast::Sender<Arc<StoredRequest>>,
+    watchers: usize,
+}
+
+pub struct LiveReceiver {
+    streams: Arc<LiveStreams>,
+    uuid: String,
+    channel_id: u64,
+    receiver: broadcast::Receiver<Arc<StoredRequest>>,
+}
+
+impl LiveStreams {
+    pub fn new(max_per_uuid: usize) -> Self {
+        Self {
+            max_per_uuid,
+            inner: Mutex::new(LiveInner::default()),
+        }
+    }
+
+    pub fn subscribe(self: &Arc<Self>, uuid: &str) -> Result<LiveReceiver, AppError> {
+        if self.max_per_uuid == 0 {
+            return Err(AppError::new(
+                StatusCode::NOT_FOUND,
+                "live streams are disabled",
+            )
+            .with_code("streams_disabled"));
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let next_id = inner.next_id;
+        let channel = inner.channels.entry(uuid.to_string()).or_insert_with(|| Channel {
+            id: next_id,
+            sender: broadcast::channel(STREAM_BUFFER).0,
+            watchers: 0,
+        });
+        if channel.watchers >= self.max_per_uuid {
+            return Err(AppError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many live streams for this subscription",
+            )
+            .with_code("too_many_streams"));
+        }
+        channel.watchers += 1;
+        let receiver = LiveReceiver {
+            streams: self.clone(),
+            uuid: uuid.to_string(),
+            channel_id: channel.id,
+            receiver: channel.sender.subscribe(),
+        };
+        if channel.id == next_id {
+            inner.next_id += 1;
+        }
+        Ok(receiver)
+    }
+
+    pub fn is_watched(&self, uuid: &str) -> bool {
+        self.inner.lock().unwrap().channels.contains_key(uuid)
+    }
+
+    pub fn publish(&self, uuid: &str, request: StoredRequest) {
+        if let Some(channel) = self.inner.lock().unwrap().channels.get(uuid) {
+            // No receivers left is fine; the last one is being dropped.
+            let _ = channel.sender.send(Arc::new(request));
+        }
+    }
+
+    // Ends every stream for a deleted subscription.
+    pub fn close(&self, uuid: &str) {
+        self.inner.lock().unwrap().channels.remove(uuid);
+    }
+}
+
+impl LiveReceiver {
+    pub async fn recv(&mut self) -> Result<Arc<StoredRequest>, RecvError> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for LiveReceiver {
+    fn drop(&mut self) {
+        let mut inner = self.streams.inner.lock().unwrap();
+        let Some(channel) = inner.channels.get_mut(&self.uuid) else {
+            return;
+        };
+        if channel.id != self.channel_id {
+            return;
+        }
+        channel.watchers -= 1;
+        if channel.watchers == 0 {
+            inner.channels.remove(&self.uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::HookMeta;
+
+    fn request(request_id: &str) -> StoredRequest {
+        let meta: HookMeta = serde_json::from_value(serde_json::json!({
+            "timestamp": "2026-01-01T00:00:00Z",
+            "method": "POST",
+            "path": "/hook/abc",
+            "query_string": "",
+            "headers": {},
+            "source_ip": "127.0.0.1"
+        }))
+        .unwrap();
+        StoredRequest {
+            request_id: request_id.to_string(),
+            meta,
+            body: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn watchers_are_capped_and_released_on_drop() {
+        let streams = Arc::new(LiveStreams::new(2));
+        let mut first = streams.subscribe("abc").unwrap();
+        let second = streams.subscribe("abc").unwrap();
+        let refused = streams.subscribe("abc");
+        assert!(matches!(refused, Err(err) if err.status == StatusCode::TOO_MANY_REQUESTS));
+
+        streams.publish("abc", request("r1"));
+        assert_eq!(first.recv().await.unwrap().request_id, "r1");
+
+        drop(second);
+        assert!(streams.subscribe("abc").is_ok());
+        drop(first);
+        assert!(!streams.is_watched("abc"));
+
+        // A closed channel ends its streams; late drops leave new ones alone.
+        let mut old = streams.subscribe("abc").unwrap();
+        streams.close("abc");
+        assert!(matches!(old.recv().await, Err(RecvError::Closed)));
+        let _new = streams.subscribe("abc").unwrap();
+        drop(old);
+        assert!(streams.is_watched("abc"));
+    }
+}
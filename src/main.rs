@@ -1,33 +1,74 @@
+mod access_log;
+mod allowlist;
+mod client_ip;
 mod config;
+mod content_dedup;
+mod cooldown;
 mod db;
+mod dedup;
 mod error;
+mod filter;
 mod handlers;
+mod heartbeat;
+mod history;
+mod hook_lock;
+mod json_guard;
+mod live;
+mod metrics;
 mod models;
+mod multipart;
+mod poll;
 mod push;
 mod queue;
 mod rate_limiter;
+mod request_id;
+mod spill;
 mod state;
+mod summary;
+mod verify;
 
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
 use axum::{
-    extract::DefaultBodyLimit,
-    http::{HeaderValue, StatusCode, Uri},
+    extract::{DefaultBodyLimit, Request},
+    http::{header::SERVER, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::Response,
     routing::{any, delete, get, get_service, post},
     Router,
 };
+use arc_swap::ArcSwap;
 use dotenvy::dotenv;
 use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::services::{ServeDir, ServeFile};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
 use crate::{
-    config::Config,
+    allowlist::load_allowlist,
+    config::{Config, LogFormat, SharedConfig},
+    content_dedup::ContentDedup,
+    cooldown::OversizeCooldown,
     db::{cleanup_expired, init_db, open_db},
-    handlers::{config as config_handler, health, hook, subscribe, unsubscribe},
+    dedup::{coalesce_purge_expired, dedup_purge_expired, init_dedup},
+    handlers::{
+        clear_dead_letters, config as config_handler, dead_letters, flush_queue, live, ready,
+        history as history_handler, hook, metrics as metrics_handler, queue_stats, replay,
+        poll, retry_dead_letter, rotate_token, stream, subscribe, subscription_status, unsubscribe,
+    },
+    history::init_history,
+    hook_lock::HookLocks,
+    live::LiveStreams,
+    metrics::Metrics,
+    poll::PollWaiters,
+    push::{validate_vapid_keys, PushTransport, WebPushTransport},
     queue::{init_queue_db, DiskQueue},
     rate_limiter::RateLimiter,
+    request_id::{request_id, X_REQUEST_ID},
     state::AppState,
 };
 use web_push::WebPushClient;
@@ -35,52 +76,163 @@ use web_push::WebPushClient;
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
-
     let cfg = Arc::new(Config::from_env()?);
+    let logs = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    match cfg.log_format {
+        LogFormat::Text => logs.init(),
+        LogFormat::Json => logs.json().init(),
+    }
+
     ensure_secure_base_url(&cfg.public_base_url)?;
+    validate_vapid_keys(&cfg)?;
     let db = Arc::new(open_db(&cfg.db_path).map_err(|err| anyhow::anyhow!(err))?);
     init_db(&db).map_err(|err| anyhow::anyhow!(err))?;
+    init_history(&db).map_err(|err| anyhow::anyhow!(err))?;
+    init_dedup(&db).map_err(|err| anyhow::anyhow!(err))?;
+    let uuid_allowlist = match &cfg.uuid_allowlist_file {
+        Some(path) => {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| anyhow::anyhow!("cannot read UUID_ALLOWLIST_FILE {path}: {err}"))?;
+            let ids = load_allowlist(&db, &cfg, &contents)?;
+            info!("closed instance: {} allowlisted subscriptions", ids.len());
+            Some(Arc::new(ids))
+        }
+        None => None,
+    };
     let queue_db = Arc::new(open_db(&cfg.queue_db_path).map_err(|err| anyhow::anyhow!(err))?);
-    init_queue_db(&queue_db).map_err(|err| anyhow::anyhow!(err))?;
+    let recovered = init_queue_db(&queue_db).map_err(|err| anyhow::anyhow!(err))?;
+    if recovered > 0 {
+        info!("recovered {recovered} inflight queue records");
+    }
     let rate_limiter = Arc::new(RateLimiter::new(cfg.rate_limit_per_minute));
     let push_client = WebPushClient::new().map_err(|err| anyhow::anyhow!(err))?;
+    let push: Arc<dyn PushTransport> =
+        Arc::new(WebPushTransport::new(cfg.clone(), db.clone(), push_client));
+    let metrics = Arc::new(Metrics::default());
+    metrics
+        .queue_records_recovered
+        .store(recovered as u64, Ordering::Relaxed);
+    let config: SharedConfig = Arc::new(ArcSwap::new(cfg.clone()));
     let push_queue = DiskQueue::new(
         queue_db.clone(),
         db.clone(),
-        cfg.clone(),
-        push_client.clone(),
+        config.clone(),
+        push.clone(),
+        metrics.clone(),
     );
 
     let state = AppState {
         db: db.clone(),
-        cfg: cfg.clone(),
+        config: config.clone(),
         rate_limiter,
         push_queue,
+        push,
+        metrics,
+        uuid_allowlist,
+        oversize_cooldown: Arc::new(OversizeCooldown::new(
+            cfg.oversize_strikes,
+            Duration::from_secs(cfg.oversize_cooldown_secs),
+        )),
+        hook_locks: Arc::new(HookLocks::new(cfg.serialize_hooks)),
+        live_streams: Arc::new(LiveStreams::new(cfg.live_streams_per_uuid)),
+        poll_waiters: Arc::new(PollWaiters::default()),
+        content_dedup: Arc::new(ContentDedup::new(Duration::from_secs(
+            cfg.content_dedup_window_secs,
+        ))),
     };
 
-    // Background cleanup for expired subscriptions (TTL).
-    if cfg.subscription_ttl_days > 0 {
+    // Background cleanup for expired subscriptions (TTL), dedup keys, coalesce
+    // windows and dead letters.
+    if cfg.subscription_ttl_days > 0 || cfg.dedup_window_secs > 0 || cfg.dead_letter_ttl_days > 0 {
         let db_clone = db.clone();
+        let push_queue = state.push_queue.clone();
         let ttl_days = cfg.subscription_ttl_days;
+        let ttl_from = cfg.subscription_ttl_from;
+        let dedup_window_secs = cfg.dedup_window_secs as i64;
+        let dead_letter_ttl_days = cfg.dead_letter_ttl_days;
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(3600));
             loop {
                 interval.tick().await;
-                if let Err(err) = cleanup_expired(&db_clone, ttl_days) {
+                if ttl_days > 0
+                    && let Err(err) = cleanup_expired(&db_clone, ttl_days, ttl_from)
+                {
                     error!("cleanup failed: {err}");
                 }
+                let now_secs = chrono::Utc::now().timestamp();
+                if dedup_window_secs > 0
+                    && let Err(err) = dedup_purge_expired(&db_clone, now_secs, dedup_window_secs)
+                {
+                    error!("dedup cleanup failed: {err}");
+                }
+                if let Err(err) = coalesce_purge_expired(&db_clone, now_secs) {
+                    error!("coalesce cleanup failed: {err}");
+                }
+                if dead_letter_ttl_days > 0 {
+                    let cutoff = chrono::Utc::now() - chrono::Duration::days(dead_letter_ttl_days);
+                    if let Err(err) = push_queue.purge_dead_letters(cutoff.timestamp_millis()) {
+                        error!("dead-letter cleanup failed: {err}");
+                    }
+                }
+            }
+        });
+    }
+
+    // Pending records that outlived QUEUE_RECORD_TTL_SECS, e.g. stuck behind
+    // a subscription whose pushes keep failing. Workers expire the ones they claim.
+    {
+        let config = config.clone();
+        let push_queue = state.push_queue.clone();
+        let metrics = state.metrics.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let cfg = config.load_full();
+                if cfg.queue_record_ttl_secs == 0 {
+                    continue;
+                }
+                let expired =
+                    push_queue.expire_stale(cfg.queue_record_ttl_secs, cfg.dead_letter_max_bytes);
+                match expired {
+                    Ok(expired) => metrics::add(&metrics.pushes_expired, expired as u64),
+                    Err(err) => error!("queue expiry failed: {err}"),
+                }
             }
         });
     }
 
+    spawn_config_reload(config, state.rate_limiter.clone());
+
+    let push_queue = state.push_queue.clone();
+    let app = build_router(state)?;
+
+    let listener = tokio::net::TcpListener::bind(&cfg.bind_addr).await?;
+    info!("listening on {}", cfg.bind_addr);
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // No more hooks arrive; give the workers a chance to push what is queued,
+    // then stop them once their current record is settled.
+    let drain_timeout = Duration::from_millis(cfg.shutdown_drain_timeout_ms);
+    match push_queue.shutdown(drain_timeout).await {
+        Ok(0) => info!("queue drained"),
+        Ok(remaining) => warn!("{remaining} queue records left undelivered; sent on next start"),
+        Err(err) => error!("queue drain failed: {err}"),
+    }
+
+    Ok(())
+}
+
+fn build_router(state: AppState) -> anyhow::Result<Router> {
+    let cfg = state.cfg();
     let cors = if cfg.cors_allow_any {
         CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
             .allow_headers(Any)
+            .expose_headers([X_REQUEST_ID])
     } else {
         let origins = cfg
             .cors_origins
@@ -91,18 +243,38 @@ async fn main() -> anyhow::Result<()> {
             .allow_origin(AllowOrigin::list(origins))
             .allow_methods(Any)
             .allow_headers(Any)
+            .expose_headers([X_REQUEST_ID])
     };
 
     let mut app = Router::new()
-        .route("/health", get(health))
+        .route("/live", get(live))
+        .route("/ready", get(ready))
+        // Kept for probes configured before /live and /ready existed.
+        .route("/health", get(live))
+        .route("/metrics", get(metrics_handler))
         .route("/api/config", get(config_handler))
         // Keep subscription payloads small (PushSubscription JSON).
         .route(
             "/api/subscribe",
             post(subscribe).layer(DefaultBodyLimit::max(8 * 1024)),
         )
-        .route("/api/subscribe/:uuid", delete(unsubscribe))
+        .route(
+            "/api/subscribe/:uuid",
+            get(subscription_status).delete(unsubscribe),
+        )
+        .route("/api/subscribe/:uuid/rotate-token", post(rotate_token))
+        .route("/api/subscribe/:uuid/stream", get(stream))
+        .route("/api/subscribe/:uuid/poll", get(poll))
+        .route("/api/deadletter", delete(clear_dead_letters))
+        .route("/api/admin/queue", get(queue_stats))
+        .route("/api/admin/queue/flush/:uuid", post(flush_queue))
+        .route("/api/admin/dead-letters", get(dead_letters))
+        .route("/api/admin/dead-letters/:seq/retry", post(retry_dead_letter))
+        .route("/api/requests/:uuid", get(history_handler))
+        .route("/api/requests/:uuid/:request_id/replay", post(replay))
         .route("/hook/:uuid", any(hook))
+        // Static segments always win over this capture in axum's router, so
+        // `/health` never reaches `hook`; db::RESERVED_WORDS keeps ids off them.
         .route("/:uuid", any(hook))
         .layer(cors)
         .with_state(state);
@@ -143,20 +315,89 @@ async fn main() -> anyhow::Result<()> {
             );
     }
 
-    let listener = tokio::net::TcpListener::bind(&cfg.bind_addr).await?;
-    info!("listening on {}", cfg.bind_addr);
-    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    if !cfg.hide_server_header {
+        app = app.layer(middleware::from_fn(server_header));
+    }
+    Ok(app.layer(middleware::from_fn(request_id)))
+}
 
-    Ok(())
+const SERVER_HEADER: &str = concat!("WebhookPush/", env!("CARGO_PKG_VERSION"));
+
+// Tells deployments apart when debugging; HIDE_SERVER_HEADER turns it off.
+async fn server_header(req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    response
+        .headers_mut()
+        .insert(SERVER, HeaderValue::from_static(SERVER_HEADER));
+    response
 }
 
+// Ctrl-C, or SIGTERM from systemd/Docker on unix.
 async fn shutdown_signal() {
-    let _ = tokio::signal::ctrl_c().await;
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                error!("cannot listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
     info!("shutdown signal received");
 }
 
+// SIGHUP re-reads the config (CONFIG_FILE is the place for settings meant to
+// change while running; the process environment is fixed).
+fn spawn_config_reload(config: SharedConfig, rate_limiter: Arc<RateLimiter>) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(err) => {
+                error!("cannot listen for SIGHUP: {err}");
+                return;
+            }
+        };
+        while sighup.recv().await.is_some() {
+            reload_config(&config, &rate_limiter);
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = (config, rate_limiter);
+}
+
+fn reload_config(config: &SharedConfig, rate_limiter: &RateLimiter) {
+    let mut next = match Config::from_env() {
+        Ok(next) => next,
+        Err(err) => {
+            error!("config reload failed, keeping the current settings: {err}");
+            return;
+        }
+    };
+    for field in next.keep_restart_only(&config.load()) {
+        warn!("config reload: {field} changed but needs a restart; ignored");
+    }
+    rate_limiter.set_limit(next.rate_limit_per_minute);
+    config.store(Arc::new(next));
+    info!("config reloaded");
+}
+
 fn ensure_secure_base_url(value: &str) -> anyhow::Result<()> {
     let uri: Uri = match value.parse() {
         Ok(uri) => uri,
@@ -174,3 +415,69 @@ fn ensure_secure_base_url(value: &str) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::test_state;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn named_routes_win_over_the_uuid_wildcard() {
+        let (state, _queue_db) = test_state(&[("STATIC_DIR", "frontend/static")]);
+        let app = build_router(state).unwrap();
+
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        for probe in ["/health", "/live", "/ready"] {
+            let response = app.clone().oneshot(get(probe)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app.clone().oneshot(get("/sw.js")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .contains("javascript"));
+
+        let response = app.oneshot(get("/favicon.ico")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn server_header_names_the_version_unless_hidden() {
+        let get = || Request::builder().uri("/live").body(Body::empty()).unwrap();
+        let (state, _queue_db) = test_state(&[]);
+        let response = build_router(state).unwrap().oneshot(get()).await.unwrap();
+        let expected = format!("WebhookPush/{}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(response.headers()["server"], expected.as_str());
+
+        let (state, _queue_db) = test_state(&[("HIDE_SERVER_HEADER", "true")]);
+        let response = build_router(state).unwrap().oneshot(get()).await.unwrap();
+        assert!(response.headers().get("server").is_none());
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_and_reported_in_errors() {
+        let (state, _queue_db) = test_state(&[]);
+        let app = build_router(state).unwrap();
+
+        let request = Request::builder()
+            .uri("/hook/000000000000")
+            .header("x-request-id", "trace-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["x-request-id"], "trace-123");
+        let body = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "trace-123");
+
+        let request = Request::builder().uri("/health").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let minted = response.headers()["x-request-id"].to_str().unwrap();
+        assert_eq!(minted.len(), 36);
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::{Map, Value};
+
+#[derive(Default)]
+pub struct Metrics {
+    pub hooks_received: AtomicU64,
+    pub hooks_relayed: AtomicU64,
+    pub chunks_enqueued: AtomicU64,
+    pub pushes_sent: AtomicU64,
+    pub push_failures: AtomicU64,
+    pub pushes_dropped: AtomicU64,
+    pub pushes_expired: AtomicU64,
+    // Set once at startup.
+    pub queue_records_recovered: AtomicU64,
+}
+
+pub struct MetricSample {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: &'static str,
+    pub value: u64,
+}
+
+pub fn inc(counter: &AtomicU64) {
+    add(counter, 1);
+}
+
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+impl Metrics {
+    // Single source for both export formats so they never disagree.
+    pub fn snapshot(&self) -> Vec<MetricSample> {
+        let counter = |name, help, value: &AtomicU64| MetricSample {
+            name,
+            help,
+            kind: "counter",
+            value: value.load(Ordering::Relaxed),
+        };
+        vec![
+            counter(
+                "webhookpush_hooks_received_total",
+                "Webhooks received for a known subscription.",
+                &self.hooks_received,
+            ),
+            counter(
+                "webhookpush_hooks_relayed_total",
+                "Webhooks fully enqueued for push delivery.",
+                &self.hooks_relayed,
+            ),
+            counter(
+                "webhookpush_chunks_enqueued_total",
+                "Chunk envelopes written to the disk queue.",
+                &self.chunks_enqueued,
+            ),
+            counter(
+                "webhookpush_pushes_sent_total",
+                "Push messages accepted by the push service.",
+                &self.pushes_sent,
+            ),
+            counter(
+                "webhookpush_push_failures_total",
+                "Push attempts that failed.",
+                &self.push_failures,
+            ),
+            counter(
+                "webhookpush_pushes_dropped_total",
+                "Queue records dropped after exhausting retries.",
+                &self.pushes_dropped,
+            ),
+            counter(
+                "webhookpush_pushes_expired_total",
+                "Queue records dead-lettered for waiting longer than QUEUE_RECORD_TTL_SECS.",
+                &self.pushes_expired,
+            ),
+            counter(
+                "webhookpush_queue_records_recovered_total",
+                "Inflight queue records returned to pending at startup.",
+                &self.queue_records_recovered,
+            ),
+        ]
+    }
+}
+
+pub fn render_prometheus(samples: &[MetricSample]) -> String {
+    let mut out = String::new();
+    for sample in samples {
+        out.push_str(&format!("# HELP {} {}\n", sample.name, sample.help));
+        out.push_str(&format!("# TYPE {} {}\n", sample.name, sample.kind));
+        out.push_str(&format!("{} {}\n", sample.name, sample.value));
+    }
+    out
+}
+
+pub fn render_json(samples: &[MetricSample]) -> Value {
+    let mut map = Map::new();
+    for sample in samples {
+        map.insert(sample.name.to_string(), Value::from(sample.value));
+    }
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_matches_prometheus_text() {
+        let metrics = Metrics::default();
+        inc(&metrics.hooks_received);
+        inc(&metrics.hooks_received);
+        inc(&metrics.pushes_sent);
+
+        let samples = metrics.snapshot();
+        let text = render_prometheus(&samples);
+        let json = render_json(&samples);
+        let object = json.as_object().unwrap();
+        assert_eq!(object.len(), samples.len());
+
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (name, value) = line.split_once(' ').unwrap();
+            let value: u64 = value.parse().unwrap();
+            assert_eq!(object[name], Value::from(value));
+        }
+        assert_eq!(object["webhookpush_hooks_received_total"], Value::from(2));
+        assert_eq!(object["webhookpush_pushes_sent_total"], Value::from(1));
+    }
+}
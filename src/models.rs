@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::multipart::FormSummary;
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct PushSubscription {
     pub endpoint: String,
@@ -16,12 +18,167 @@ pub struct PushKeys {
     pub auth: String,
 }
 
+#[derive(Deserialize)]
+pub struct SubscribeRequest {
+    #[serde(flatten)]
+    pub subscription: PushSubscription,
+    #[serde(default)]
+    pub options: SubscriptionOptions,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct SubscriptionOptions {
+    // Bodies shorter than this are acknowledged but not relayed.
+    #[serde(default)]
+    pub min_body_bytes: usize,
+    // Reply sent to the hook caller; falls back to the server default.
+    #[serde(default)]
+    pub response_status: Option<u16>,
+    #[serde(default)]
+    pub response_body: Option<String>,
+    #[serde(default)]
+    pub response_content_type: Option<String>,
+    // Pins the reply's shape for providers that check it; unset keeps the
+    // server's HOOK_RESPONSE_BODY/HOOK_ACK_BODY behaviour.
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    // Overrides FORWARD_QUERY_STRING for this subscription.
+    #[serde(default)]
+    pub forward_query_string: Option<bool>,
+    // Header carrying the provider's idempotency key; defaults to Idempotency-Key.
+    #[serde(default)]
+    pub idempotency_header: Option<String>,
+    // Overrides CAPTURE_HEADERS for this subscription; ["*"] keeps all.
+    #[serde(default)]
+    pub capture_headers: Option<Vec<String>>,
+    // Overrides OVERSIZE_MODE for this subscription.
+    #[serde(default)]
+    pub oversize_mode: Option<OversizeMode>,
+    // Default for hooks without `?delivery=`.
+    #[serde(default)]
+    pub delivery: Option<DeliveryMode>,
+    // Sender IPs/CIDRs allowed to call the hook; empty allows everyone.
+    #[serde(default)]
+    pub allowed_source_cidrs: Vec<String>,
+    // When set, senders must present `Authorization: Bearer <token>`.
+    #[serde(default)]
+    pub hook_auth_token: Option<String>,
+    // Reject hooks whose provider signature doesn't match.
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    // Answer the provider's URL verification challenge.
+    #[serde(default)]
+    pub challenge: Option<Challenge>,
+    // Receive a heartbeat push every HEARTBEAT_INTERVAL_MINUTES.
+    #[serde(default)]
+    pub heartbeat: bool,
+    // JSON pointers (`*` matches every element) to relay instead of the whole
+    // body; bodies that aren't JSON are relayed unchanged.
+    #[serde(default)]
+    pub filters: Vec<String>,
+    // Give repeats a shared push topic so the client updates one notification.
+    #[serde(default)]
+    pub coalesce: Option<Coalesce>,
+    // Free-form tags for operators, e.g. {"owner": "payments-team"}.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+// What happens to a body larger than MAX_BODY_BYTES.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizeMode {
+    // Refuse with 413.
+    Reject,
+    // Relay the first bytes that fit and drop the rest.
+    Truncate,
+}
+
+// Hooks with the same key inside the window reuse the first one's topic.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Coalesce {
+    // Header whose value is the key, e.g. an alert name; identical bodies when unset.
+    #[serde(default)]
+    pub header: Option<String>,
+    // Overrides COALESCE_WINDOW_SECS for this subscription.
+    #[serde(default)]
+    pub window_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    // `response_body` (which must be JSON), else the ack, as application/json.
+    Json,
+    // `response_body` or nothing, as text/plain.
+    Text,
+    // Status only.
+    Empty,
+}
+
+// When the hook caller gets its response.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryMode {
+    // Once the chunks are queued.
+    Async,
+    // Once the push service has accepted every chunk.
+    Sync,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum Verification {
+    // HMAC-SHA256 of the body in `X-Hub-Signature-256`.
+    Github { secret: String },
+    // HMAC-SHA256 of "{t}.{body}" in `Stripe-Signature`; `t` must be recent.
+    Stripe {
+        secret: String,
+        #[serde(default)]
+        tolerance_secs: Option<u64>,
+    },
+    // HMAC-SHA256 of the body in a sender-chosen header, hex (optionally
+    // "sha256=" prefixed) or base64.
+    Hmac { secret: String, header: String },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Challenge {
+    #[serde(flatten)]
+    pub provider: ChallengeProvider,
+    // Also relay the challenge request, flagged with `verification: true`.
+    #[serde(default)]
+    pub push: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum ChallengeProvider {
+    // `{"type":"url_verification","challenge":"..."}` bodies.
+    Slack,
+    // A query parameter echoed back, `hub.challenge` unless named.
+    Query {
+        #[serde(default)]
+        param: Option<String>,
+    },
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct StoredSubscription {
     pub subscription: PushSubscription,
     pub created_at: DateTime<Utc>,
     #[serde(default)]
     pub delete_token: String,
+    #[serde(default)]
+    pub options: SubscriptionOptions,
+    // Latest relayed hook, to ACTIVITY_RESOLUTION_SECS; unset until the first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_active_at: Option<DateTime<Utc>>,
+    // Latest push failure, cleared by the next successful push.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize)]
@@ -29,27 +186,196 @@ pub struct SubscribeResponse {
     pub uuid: String,
     pub url: String,
     pub delete_token: String,
+    // Raw bytes carried per push, so clients can warn about large webhooks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_chunk_bytes: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionStatus {
+    pub uuid: String,
+    pub created_at: DateTime<Utc>,
+    pub last_active_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize)]
+pub struct RotateTokenResponse {
+    pub delete_token: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct HookMeta {
     pub timestamp: String,
     pub method: String,
     pub path: String,
     pub query_string: String,
+    // The request's Content-Type, kept even when capture_headers drops it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // Bytes of the body that follows this metadata in the payload.
+    #[serde(default)]
+    pub content_length: usize,
     pub headers: HashMap<String, String>,
+    // Headers left out by the capture allowlist.
+    #[serde(default)]
+    pub headers_omitted: usize,
     pub source_ip: String,
+    // Set when the body was cut at MAX_BODY_BYTES in truncate mode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_content_length: Option<usize>,
+    // multipart/form-data bodies under MULTIPART_MODE=summarize; the body is then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form: Option<FormSummary>,
+    // mTLS identity passed on by the terminating proxy, when configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert_subject: Option<String>,
+    // The body was reduced to the subscription's filters.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub filtered: bool,
+    // A provider challenge that was answered rather than a real event.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verification: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoredRequest {
+    pub request_id: String,
+    pub meta: HookMeta,
+    // Base64 of the raw body bytes.
+    pub body: String,
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub request: StoredRequest,
 }
 
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub requests: Vec<HistoryEntry>,
+    pub next_before: Option<u64>,
+}
+
+// Default hook reply, so senders can correlate a delivery with the push.
+#[derive(Serialize)]
+pub struct HookAck {
+    pub request_id: String,
+    pub total_chunks: usize,
+    // Body bytes read from the sender, including any cut by truncation.
+    pub received_bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct ReplayResponse {
+    pub request_id: String,
+}
+
+// Version 2 adds payload_sha256/payload_len to the last chunk; version 3 adds
+// crc32 to every chunk.
+pub const ENVELOPE_VERSION: u8 = 3;
+
 #[derive(Serialize)]
 pub struct ChunkEnvelope {
+    pub version: u8,
     pub request_id: String,
     pub chunk_index: usize,
     pub total_chunks: Option<usize>,
     pub is_last: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    // Shared by coalesced hooks; the service worker uses it as the notification tag.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    // Hex SHA-256 and length of the reassembled data (before gzip inflation),
+    // set on the last chunk only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_len: Option<usize>,
+    // First chunk only, so a notification can be shown before reassembly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<HookSummary>,
+    // First chunk only: the reassembled data's length, as payload_len will be.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<usize>,
+    // CRC-32 (IEEE) of this chunk's raw bytes, before base64.
+    pub crc32: u32,
     pub data: String,
 }
 
+// Short, clipped notification text; drawn only from captured headers.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct HookSummary {
+    pub method: String,
+    pub path: String,
+    // Provider name, else the sender's User-Agent product.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // Start of a text body, unless SUMMARY_BODY_PREVIEW=false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+// Scheduled keepalive; carries no request.
+#[derive(Serialize)]
+pub struct HeartbeatEnvelope {
+    pub version: u8,
+    pub heartbeat: bool,
+}
+
+#[derive(Serialize)]
+pub struct PurgeResponse {
+    pub purged: usize,
+}
+
+#[derive(Serialize)]
+pub struct FlushResponse {
+    pub flushed: usize,
+}
+
+// Disk queue backlog, from table lengths and QUEUE_META counters.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct QueueStats {
+    pub pending: u64,
+    pub inflight: u64,
+    pub queue_bytes: u64,
+    // When the longest-waiting pending record was enqueued, heartbeats
+    // aside; it can be long past when the queue is backing up.
+    pub oldest_pending_enqueued_at_ms: Option<i64>,
+    pub dead_letters: u64,
+    pub dead_letter_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    pub queue: QueueStats,
+}
+
+// A queue record that exhausted its retries, was refused by the push service
+// or waited past QUEUE_RECORD_TTL_SECS.
+#[derive(Serialize)]
+pub struct DeadLetter {
+    pub seq: u64,
+    pub uuid: String,
+    pub dead_at: DateTime<Utc>,
+    pub error: String,
+    pub attempts: u32,
+    pub payload_bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct DeadLettersResponse {
+    pub dead_letters: Vec<DeadLetter>,
+}
+
 #[derive(Serialize)]
 pub struct ConfigResponse {
     pub public_key: String,
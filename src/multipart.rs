@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+
+// Text fields inline, file parts reduced to stubs.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FormSummary {
+    pub fields: Vec<FormField>,
+    pub files: Vec<FormFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FormField {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FormFile {
+    pub name: String,
+    pub filename: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub size: usize,
+}
+
+// Boundary of a `multipart/form-data` Content-Type, if that's what it is.
+pub fn form_data_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+    })
+}
+
+// Parse a buffered RFC 7578 body; None if it is malformed.
+pub fn summarize(body: &[u8], boundary: &str) -> Option<FormSummary> {
+    let delimiter = format!("--{boundary}").into_bytes();
+    let mut rest = &body[find(body, &delimiter)? + delimiter.len()..];
+    let mut summary = FormSummary::default();
+    loop {
+        if rest.starts_with(b"--") {
+            return Some(summary);
+        }
+        rest = rest.strip_prefix(b"\r\n")?;
+        let header_end = find(rest, b"\r\n\r\n")?;
+        let headers = std::str::from_utf8(&rest[..header_end]).ok()?;
+        rest = &rest[header_end + 4..];
+
+        let mut next = b"\r\n".to_vec();
+        next.extend_from_slice(&delimiter);
+        let content_end = find(rest, &next)?;
+        let content = &rest[..content_end];
+        rest = &rest[content_end + next.len()..];
+
+        let mut name = None;
+        let mut filename = None;
+        let mut content_type = None;
+        for line in headers.split("\r\n") {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            if key.trim().eq_ignore_ascii_case("content-disposition") {
+                name = disposition_param(value, "name");
+                filename = disposition_param(value, "filename");
+            } else if key.trim().eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+        let name = name?;
+        match filename {
+            Some(filename) => summary.files.push(FormFile {
+                name,
+                filename,
+                content_type,
+                size: content.len(),
+            }),
+            None => summary.fields.push(FormField {
+                name,
+                value: String::from_utf8_lossy(content).into_owned(),
+            }),
+        }
+    }
+}
+
+// `form-data; name="field"; filename="a.png"` -> the named parameter.
+fn disposition_param(value: &str, key: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (param_key, param_value) = param.trim().split_once('=')?;
+        param_key
+            .trim()
+            .eq_ignore_ascii_case(key)
+            .then(|| param_value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const BOUNDARY: &str = "----WebKitFormBoundary7MA4YWxkTrZu0gW";
+
+    // One text field and one binary attachment, as Mailgun would post.
+    pub(crate) fn fixture() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"subject\"\r\n\r\n");
+        body.extend_from_slice(b"Invoice #42\r\n");
+        body.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+        body.extend_from_slice(
+            b"Content-Disposition: form-data; name=\"attachment-1\"; filename=\"scan.png\"\r\n",
+        );
+        body.extend_from_slice(b"Content-Type: image/png\r\n\r\n");
+        body.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+        body.extend((0..4096).map(|i| (i % 256) as u8));
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    #[test]
+    fn summarize_inlines_text_and_stubs_files() {
+        let content_type = format!("multipart/form-data; boundary=\"{BOUNDARY}\"");
+        let boundary = form_data_boundary(&content_type).unwrap();
+        let summary = summarize(&fixture(), &boundary).unwrap();
+        assert_eq!(
+            summary.fields,
+            vec![FormField {
+                name: "subject".to_string(),
+                value: "Invoice #42".to_string(),
+            }]
+        );
+        assert_eq!(
+            summary.files,
+            vec![FormFile {
+                name: "attachment-1".to_string(),
+                filename: "scan.png".to_string(),
+                content_type: Some("image/png".to_string()),
+                size: 8 + 4096,
+            }]
+        );
+
+        assert!(form_data_boundary("application/json").is_none());
+        assert!(summarize(b"not multipart", &boundary).is_none());
+    }
+}
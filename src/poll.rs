@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+// Wakes GET /api/subscribe/:uuid/poll waiters when a hook is stored in
+// history. Entries live only while someone is polling that uuid.
+#[derive(Default)]
+pub struct PollWaiters {
+    inner: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+pub struct PollWaiter<'a> {
+    waiters: &'a PollWaiters,
+    uuid: String,
+    notify: Option<Arc<Notify>>,
+}
+
+impl PollWaiters {
+    pub fn waiter(&self, uuid: &str) -> PollWaiter<'_> {
+        let notify = self
+            .inner
+            .lock()
+            .unwrap()
+            .entry(uuid.to_string())
+            .or_default()
+            .clone();
+        PollWaiter {
+            waiters: self,
+            uuid: uuid.to_string(),
+            notify: Some(notify),
+        }
+    }
+
+    pub fn notify(&self, uuid: &str) {
+        if let Some(notify) = self.inner.lock().unwrap().get(uuid) {
+            notify.notify_waiters();
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+}
+
+impl PollWaiter<'_> {
+    pub fn notify(&self) -> &Notify {
+        self.notify.as_ref().unwrap()
+    }
+}
+
+impl Drop for PollWaiter<'_> {
+    fn drop(&mut self) {
+        // As with hook locks, drop under the map lock so a new waiter can't
+        // clone the entry between the count check and its removal.
+        let mut map = self.waiters.inner.lock().unwrap();
+        drop(self.notify.take());
+        if map
+            .get(&self.uuid)
+            .is_some_and(|notify| Arc::strong_count(notify) == 1)
+        {
+            map.remove(&self.uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn notify_wakes_enabled_waiters_and_entries_are_dropped() {
+        let waiters = PollWaiters::default();
+        let first = waiters.waiter("abc");
+        let second = waiters.waiter("abc");
+        {
+            let notified = first.notify().notified();
+            tokio::pin!(notified);
+            // Registered before the hook arrives, so the wakeup isn't lost.
+            notified.as_mut().enable();
+            waiters.notify("other");
+            assert!(futures_util::poll!(notified.as_mut()).is_pending());
+            waiters.notify("abc");
+            notified.await;
+        }
+
+        drop(first);
+        assert_eq!(waiters.len(), 1);
+        drop(second);
+        assert_eq!(waiters.len(), 0);
+    }
+}
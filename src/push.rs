@@ -1,21 +1,105 @@
+use std::{sync::Arc, time::Duration};
+
 use axum::http::StatusCode;
-use base64::URL_SAFE_NO_PAD;
-use tracing::error;
+use base64::{decode_config, URL_SAFE_NO_PAD};
+use futures_util::future::BoxFuture;
+use tracing::{error, warn};
 use web_push::{
-    ContentEncoding, SubscriptionInfo, VapidSignatureBuilder, WebPushError, WebPushMessageBuilder,
+    ContentEncoding, SubscriptionInfo, VapidSignatureBuilder, WebPushClient, WebPushError,
+    WebPushMessageBuilder,
 };
 
 use crate::{config::Config, db::db_delete, error::AppError, models::PushSubscription};
 use redb::Database;
 
-pub async fn send_push(
+// Push failure plus whether the queue should try the record again.
+#[derive(Debug)]
+pub struct PushError {
+    pub error: AppError,
+    pub retryable: bool,
+    // The push service's Retry-After; replaces the queue's backoff when set.
+    pub retry_after: Option<Duration>,
+}
+
+impl PushError {
+    pub fn permanent(error: AppError) -> Self {
+        Self {
+            error,
+            retryable: false,
+            retry_after: None,
+        }
+    }
+}
+
+impl From<AppError> for PushError {
+    fn from(error: AppError) -> Self {
+        Self {
+            error,
+            retryable: true,
+            retry_after: None,
+        }
+    }
+}
+
+impl From<PushError> for AppError {
+    fn from(err: PushError) -> Self {
+        err.error
+    }
+}
+
+// Delivers one chunk to a subscription; tests swap in a fake.
+pub trait PushTransport: Send + Sync {
+    fn send<'a>(
+        &'a self,
+        uuid: &'a str,
+        subscription: &'a PushSubscription,
+        payload: &'a [u8],
+        ttl_secs: u32,
+    ) -> BoxFuture<'a, Result<(), PushError>>;
+}
+
+// Encrypted, VAPID-signed Web Push to the subscription's push service.
+pub struct WebPushTransport {
+    cfg: Arc<Config>,
+    db: Arc<Database>,
+    client: WebPushClient,
+}
+
+impl WebPushTransport {
+    pub fn new(cfg: Arc<Config>, db: Arc<Database>, client: WebPushClient) -> Self {
+        Self { cfg, db, client }
+    }
+}
+
+impl PushTransport for WebPushTransport {
+    fn send<'a>(
+        &'a self,
+        uuid: &'a str,
+        subscription: &'a PushSubscription,
+        payload: &'a [u8],
+        ttl_secs: u32,
+    ) -> BoxFuture<'a, Result<(), PushError>> {
+        Box::pin(send_push(
+            &self.cfg,
+            &self.db,
+            &self.client,
+            uuid,
+            subscription,
+            payload,
+            ttl_secs,
+        ))
+    }
+}
+
+async fn send_push(
     cfg: &Config,
     db: &Database,
-    push_client: &web_push::WebPushClient,
+    push_client: &WebPushClient,
     uuid: &str,
     subscription: &PushSubscription,
     payload: &[u8],
-) -> Result<(), AppError> {
+    ttl_secs: u32,
+) -> Result<(), PushError> {
     // Web Push requires endpoint + p256dh + auth (from browser subscription).
     let subscription_info = SubscriptionInfo::new(
         subscription.endpoint.clone(),
@@ -33,7 +117,7 @@ pub async fn send_push(
 
     // Encrypt payload per RFC 8030 (AES-128-GCM).
     builder.set_payload(ContentEncoding::Aes128Gcm, payload);
-    builder.set_ttl(60);
+    builder.set_ttl(ttl_secs);
 
     // Sign VAPID JWT (ES256) so push services can authenticate the sender.
     let mut vapid_builder = VapidSignatureBuilder::from_base64(
@@ -41,11 +125,11 @@ pub async fn send_push(
         URL_SAFE_NO_PAD,
         &subscription_info,
     )
-    .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    .map_err(|err| map_vapid_error(uuid, err))?;
     vapid_builder.add_claim("sub", cfg.vapid_subject.as_str());
     let signature = vapid_builder
         .build()
-        .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+        .map_err(|err| map_vapid_error(uuid, err))?;
 
     builder.set_vapid_signature(signature);
 
@@ -55,38 +139,231 @@ pub async fn send_push(
             return Err(AppError::new(
                 StatusCode::PAYLOAD_TOO_LARGE,
                 "push payload too large",
-            ))
+            )
+            .into())
         }
         Err(err) => {
             return Err(AppError::new(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 err.to_string(),
-            ))
+            )
+            .into())
+        }
+    };
+
+    // The client never times out on its own; a hung push service would pin the worker.
+    let timeout = Duration::from_millis(cfg.push_request_timeout_ms);
+    let send_result = match tokio::time::timeout(timeout, push_client.send(message)).await {
+        Ok(result) => result,
+        Err(_) => {
+            error!("push timed out for {uuid}");
+            return Err(AppError::new(
+                StatusCode::BAD_GATEWAY,
+                "push request timed out",
+            )
+            .into());
         }
     };
 
-    match push_client.send(message).await {
+    match send_result {
         Ok(()) => Ok(()),
-        Err(WebPushError::EndpointNotValid) | Err(WebPushError::EndpointNotFound) => {
-            // Remove dead subscriptions when push services report expiration.
-            let _ = db_delete(db, uuid);
-            error!("subscription expired for {uuid}");
-            Err(AppError::new(
+        Err(err) => {
+            if is_dead_endpoint(&err) {
+                // Remove dead subscriptions when push services report expiration.
+                let _ = db_delete(db, uuid);
+            }
+            Err(map_send_error(uuid, err))
+        }
+    }
+}
+
+// Boot-time check that VAPID_PRIVATE_KEY decodes and signs, and that
+// VAPID_PUBLIC_KEY (what browsers subscribe with) is its public half.
+// Otherwise every push would fail later as a 502.
+pub fn validate_vapid_keys(cfg: &Config) -> anyhow::Result<()> {
+    let partial = VapidSignatureBuilder::from_base64_no_sub(&cfg.vapid_private_key, URL_SAFE_NO_PAD)
+        .map_err(|err| {
+            anyhow::anyhow!("VAPID_PRIVATE_KEY is not a base64url P-256 private key: {err}")
+        })?;
+    let public_key = decode_config(cfg.vapid_public_key.trim_end_matches('='), URL_SAFE_NO_PAD)
+        .map_err(|_| anyhow::anyhow!("VAPID_PUBLIC_KEY is not base64url"))?;
+    if public_key != partial.get_public_key() {
+        anyhow::bail!("VAPID_PUBLIC_KEY does not match VAPID_PRIVATE_KEY");
+    }
+
+    let probe = SubscriptionInfo::new("https://push.example.com/vapid-check", "", "");
+    let mut builder = partial.add_sub_info(&probe);
+    builder.add_claim("sub", cfg.vapid_subject.as_str());
+    builder
+        .build()
+        .map_err(|err| anyhow::anyhow!("VAPID signing failed: {err}"))?;
+    Ok(())
+}
+
+// Signing is deterministic, so a bad key or endpoint fails every time. The
+// exception is the JWT `exp` claim, derived from the system clock: a clock
+// jump can make it invalid until the clock settles, so that case is retried.
+fn map_vapid_error(uuid: &str, err: WebPushError) -> PushError {
+    match err {
+        WebPushError::InvalidClaims => {
+            warn!("VAPID signing failed for {uuid}; system clock may have jumped, retrying");
+            AppError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "vapid signing failed: invalid claims (clock skew?)",
+            )
+            .into()
+        }
+        err => {
+            error!("VAPID signing failed for {uuid}: {err}");
+            PushError::permanent(AppError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("vapid signing failed: {err}"),
+            ))
+        }
+    }
+}
+
+// Endpoints the push service reports as permanently gone (404/410).
+fn is_dead_endpoint(err: &WebPushError) -> bool {
+    match err {
+        WebPushError::EndpointNotValid
+        | WebPushError::EndpointNotFound
+        | WebPushError::InvalidUri => true,
+        // Unmapped statuses arrive as `Other("<code>")`.
+        WebPushError::Other(status) => matches!(
+            status
+                .split_whitespace()
+                .next()
+                .and_then(|code| code.parse::<u16>().ok()),
+            Some(404 | 410)
+        ),
+        _ => false,
+    }
+}
+
+// Translate push service failures; retrying only helps for transient ones.
+fn map_send_error(uuid: &str, err: WebPushError) -> PushError {
+    if is_dead_endpoint(&err) {
+        error!("subscription expired for {uuid}: {err}");
+        return PushError::permanent(AppError::new(
+            StatusCode::BAD_GATEWAY,
+            "subscription expired",
+        ));
+    }
+
+    match err {
+        WebPushError::Unauthorized => {
+            // 401/403 from the push service: our VAPID key or `sub` claim is wrong.
+            error!(
+                "push service rejected VAPID credentials for {uuid}; check VAPID configuration (VAPID_PRIVATE_KEY, VAPID_SUBJECT)"
+            );
+            PushError::permanent(AppError::new(
                 StatusCode::BAD_GATEWAY,
-                "subscription expired",
+                "push unauthorized: check VAPID configuration",
             ))
         }
-        Err(WebPushError::PayloadTooLarge) => Err(AppError::new(
+        WebPushError::PayloadTooLarge => AppError::new(
             StatusCode::PAYLOAD_TOO_LARGE,
             "push payload too large",
-        )),
-        Err(err) => {
+        )
+        .into(),
+        // The client only parses Retry-After on 5xx; a 429 arrives as
+        // `Other("429 ...")` without it and falls back to backoff.
+        WebPushError::ServerError(Some(retry_after)) => {
+            warn!("push service asked to retry {uuid} in {}s", retry_after.as_secs());
+            PushError {
+                retry_after: Some(retry_after),
+                ..AppError::new(
+                    StatusCode::BAD_GATEWAY,
+                    "push failed: push service unavailable",
+                )
+                .into()
+            }
+        }
+        err => {
             error!("push failed: {err}");
-            Err(AppError::new(
+            AppError::new(
                 StatusCode::BAD_GATEWAY,
                 format!("push failed: {err}"),
-            ))
+            )
+            .into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unauthorized_is_not_retried() {
+        let err = map_send_error("abc", WebPushError::Unauthorized);
+        assert!(!err.retryable);
+        assert_eq!(err.error.status, StatusCode::BAD_GATEWAY);
+        assert!(err.error.message.contains("VAPID"));
+    }
+
+    #[test]
+    fn gone_endpoints_are_not_retried() {
+        for err in [
+            WebPushError::EndpointNotValid,
+            WebPushError::EndpointNotFound,
+            WebPushError::InvalidUri,
+            WebPushError::Other("410".to_string()),
+            WebPushError::Other("404 Not Found".to_string()),
+        ] {
+            assert!(is_dead_endpoint(&err), "{err:?}");
+            assert!(!map_send_error("abc", err).retryable);
+        }
+        assert!(!is_dead_endpoint(&WebPushError::Other("429".to_string())));
+    }
+
+    #[test]
+    fn vapid_clock_skew_is_retried_but_bad_keys_are_not() {
+        let skew = map_vapid_error("abc", WebPushError::InvalidClaims);
+        assert!(skew.retryable);
+        assert!(skew.error.message.contains("clock"));
+        for err in [WebPushError::InvalidCryptoKeys, WebPushError::MissingCryptoKeys] {
+            assert!(!map_vapid_error("abc", err).retryable);
         }
     }
 
+    #[test]
+    fn transient_failures_are_retried() {
+        let err = map_send_error("abc", WebPushError::ServerError(None));
+        assert!(err.retryable);
+        assert_eq!(err.retry_after, None);
+
+        let err = map_send_error(
+            "abc",
+            WebPushError::ServerError(Some(Duration::from_secs(30))),
+        );
+        assert!(err.retryable);
+        assert_eq!(err.retry_after, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn vapid_keys_must_be_a_matching_pair() {
+        let config = |private_key: &str, public_key: &str| {
+            Config::from_source(&|key| match key {
+                "VAPID_PRIVATE_KEY" => Some(private_key.to_string()),
+                "VAPID_PUBLIC_KEY" => Some(public_key.to_string()),
+                _ => None,
+            })
+            .unwrap()
+        };
+        let private_key = "1adGdBk8EECfC9h2ia0ja_JR2k6fomXbrayNA84txys";
+        let public_key = concat!(
+            "BAPds9LdomeoNOiwVyEWvHcp3qoj8OxRPjzM9UffPT36rq6t-",
+            "jjgfuoiRWAiXhsZSProN93WE2sL1egzOWYj8ao"
+        );
+        validate_vapid_keys(&config(private_key, public_key)).unwrap();
+
+        let err = validate_vapid_keys(&config("REPLACE_ME", public_key)).unwrap_err();
+        assert!(err.to_string().contains("VAPID_PRIVATE_KEY"), "{err}");
+        // A valid key, but not the one the public key belongs to.
+        let other = "Ap2DcHHwzdB2qfF5xZpS6zlcOT4ytoqvL0ZhtI3AJ0I";
+        let err = validate_vapid_keys(&config(other, public_key)).unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+    }
 }
@@ -1,37 +1,128 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use chrono::Utc;
-use redb::{Database, ReadableTable, TableDefinition};
-use tokio::sync::{mpsc, oneshot};
-use tracing::error;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot, Notify,
+};
+use tokio::task::JoinHandle;
+use serde::Deserialize;
+use tracing::{error, field::Empty, info_span, warn, Instrument, Span};
 
 use crate::{
-    config::Config,
-    db::db_get,
+    config::{Config, QueueFullPolicy, SharedConfig},
+    db::{db_get, db_record_push_result},
     error::AppError,
-    push::send_push,
+    heartbeat::heartbeat_enabled,
+    metrics::{self, Metrics},
+    models::{DeadLetter, QueueStats},
+    push::PushTransport,
 };
 
 const QUEUE_PENDING: TableDefinition<u64, &[u8]> = TableDefinition::new("queue_pending");
 const QUEUE_INFLIGHT: TableDefinition<u64, &[u8]> = TableDefinition::new("queue_inflight");
 const QUEUE_META: TableDefinition<&str, u64> = TableDefinition::new("queue_meta");
+// seq -> dead_at_ms (i64 BE) + error_len (u16 BE) + error + encoded record.
+const QUEUE_DEAD: TableDefinition<u64, &[u8]> = TableDefinition::new("queue_dead");
+// seq -> claimed_at_ms for each inflight record.
+const QUEUE_CLAIMS: TableDefinition<u64, i64> = TableDefinition::new("queue_claims");
+// uuid -> bytes of its pending and inflight records, moved with QUEUE_BYTES.
+const QUEUE_PER_UUID: TableDefinition<&str, u64> = TableDefinition::new("queue_per_uuid");
+// (uuid, seq) of each recurring record, so enqueues find a twin without a scan.
+// Entries whose row is gone are dropped when next looked at.
+const QUEUE_RECURRING: TableDefinition<(&str, u64), ()> =
+    TableDefinition::new("queue_recurring");
 
 const META_NEXT_SEQ: &str = "next_seq";
 const META_QUEUE_BYTES: &str = "queue_bytes";
+// Bytes of QUEUE_DEAD entries, kept under DEAD_LETTER_MAX_BYTES.
+const META_DEAD_BYTES: &str = "dead_bytes";
+
+// The envelope fields that identify a chunk; heartbeats have neither.
+#[derive(Deserialize)]
+struct ChunkIds {
+    request_id: Option<String>,
+    chunk_index: Option<usize>,
+}
+
+// Span for one push attempt, so push errors carry the hook and chunk they
+// belong to as fields (queryable with LOG_FORMAT=json).
+fn push_span(record: &QueueRecord, seq: u64) -> Span {
+    let span = info_span!(
+        "push",
+        uuid = %record.uuid,
+        seq,
+        attempt = record.attempts + 1,
+        request_id = Empty,
+        chunk_index = Empty,
+    );
+    if let Ok(ids) = serde_json::from_slice::<ChunkIds>(&record.payload) {
+        if let Some(request_id) = ids.request_id {
+            span.record("request_id", request_id);
+        }
+        if let Some(chunk_index) = ids.chunk_index {
+            span.record("chunk_index", chunk_index);
+        }
+    }
+    span
+}
+
+// Dead-letter reason for records older than QUEUE_RECORD_TTL_SECS.
+const EXPIRED_REASON: &str = "expired in queue";
+
+// Retry-After for a hook refused because its subscriber's backlog is full.
+const BACKLOG_RETRY_AFTER_SECS: u64 = 5;
 
-const WRITE_BUFFER: usize = 1024;
-const IDLE_SLEEP_MS: u64 = 50;
-const RETRY_DELAY_MS: i64 = 500;
-const MAX_ATTEMPTS: u32 = 5;
+const SUPERVISOR_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const SUPERVISOR_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const SUPERVISOR_STABLE_RUN: Duration = Duration::from_secs(60);
+const SUPERVISOR_MAX_RAPID_CRASHES: u32 = 10;
 
 #[derive(Clone)]
 pub struct DiskQueue {
-    sender: mpsc::Sender<QueueInsert>,
+    writer: Arc<Mutex<mpsc::Sender<QueueInsert>>>,
+    queue_db: Arc<Database>,
+    capacity: QueueCapacity,
+    write_buffer: usize,
+    idle_sleep: Duration,
+    // Wakes idle workers when records become sendable.
+    wakeup: Arc<Notify>,
+    // Set by `shutdown`; later enqueues are refused.
+    closed: Arc<AtomicBool>,
+    // Set by `shutdown` once draining ends; workers return instead of claiming.
+    stopping: Arc<AtomicBool>,
+    workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    // How long a send in flight at shutdown may still take.
+    send_timeout: Duration,
+}
+
+#[derive(Clone, Copy)]
+struct QueueCapacity {
+    max_bytes: u64,
+    // 0 disables the per-uuid cap.
+    max_bytes_per_uuid: u64,
+    full_policy: QueueFullPolicy,
 }
 
+// Records written in one transaction: all of them are queued or none are.
 struct QueueInsert {
-    record: QueueRecord,
-    ack: oneshot::Sender<Result<(), AppError>>,
+    records: Vec<QueueRecord>,
+    // False for a hook's later batches: it was admitted with its first one,
+    // so QUEUE_MAX_BYTES_PER_UUID can't cut it off halfway.
+    check_backlog: bool,
+    // The seqs the records were queued under.
+    ack: oneshot::Sender<Result<Range<u64>, AppError>>,
 }
 
 struct QueueRecord {
@@ -39,87 +130,308 @@ struct QueueRecord {
     payload: Vec<u8>,
     send_after_ms: i64,
     attempts: u32,
+    // Push TTL for this record; PUSH_TTL_SECS when unset.
+    ttl_secs: Option<u32>,
+    // Recurring records go back in the queue this long after each delivery.
+    repeat_every_ms: Option<u64>,
+    // When the record was first queued; unset in records from before it was
+    // kept, which never expire.
+    enqueued_at_ms: Option<i64>,
 }
 
-pub fn init_queue_db(db: &Database) -> Result<(), AppError> {
+// Returns how many records a crash had left inflight.
+pub fn init_queue_db(db: &Database) -> Result<usize, AppError> {
     let write_txn = db.begin_write()?;
     {
         write_txn.open_table(QUEUE_PENDING)?;
         write_txn.open_table(QUEUE_INFLIGHT)?;
+        write_txn.open_table(QUEUE_CLAIMS)?;
+        write_txn.open_table(QUEUE_DEAD)?;
+        write_txn.open_table(QUEUE_PER_UUID)?;
+        write_txn.open_table(QUEUE_RECURRING)?;
         let mut meta = write_txn.open_table(QUEUE_META)?;
         if meta.get(META_NEXT_SEQ)?.is_none() {
             meta.insert(META_NEXT_SEQ, 0)?;
         }
-        if meta.get(META_QUEUE_BYTES)?.is_none() {
-            meta.insert(META_QUEUE_BYTES, 0)?;
+        // Databases from before the counter may already hold dead letters.
+        if meta.get(META_DEAD_BYTES)?.is_none() {
+            let dead = write_txn.open_table(QUEUE_DEAD)?;
+            let mut dead_bytes = 0;
+            for entry in dead.iter()? {
+                dead_bytes += entry?.1.value().len() as u64;
+            }
+            meta.insert(META_DEAD_BYTES, dead_bytes)?;
         }
     }
     write_txn.commit()?;
-    Ok(())
+
+    let recovered = recover_inflight(db)?;
+    recount_queue_bytes(db)?;
+    Ok(recovered)
+}
+
+// Recompute QUEUE_BYTES, the per-uuid counts and the recurring index from
+// the pending and inflight rows, so a counter that drifted (or predates the
+// rows) can't leave the queue "full" while it is nearly empty. Returns the
+// corrected total.
+fn recount_queue_bytes(db: &Database) -> Result<u64, AppError> {
+    let write_txn = db.begin_write()?;
+    let actual = {
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let mut recurring = write_txn.open_table(QUEUE_RECURRING)?;
+        let mut actual = 0;
+        let mut actual_per_uuid: HashMap<String, u64> = HashMap::new();
+        let mut actual_recurring = Vec::new();
+        for table in [QUEUE_PENDING, QUEUE_INFLIGHT] {
+            for entry in write_txn.open_table(table)?.iter()? {
+                let (seq, value) = entry?;
+                let len = value.value().len() as u64;
+                actual += len;
+                if let Some((uuid, _)) = peek_record(value.value()) {
+                    *actual_per_uuid.entry(uuid.to_string()).or_default() += len;
+                    if record_repeats(value.value()) {
+                        actual_recurring.push((uuid.to_string(), seq.value()));
+                    }
+                }
+            }
+        }
+        per_uuid.retain(|_, _| false)?;
+        for (uuid, bytes) in &actual_per_uuid {
+            per_uuid.insert(uuid.as_str(), bytes)?;
+        }
+        recurring.retain(|_, _| false)?;
+        for (uuid, seq) in &actual_recurring {
+            recurring.insert((uuid.as_str(), *seq), ())?;
+        }
+        let recorded = meta.get(META_QUEUE_BYTES)?.map(|value| value.value());
+        if recorded != Some(actual) {
+            if let Some(recorded) = recorded {
+                warn!("queue byte counter drifted: recorded {recorded}, stored {actual}");
+            }
+            meta.insert(META_QUEUE_BYTES, actual)?;
+        }
+        actual
+    };
+    write_txn.commit()?;
+    Ok(actual)
+}
+
+// Records left inflight by a crash would never be claimed again; put them
+// back in pending, counting the interrupted send as an attempt. Re-encoding
+// can change a record's size, so QUEUE_BYTES follows any difference.
+fn recover_inflight(db: &Database) -> Result<usize, AppError> {
+    let write_txn = db.begin_write()?;
+    let recovered = {
+        let mut inflight = write_txn.open_table(QUEUE_INFLIGHT)?;
+        let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut claims = write_txn.open_table(QUEUE_CLAIMS)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        claims.retain(|_, _| false)?;
+        let mut next_seq = meta
+            .get(META_NEXT_SEQ)?
+            .map(|value| value.value())
+            .unwrap_or(0);
+
+        let mut recovered = 0;
+        let (mut removed_bytes, mut added_bytes) = (0u64, 0u64);
+        while let Some((key, value)) = inflight.pop_first()? {
+            let seq = key.value();
+            let value_len = value.value().len() as u64;
+            // Same seq keeps the record ahead of later chunks for its uuid.
+            let target = if pending.get(seq)?.is_some() { next_seq } else { seq };
+            next_seq = next_seq.max(target + 1);
+            // Undecodable records go back untouched for the worker to drop.
+            let value = match decode_record(value.value()) {
+                Ok(mut record) => {
+                    record.attempts = record.attempts.saturating_add(1);
+                    encode_record(&record)?
+                }
+                Err(_) => value.value().to_vec(),
+            };
+            removed_bytes += value_len;
+            added_bytes += value.len() as u64;
+            if value.len() as u64 != value_len {
+                adjust_uuid_bytes(&mut per_uuid, &value, value_len, value.len() as u64)?;
+            }
+            pending.insert(target, value.as_slice())?;
+            recovered += 1;
+        }
+        meta.insert(META_NEXT_SEQ, next_seq)?;
+        if added_bytes != removed_bytes {
+            let queue_bytes = meta
+                .get(META_QUEUE_BYTES)?
+                .map(|value| value.value())
+                .unwrap_or(0)
+                .saturating_sub(removed_bytes)
+                .saturating_add(added_bytes);
+            meta.insert(META_QUEUE_BYTES, queue_bytes)?;
+        }
+        recovered
+    };
+    write_txn.commit()?;
+    Ok(recovered)
 }
 
 impl DiskQueue {
     pub fn new(
         queue_db: Arc<Database>,
         subs_db: Arc<Database>,
-        cfg: Arc<Config>,
-        push_client: web_push::WebPushClient,
+        config: SharedConfig,
+        push: Arc<dyn PushTransport>,
+        metrics: Arc<Metrics>,
     ) -> Self {
-        let (sender, mut receiver) = mpsc::channel::<QueueInsert>(WRITE_BUFFER);
-
-        let writer_db = queue_db.clone();
-        let max_bytes = cfg.queue_max_bytes as u64;
-        tokio::spawn(async move {
-            while let Some(item) = receiver.recv().await {
-                let record = item.record;
-                let result = tokio::task::spawn_blocking({
-                    let db = writer_db.clone();
-                    move || enqueue_record(&db, &record, max_bytes)
-                })
-                .await
-                .unwrap_or_else(|err| Err(AppError::new(
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("queue writer crashed: {err}"),
-                )));
-
-                let _ = item.ack.send(result);
-            }
-        });
+        // Sizes are fixed here; workers read the rest as it is reloaded.
+        let cfg = config.load_full();
+        let capacity = QueueCapacity {
+            max_bytes: cfg.queue_max_bytes as u64,
+            max_bytes_per_uuid: cfg.queue_max_bytes_per_uuid as u64,
+            full_policy: cfg.queue_full_policy,
+        };
+        let wakeup = Arc::new(Notify::new());
+        let sender = spawn_writer(
+            queue_db.clone(),
+            capacity,
+            cfg.queue_write_buffer,
+            wakeup.clone(),
+        );
 
-        for _ in 0..cfg.queue_workers {
+        let stopping = Arc::new(AtomicBool::new(false));
+        let mut workers = Vec::with_capacity(cfg.queue_workers);
+        for worker_id in 0..cfg.queue_workers {
             let queue_db = queue_db.clone();
             let subs_db = subs_db.clone();
-            let cfg = cfg.clone();
-            let push_client = push_client.clone();
-            tokio::spawn(async move {
-                worker_loop(queue_db, subs_db, cfg, push_client).await;
-            });
+            let config = config.clone();
+            let push = push.clone();
+            let metrics = metrics.clone();
+            let wakeup = wakeup.clone();
+            let stopping = stopping.clone();
+            workers.push(supervise(worker_id, move || {
+                worker_loop(
+                    queue_db.clone(),
+                    subs_db.clone(),
+                    config.clone(),
+                    push.clone(),
+                    metrics.clone(),
+                    wakeup.clone(),
+                    stopping.clone(),
+                )
+            }));
         }
 
-        Self { sender }
+        Self {
+            writer: Arc::new(Mutex::new(sender)),
+            queue_db,
+            capacity,
+            write_buffer: cfg.queue_write_buffer,
+            idle_sleep: Duration::from_millis(cfg.queue_idle_sleep_ms),
+            wakeup,
+            closed: Arc::new(AtomicBool::new(false)),
+            stopping,
+            workers: Arc::new(Mutex::new(workers)),
+            send_timeout: Duration::from_millis(cfg.push_request_timeout_ms),
+        }
     }
 
+    #[cfg(test)]
     pub async fn enqueue(
         &self,
         uuid: &str,
         payload: Vec<u8>,
         send_after_ms: i64,
+        ttl_secs: Option<u32>,
+    ) -> Result<(), AppError> {
+        self.enqueue_batch(uuid, vec![(payload, send_after_ms)], ttl_secs, false)
+            .await?;
+        Ok(())
+    }
+
+    // Queue several (payload, send_after_ms) records for `uuid` in one write
+    // transaction. If the queue can't take all of them, none are queued.
+    // `continues_hook` marks a batch after the hook's first. Returns the seqs
+    // queued, for `remove_pending` if the rest of the hook fails.
+    pub async fn enqueue_batch(
+        &self,
+        uuid: &str,
+        batch: Vec<(Vec<u8>, i64)>,
+        ttl_secs: Option<u32>,
+        continues_hook: bool,
+    ) -> Result<Range<u64>, AppError> {
+        let enqueued_at_ms = Utc::now().timestamp_millis();
+        let records = batch
+            .into_iter()
+            .map(|(payload, send_after_ms)| QueueRecord {
+                uuid: uuid.to_string(),
+                payload,
+                send_after_ms,
+                attempts: 0,
+                ttl_secs,
+                repeat_every_ms: None,
+                enqueued_at_ms: Some(enqueued_at_ms),
+            })
+            .collect();
+        self.insert(records, !continues_hook).await
+    }
+
+    // Queue `payload` for `uuid` every `every_ms`, starting one interval from now.
+    pub async fn enqueue_recurring(
+        &self,
+        uuid: &str,
+        payload: Vec<u8>,
+        every_ms: u64,
+        ttl_secs: u32,
     ) -> Result<(), AppError> {
+        let enqueued_at_ms = Utc::now().timestamp_millis();
         let record = QueueRecord {
             uuid: uuid.to_string(),
             payload,
-            send_after_ms,
+            send_after_ms: enqueued_at_ms + every_ms as i64,
             attempts: 0,
+            ttl_secs: Some(ttl_secs),
+            repeat_every_ms: Some(every_ms),
+            enqueued_at_ms: Some(enqueued_at_ms),
         };
+        self.insert(vec![record], true).await?;
+        Ok(())
+    }
+
+    async fn insert(
+        &self,
+        records: Vec<QueueRecord>,
+        check_backlog: bool,
+    ) -> Result<Range<u64>, AppError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(AppError::new(
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                "server is shutting down",
+            )
+            .with_code("shutting_down"));
+        }
         let (ack_tx, ack_rx) = oneshot::channel();
-        self.sender
-            .try_send(QueueInsert { record, ack: ack_tx })
-            .map_err(|_| {
-                AppError::new(
-                    axum::http::StatusCode::SERVICE_UNAVAILABLE,
+        let sender = self.writer.lock().unwrap().clone();
+        match sender.try_send(QueueInsert {
+            records,
+            check_backlog,
+            ack: ack_tx,
+        }) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                return Err(AppError::new(
+                    axum::http::StatusCode::TOO_MANY_REQUESTS,
                     "queue writer busy",
                 )
-            })?;
+                .with_code("queue_busy"));
+            }
+            Err(TrySendError::Closed(_)) => {
+                // The writer task is gone; restart it so later requests recover.
+                self.restart_writer();
+                return Err(AppError::new(
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "queue writer unavailable",
+                ));
+            }
+        }
 
         match ack_rx.await {
             Ok(result) => result,
@@ -129,19 +441,219 @@ impl DiskQueue {
             )),
         }
     }
+
+    pub fn purge_dead_letters(&self, cutoff_ms: i64) -> Result<usize, AppError> {
+        purge_dead_letters(&self.queue_db, cutoff_ms)
+    }
+
+    pub fn stats(&self) -> Result<QueueStats, AppError> {
+        queue_stats(&self.queue_db)
+    }
+
+    // Dead letters oldest first, optionally for one subscription.
+    pub fn dead_letters(&self, uuid: Option<&str>) -> Result<Vec<DeadLetter>, AppError> {
+        list_dead_letters(&self.queue_db, uuid)
+    }
+
+    // Send a dead letter again as a fresh pending record.
+    pub fn retry_dead_letter(&self, seq: u64) -> Result<(), AppError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(AppError::new(
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                "server is shutting down",
+            )
+            .with_code("shutting_down"));
+        }
+        retry_dead_letter(&self.queue_db, seq, self.capacity, Utc::now().timestamp_millis())?;
+        self.wakeup.notify_waiters();
+        Ok(())
+    }
+
+    // Dead-letter pending records queued more than `ttl_secs` ago.
+    pub fn expire_stale(&self, ttl_secs: u64, max_dead_bytes: u64) -> Result<usize, AppError> {
+        let ttl_ms = ttl_secs.saturating_mul(1000) as i64;
+        expire_pending(&self.queue_db, ttl_ms, Utc::now().timestamp_millis(), max_dead_bytes)
+    }
+
+    // Drop a deleted subscription's pending records; returns how many. Its
+    // inflight ones are dropped by their workers.
+    pub fn purge(&self, uuid: &str) -> Result<usize, AppError> {
+        purge_pending(&self.queue_db, uuid)
+    }
+
+    // Take back records queued under `seqs` that no worker has claimed yet;
+    // returns how many.
+    pub fn remove_pending(&self, seqs: &[Range<u64>]) -> Result<usize, AppError> {
+        remove_pending(&self.queue_db, seqs)
+    }
+
+    // Make every delayed pending record for `uuid` sendable now.
+    pub fn flush(&self, uuid: &str) -> Result<usize, AppError> {
+        let flushed = flush_pending(&self.queue_db, uuid, Utc::now().timestamp_millis())?;
+        self.wakeup.notify_waiters();
+        Ok(flushed)
+    }
+
+    // Refuse new records and let the workers deliver the rest for up to
+    // `deadline`, then stop them. A record already claimed is settled first,
+    // so nothing is left inflight. Returns how many records were left; they
+    // are sent on the next boot.
+    pub async fn shutdown(&self, deadline: Duration) -> Result<usize, AppError> {
+        self.closed.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + deadline;
+        while self.undelivered().await? > 0 && Instant::now() < deadline {
+            tokio::time::sleep(self.idle_sleep).await;
+        }
+
+        self.stopping.store(true, Ordering::SeqCst);
+        self.wakeup.notify_waiters();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let stopped = futures_util::future::join_all(workers);
+        if tokio::time::timeout(self.send_timeout, stopped).await.is_err() {
+            warn!("queue workers still busy at shutdown; their records are retried on next start");
+        }
+        self.undelivered().await
+    }
+
+    async fn undelivered(&self) -> Result<usize, AppError> {
+        let db = self.queue_db.clone();
+        tokio::task::spawn_blocking(move || undelivered_count(&db)).await?
+    }
+
+    // Readiness: the queue database answers and the writer task is running.
+    pub fn check_ready(&self) -> Result<(), AppError> {
+        if self.writer.lock().unwrap().is_closed() {
+            return Err(AppError::new(
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                "queue writer stopped",
+            )
+            .with_code("not_ready"));
+        }
+        self.queue_db.begin_read().map_err(|err| {
+            AppError::new(
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                format!("queue database unavailable: {err}"),
+            )
+            .with_code("not_ready")
+        })?;
+        Ok(())
+    }
+
+    fn restart_writer(&self) {
+        let mut writer = self.writer.lock().unwrap();
+        // Concurrent callers may race here; only the first one respawns.
+        if writer.is_closed() {
+            error!("queue writer stopped; restarting");
+            *writer = spawn_writer(
+                self.queue_db.clone(),
+                self.capacity,
+                self.write_buffer,
+                self.wakeup.clone(),
+            );
+        }
+    }
+}
+
+// Single writer task serializes inserts into the queue database.
+fn spawn_writer(
+    queue_db: Arc<Database>,
+    capacity: QueueCapacity,
+    buffer: usize,
+    wakeup: Arc<Notify>,
+) -> mpsc::Sender<QueueInsert> {
+    let (sender, mut receiver) = mpsc::channel::<QueueInsert>(buffer);
+    tokio::spawn(async move {
+        while let Some(item) = receiver.recv().await {
+            let records = item.records;
+            let capacity = if item.check_backlog {
+                capacity
+            } else {
+                QueueCapacity {
+                    max_bytes_per_uuid: 0,
+                    ..capacity
+                }
+            };
+            let result = tokio::task::spawn_blocking({
+                let db = queue_db.clone();
+                move || enqueue_records(&db, &records, capacity)
+            })
+            .await
+            .unwrap_or_else(|err| Err(AppError::new(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("queue writer crashed: {err}"),
+            )));
+
+            if result.is_ok() {
+                wakeup.notify_waiters();
+            }
+            let _ = item.ack.send(result);
+        }
+    });
+    sender
+}
+
+// Respawn a worker whose task ends (e.g. a panic) so capacity doesn't silently shrink.
+fn supervise<F, Fut>(worker_id: usize, spawn_worker: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = SUPERVISOR_MIN_BACKOFF;
+        let mut rapid_crashes = 0u32;
+        loop {
+            let started = Instant::now();
+            match tokio::spawn(spawn_worker()).await {
+                Err(err) if err.is_panic() => error!("queue worker {worker_id} panicked"),
+                Err(err) => error!("queue worker {worker_id} aborted: {err}"),
+                // Workers only return once the queue is shutting down.
+                Ok(()) => return,
+            }
+
+            // A worker that ran for a while earns a fresh backoff.
+            if started.elapsed() >= SUPERVISOR_STABLE_RUN {
+                backoff = SUPERVISOR_MIN_BACKOFF;
+                rapid_crashes = 0;
+            }
+            rapid_crashes += 1;
+            if rapid_crashes > SUPERVISOR_MAX_RAPID_CRASHES {
+                error!("queue worker {worker_id} is crash looping; not restarting");
+                return;
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(SUPERVISOR_MAX_BACKOFF);
+            error!("restarting queue worker {worker_id}");
+        }
+    })
 }
 
 async fn worker_loop(
     queue_db: Arc<Database>,
     subs_db: Arc<Database>,
-    cfg: Arc<Config>,
-    push_client: web_push::WebPushClient,
+    config: SharedConfig,
+    push: Arc<dyn PushTransport>,
+    metrics: Arc<Metrics>,
+    wakeup: Arc<Notify>,
+    stopping: Arc<AtomicBool>,
 ) {
     loop {
+        let cfg = config.load_full();
+        let idle_sleep = Duration::from_millis(cfg.queue_idle_sleep_ms);
+        // Registered before looking, so an enqueue during the claim still
+        // wakes this worker. The timeout catches delayed records coming due.
+        // Shutdown wakes it too, after setting `stopping`.
+        let woken = wakeup.notified();
+        tokio::pin!(woken);
+        woken.as_mut().enable();
+        if stopping.load(Ordering::SeqCst) {
+            return;
+        }
         let now_ms = Utc::now().timestamp_millis();
         let claimed = tokio::task::spawn_blocking({
             let db = queue_db.clone();
-            move || claim_next(&db, now_ms)
+            let visibility_timeout_ms = cfg.queue_visibility_timeout_ms as i64;
+            move || claim_next(&db, now_ms, visibility_timeout_ms)
         })
         .await;
 
@@ -149,31 +661,51 @@ async fn worker_loop(
             Ok(result) => result,
             Err(err) => {
                 error!("queue worker failed: {err}");
-                tokio::time::sleep(std::time::Duration::from_millis(IDLE_SLEEP_MS)).await;
+                tokio::time::sleep(idle_sleep).await;
                 continue;
             }
         };
 
-        let (seq, record_bytes) = match claimed {
-            Ok(Some(item)) => item,
+        let Claim {
+            seq,
+            claimed_at_ms,
+            record_bytes,
+        } = match claimed {
+            Ok(Some(claim)) => claim,
             Ok(None) => {
-                tokio::time::sleep(std::time::Duration::from_millis(IDLE_SLEEP_MS)).await;
+                let _ = tokio::time::timeout(idle_sleep, woken).await;
                 continue;
             }
             Err(err) => {
                 error!("queue claim error: {err}");
-                tokio::time::sleep(std::time::Duration::from_millis(IDLE_SLEEP_MS)).await;
+                tokio::time::sleep(idle_sleep).await;
                 continue;
             }
         };
 
+        // Chunks of a hook this late are more confusing than useful.
+        let ttl_ms = cfg.queue_record_ttl_secs.saturating_mul(1000) as i64;
+        if record_expired(&record_bytes, ttl_ms, Utc::now().timestamp_millis()) {
+            metrics::inc(&metrics.pushes_expired);
+            let _ = tokio::task::spawn_blocking({
+                let db = queue_db.clone();
+                let max_bytes = cfg.dead_letter_max_bytes;
+                move || {
+                    let now_ms = Utc::now().timestamp_millis();
+                    dead_letter_inflight(&db, seq, claimed_at_ms, EXPIRED_REASON, now_ms, max_bytes)
+                }
+            })
+            .await;
+            continue;
+        }
+
         let record = match decode_record(&record_bytes) {
             Ok(record) => record,
             Err(err) => {
                 error!("queue decode error: {err}");
                 let _ = tokio::task::spawn_blocking({
                     let db = queue_db.clone();
-                    move || drop_inflight(&db, seq)
+                    move || drop_inflight(&db, seq, claimed_at_ms)
                 })
                 .await;
                 continue;
@@ -195,67 +727,181 @@ async fn worker_loop(
         .and_then(|res| res.ok())
         .flatten();
 
+        // Recurring records are heartbeats; they stop with the subscription
+        // or once heartbeats are switched off.
         let stored = match stored {
-            Some(value) => value,
-            None => {
+            Some(value)
+                if record.repeat_every_ms.is_none()
+                    || heartbeat_enabled(&cfg, &value.options) =>
+            {
+                value
+            }
+            _ => {
                 let _ = tokio::task::spawn_blocking({
                     let db = queue_db.clone();
-                    move || drop_inflight(&db, seq)
+                    move || drop_inflight(&db, seq, claimed_at_ms)
                 })
                 .await;
                 continue;
             }
         };
 
-        let send_result = send_push(
-            &cfg,
-            &subs_db,
-            &push_client,
-            &record.uuid,
-            &stored.subscription,
-            &record.payload,
-        )
-        .await;
+        let ttl_secs = record.ttl_secs.unwrap_or(cfg.push_ttl_secs);
+        let send_result = push
+            .send(&record.uuid, &stored.subscription, &record.payload, ttl_secs)
+            .instrument(push_span(&record, seq))
+            .await;
+
+        if cfg.record_push_errors {
+            let error = match &send_result {
+                Ok(()) if stored.last_error.is_none() => None,
+                Ok(()) => Some(None),
+                Err(err) => Some(Some(err.error.message.clone())),
+            };
+            if let Some(error) = error {
+                let _ = tokio::task::spawn_blocking({
+                    let db = subs_db.clone();
+                    let uuid = record.uuid.clone();
+                    move || db_record_push_result(&db, &uuid, error.as_deref())
+                })
+                .await;
+            }
+        }
+
+        let failure = match send_result {
+            Ok(()) => {
+                metrics::inc(&metrics.pushes_sent);
+                let _ = tokio::task::spawn_blocking({
+                    let db = queue_db.clone();
+                    move || {
+                        let now_ms = Utc::now().timestamp_millis();
+                        finish_inflight(&db, seq, claimed_at_ms, &record, now_ms)
+                    }
+                })
+                .await;
+                continue;
+            }
+            Err(err) => err,
+        };
 
-        if send_result.is_ok() {
+        metrics::inc(&metrics.push_failures);
+        let attempts = record.attempts.saturating_add(1);
+        // A recurring record that keeps failing just waits for its next run.
+        let max_attempts = cfg.queue_max_attempts;
+        if failure.retryable && attempts >= max_attempts && record.repeat_every_ms.is_some() {
             let _ = tokio::task::spawn_blocking({
                 let db = queue_db.clone();
-                move || drop_inflight(&db, seq)
+                move || {
+                    let now_ms = Utc::now().timestamp_millis();
+                    finish_inflight(&db, seq, claimed_at_ms, &record, now_ms)
+                }
             })
             .await;
             continue;
         }
-
-        let attempts = record.attempts.saturating_add(1);
-        if attempts >= MAX_ATTEMPTS {
+        if !failure.retryable || attempts >= max_attempts {
+            metrics::inc(&metrics.pushes_dropped);
             let _ = tokio::task::spawn_blocking({
                 let db = queue_db.clone();
-                move || drop_inflight(&db, seq)
+                let reason = failure.error.message;
+                let max_bytes = cfg.dead_letter_max_bytes;
+                move || {
+                    let now_ms = Utc::now().timestamp_millis();
+                    dead_letter_inflight(&db, seq, claimed_at_ms, &reason, now_ms, max_bytes)
+                }
             })
             .await;
             continue;
         }
 
+        // Retry-After is capped like the backoff: claims keep per-uuid order,
+        // so a parked record holds back the subscriber's later hooks.
+        let delay_ms = match failure.retry_after {
+            Some(retry_after) => retry_after.as_millis().min(cfg.push_retry_max_ms.into()) as u64,
+            None => retry_delay_ms(&cfg, attempts, &mut rand::thread_rng()),
+        };
         let mut retry_record = record;
         retry_record.attempts = attempts;
-        retry_record.send_after_ms = Utc::now().timestamp_millis() + RETRY_DELAY_MS;
+        retry_record.send_after_ms = Utc::now()
+            .timestamp_millis()
+            .saturating_add(i64::try_from(delay_ms).unwrap_or(i64::MAX));
 
         let _ = tokio::task::spawn_blocking({
             let db = queue_db.clone();
-            move || requeue_inflight(&db, seq, &retry_record)
+            move || requeue_inflight(&db, seq, claimed_at_ms, &retry_record)
         })
         .await;
     }
 }
 
-fn enqueue_record(db: &Database, record: &QueueRecord, max_bytes: u64) -> Result<(), AppError> {
-    let record_bytes = encode_record(record)?;
-    let record_len = record_bytes.len() as u64;
+// Exponential backoff before retry number `attempt` (1-based), with +/-20%
+// jitter so records that failed together don't retry together.
+fn retry_delay_ms(cfg: &Config, attempt: u32, rng: &mut impl Rng) -> u64 {
+    let exponent = attempt.saturating_sub(1).min(32);
+    let delay = cfg
+        .push_retry_base_ms
+        .saturating_mul(1 << exponent)
+        .min(cfg.push_retry_max_ms);
+    let jittered = (delay as f64 * rng.gen_range(0.8..=1.2)) as u64;
+    jittered.min(cfg.push_retry_max_ms)
+}
+
+#[cfg(test)]
+fn enqueue_record(
+    db: &Database,
+    record: &QueueRecord,
+    capacity: QueueCapacity,
+) -> Result<Range<u64>, AppError> {
+    enqueue_records(db, std::slice::from_ref(record), capacity)
+}
 
+// Insert every record, or none of them when they don't fit together.
+// Returns the seqs they were given.
+fn enqueue_records(
+    db: &Database,
+    records: &[QueueRecord],
+    capacity: QueueCapacity,
+) -> Result<Range<u64>, AppError> {
     let write_txn = db.begin_write()?;
-    {
+    let seqs = {
         let mut pending = write_txn.open_table(QUEUE_PENDING)?;
         let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let mut recurring = write_txn.open_table(QUEUE_RECURRING)?;
+        let inflight = write_txn.open_table(QUEUE_INFLIGHT)?;
+
+        let mut encoded = Vec::with_capacity(records.len());
+        let mut added_per_uuid: HashMap<&str, u64> = HashMap::new();
+        for record in records {
+            // One scheduled run per subscription and recurring payload, so a
+            // backlog can't multiply heartbeats.
+            if record.repeat_every_ms.is_some()
+                && recurring_scheduled(&mut recurring, &pending, &inflight, record)?
+            {
+                continue;
+            }
+            let record_bytes = encode_record(record)?;
+            *added_per_uuid.entry(&record.uuid).or_default() += record_bytes.len() as u64;
+            encoded.push((record, record_bytes));
+        }
+        if encoded.is_empty() {
+            return Ok(0..0);
+        }
+        let batch_len: u64 = encoded.iter().map(|(_, bytes)| bytes.len() as u64).sum();
+
+        // Checked before any eviction, so one subscriber can't push out the
+        // others. A batch over the cap still goes in once that subscriber's
+        // backlog is empty, or a large hook would be refused forever; its
+        // heartbeats never drain, so they don't count against that.
+        for (&uuid, &added) in &added_per_uuid {
+            let current = per_uuid.get(uuid)?.map(|value| value.value()).unwrap_or(0);
+            if capacity.max_bytes_per_uuid > 0
+                && current.saturating_add(added) > capacity.max_bytes_per_uuid
+                && current > recurring_bytes(&recurring, &pending, &inflight, uuid)?
+            {
+                return Err(backlog_full());
+            }
+        }
 
         let next_seq = meta
             .get(META_NEXT_SEQ)?
@@ -265,64 +911,248 @@ fn enqueue_record(db: &Database, record: &QueueRecord, max_bytes: u64) -> Result
             .get(META_QUEUE_BYTES)?
             .map(|value| value.value())
             .unwrap_or(0);
-        let next_bytes = current_bytes.saturating_add(record_len);
-        if next_bytes > max_bytes {
+        let mut next_bytes = current_bytes.saturating_add(batch_len);
+        if next_bytes > capacity.max_bytes
+            && capacity.full_policy == QueueFullPolicy::DropOldest
+            && batch_len <= capacity.max_bytes
+        {
+            // Only pending records are evicted; inflight ones belong to a worker.
+            // Heartbeats keep their first seq and are only scheduled on
+            // subscribe, so they would always go first and never come back.
+            let mut evicted = Vec::new();
+            for entry in pending.iter()? {
+                if next_bytes <= capacity.max_bytes {
+                    break;
+                }
+                let (seq, value) = entry?;
+                if record_repeats(value.value()) {
+                    continue;
+                }
+                next_bytes = next_bytes.saturating_sub(value.value().len() as u64);
+                evicted.push(seq.value());
+            }
+            for &seq in &evicted {
+                if let Some(value) = pending.remove(seq)? {
+                    let len = value.value().len() as u64;
+                    adjust_uuid_bytes(&mut per_uuid, value.value(), len, 0)?;
+                }
+            }
+            if !evicted.is_empty() {
+                let dropped = evicted.len();
+                warn!("queue full; dropped {dropped} oldest pending records");
+            }
+        }
+        if next_bytes > capacity.max_bytes {
             return Err(AppError::new(
                 axum::http::StatusCode::SERVICE_UNAVAILABLE,
                 "queue full",
-            ));
+            )
+            .with_code("queue_full"));
         }
 
-        pending.insert(next_seq, record_bytes.as_slice())?;
-        meta.insert(META_NEXT_SEQ, next_seq + 1)?;
+        for (seq, (record, record_bytes)) in (next_seq..).zip(&encoded) {
+            pending.insert(seq, record_bytes.as_slice())?;
+            if record.repeat_every_ms.is_some() {
+                recurring.insert((record.uuid.as_str(), seq), ())?;
+            }
+        }
+        meta.insert(META_NEXT_SEQ, next_seq + encoded.len() as u64)?;
         meta.insert(META_QUEUE_BYTES, next_bytes)?;
-    }
+        for (uuid, added) in added_per_uuid {
+            let current = per_uuid.get(uuid)?.map(|value| value.value()).unwrap_or(0);
+            per_uuid.insert(uuid, current + added)?;
+        }
+        next_seq..next_seq + encoded.len() as u64
+    };
     write_txn.commit()?;
+    Ok(seqs)
+}
+
+// 429 rather than 503: only this subscriber is behind, and its backlog
+// shrinks as its pushes go out.
+fn backlog_full() -> AppError {
+    let mut headers = axum::http::HeaderMap::new();
+    headers.insert(
+        axum::http::header::RETRY_AFTER,
+        BACKLOG_RETRY_AFTER_SECS.into(),
+    );
+    AppError::new(
+        axum::http::StatusCode::TOO_MANY_REQUESTS,
+        "subscriber backlog full",
+    )
+    .with_code("subscriber_backlog_full")
+    .with_headers(headers)
+}
+
+// Move the count of an encoded record's uuid; rows that reach zero go.
+fn adjust_uuid_bytes(
+    per_uuid: &mut redb::Table<&str, u64>,
+    record_bytes: &[u8],
+    removed: u64,
+    added: u64,
+) -> Result<(), AppError> {
+    let Some((uuid, _)) = peek_record(record_bytes) else {
+        return Ok(());
+    };
+    let current = per_uuid.get(uuid)?.map(|value| value.value()).unwrap_or(0);
+    let next = current.saturating_sub(removed).saturating_add(added);
+    if next == 0 {
+        per_uuid.remove(uuid)?;
+    } else {
+        per_uuid.insert(uuid, next)?;
+    }
     Ok(())
 }
 
-fn claim_next(db: &Database, now_ms: i64) -> Result<Option<(u64, Vec<u8>)>, AppError> {
-    let write_txn = db.begin_write()?;
-    let mut selected: Option<(u64, Vec<u8>)> = None;
+// A claimed record. `claimed_at_ms` identifies the claim, so a stalled worker
+// whose record was reclaimed can't drop or requeue the new claim.
+struct Claim {
+    seq: u64,
+    claimed_at_ms: i64,
+    record_bytes: Vec<u8>,
+}
+
+// Claim the next ready record, keeping each uuid's records strictly in order:
+// a uuid with an inflight record, or an earlier record still waiting on its
+// `send_after_ms`, is skipped so chunks can't overtake one another. Inflight
+// records claimed more than `visibility_timeout_ms` ago are reclaimed first.
+fn claim_next(
+    db: &Database,
+    now_ms: i64,
+    visibility_timeout_ms: i64,
+) -> Result<Option<Claim>, AppError> {
+    // Look first under a read transaction, so idle workers don't take turns
+    // at the write lock while nothing is due.
     {
+        let read_txn = db.begin_read()?;
+        let claims = read_txn.open_table(QUEUE_CLAIMS)?;
+        let mut expired = false;
+        for entry in claims.iter()? {
+            let (_, value) = entry?;
+            if value.value().saturating_add(visibility_timeout_ms) <= now_ms {
+                expired = true;
+                break;
+            }
+        }
+        let pending = read_txn.open_table(QUEUE_PENDING)?;
+        let inflight = read_txn.open_table(QUEUE_INFLIGHT)?;
+        if !expired && select_claimable(&pending, &inflight, now_ms)?.is_none() {
+            return Ok(None);
+        }
+    }
+
+    let write_txn = db.begin_write()?;
+    let (selected, reclaimed) = {
         let mut pending = write_txn.open_table(QUEUE_PENDING)?;
         let mut inflight = write_txn.open_table(QUEUE_INFLIGHT)?;
+        let mut claims = write_txn.open_table(QUEUE_CLAIMS)?;
 
-        let mut iter = pending.iter()?;
-        for entry in iter.by_ref() {
+        let mut expired = Vec::new();
+        for entry in claims.iter()? {
             let (key, value) = entry?;
-            let bytes = value.value().to_vec();
-            let record = decode_record(&bytes).ok();
-            let ready = record
-                .as_ref()
-                .map(|rec| rec.send_after_ms <= now_ms)
-                .unwrap_or(true);
-            if ready {
-                selected = Some((key.value(), bytes));
-                break;
+            if value.value().saturating_add(visibility_timeout_ms) <= now_ms {
+                expired.push(key.value());
+            }
+        }
+        for seq in &expired {
+            claims.remove(seq)?;
+            if let Some(value) = inflight.remove(seq)? {
+                pending.insert(seq, value.value())?;
             }
         }
 
-        if let Some((seq, ref bytes)) = selected {
-            inflight.insert(seq, bytes.as_slice())?;
-            pending.remove(seq)?;
+        let selected = select_claimable(&pending, &inflight, now_ms)?;
+        let selected = selected.map(|(seq, record_bytes)| Claim {
+            seq,
+            claimed_at_ms: now_ms,
+            record_bytes,
+        });
+        if let Some(claim) = &selected {
+            inflight.insert(claim.seq, claim.record_bytes.as_slice())?;
+            claims.insert(claim.seq, claim.claimed_at_ms)?;
+            pending.remove(claim.seq)?;
         }
-    }
+        (selected, expired.len())
+    };
 
-    if selected.is_some() {
+    if selected.is_some() || reclaimed > 0 {
         write_txn.commit()?;
     }
+    if reclaimed > 0 {
+        warn!("reclaimed {reclaimed} queue records past the visibility timeout");
+    }
 
     Ok(selected)
 }
 
-fn drop_inflight(db: &Database, seq: u64) -> Result<(), AppError> {
+// The first pending record a worker may send now: due, and not behind an
+// inflight or still-delayed record for the same uuid.
+fn select_claimable(
+    pending: &impl ReadableTable<u64, &'static [u8]>,
+    inflight: &impl ReadableTable<u64, &'static [u8]>,
+    now_ms: i64,
+) -> Result<Option<(u64, Vec<u8>)>, AppError> {
+    let mut blocked: HashSet<String> = HashSet::new();
+    for entry in inflight.iter()? {
+        let (_, value) = entry?;
+        if let Some((uuid, _)) = peek_record(value.value()) {
+            blocked.insert(uuid.to_string());
+        }
+    }
+
+    for entry in pending.iter()? {
+        let (key, value) = entry?;
+        let bytes = value.value();
+        let Some((uuid, send_after_ms)) = peek_record(bytes) else {
+            // Corrupt records are handed out so the worker can drop them.
+            return Ok(Some((key.value(), bytes.to_vec())));
+        };
+        if blocked.contains(uuid) {
+            continue;
+        }
+        if send_after_ms <= now_ms {
+            return Ok(Some((key.value(), bytes.to_vec())));
+        }
+        // A waiting heartbeat doesn't hold back the uuid's hooks.
+        if !record_repeats(bytes) {
+            blocked.insert(uuid.to_string());
+        }
+    }
+    Ok(None)
+}
+
+// True if `claimed_at_ms` still owns the inflight record at `seq`.
+fn release_claim(
+    claims: &mut redb::Table<u64, i64>,
+    seq: u64,
+    claimed_at_ms: i64,
+) -> Result<bool, AppError> {
+    let current = claims.get(seq)?.map(|value| value.value());
+    if current != Some(claimed_at_ms) {
+        return Ok(false);
+    }
+    claims.remove(seq)?;
+    Ok(true)
+}
+
+fn drop_inflight(db: &Database, seq: u64, claimed_at_ms: i64) -> Result<(), AppError> {
     let write_txn = db.begin_write()?;
     {
         let mut inflight = write_txn.open_table(QUEUE_INFLIGHT)?;
+        let mut claims = write_txn.open_table(QUEUE_CLAIMS)?;
         let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let mut recurring = write_txn.open_table(QUEUE_RECURRING)?;
+        if !release_claim(&mut claims, seq, claimed_at_ms)? {
+            return Ok(());
+        }
         if let Some(value) = inflight.remove(seq)? {
             let len = value.value().len() as u64;
+            adjust_uuid_bytes(&mut per_uuid, value.value(), len, 0)?;
+            // A stopped heartbeat leaves the index with it.
+            if let Some((uuid, _)) = peek_record(value.value()) {
+                recurring.remove((uuid, seq))?;
+            }
             let current_bytes = meta
                 .get(META_QUEUE_BYTES)?
                 .map(|value| value.value())
@@ -335,55 +1165,672 @@ fn drop_inflight(db: &Database, seq: u64) -> Result<(), AppError> {
     Ok(())
 }
 
-fn requeue_inflight(db: &Database, seq: u64, record: &QueueRecord) -> Result<(), AppError> {
+// A delivered record is done, unless it recurs: then it goes back to pending
+// under its seq for the next run.
+fn finish_inflight(
+    db: &Database,
+    seq: u64,
+    claimed_at_ms: i64,
+    record: &QueueRecord,
+    now_ms: i64,
+) -> Result<(), AppError> {
+    let Some(every_ms) = record.repeat_every_ms else {
+        return drop_inflight(db, seq, claimed_at_ms);
+    };
+    let next = QueueRecord {
+        uuid: record.uuid.clone(),
+        payload: record.payload.clone(),
+        send_after_ms: now_ms + every_ms as i64,
+        attempts: 0,
+        ttl_secs: record.ttl_secs,
+        repeat_every_ms: Some(every_ms),
+        enqueued_at_ms: record.enqueued_at_ms,
+    };
+    requeue_inflight(db, seq, claimed_at_ms, &next)
+}
+
+fn requeue_inflight(
+    db: &Database,
+    seq: u64,
+    claimed_at_ms: i64,
+    record: &QueueRecord,
+) -> Result<(), AppError> {
     let record_bytes = encode_record(record)?;
     let write_txn = db.begin_write()?;
     {
         let mut inflight = write_txn.open_table(QUEUE_INFLIGHT)?;
         let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut claims = write_txn.open_table(QUEUE_CLAIMS)?;
         let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        if !release_claim(&mut claims, seq, claimed_at_ms)? {
+            return Ok(());
+        }
 
-        let next_seq = meta
-            .get(META_NEXT_SEQ)?
-            .map(|value| value.value())
+        // Keep the original seq so the retry stays ahead of later chunks.
+        let old_len = inflight
+            .remove(seq)?
+            .map(|value| value.value().len() as u64)
             .unwrap_or(0);
+        pending.insert(seq, record_bytes.as_slice())?;
 
-        inflight.remove(seq)?;
-        pending.insert(next_seq, record_bytes.as_slice())?;
-        meta.insert(META_NEXT_SEQ, next_seq + 1)?;
+        // The record is still queued, so its bytes stay counted; only a size
+        // change between the old and new encoding moves the total.
+        let new_len = record_bytes.len() as u64;
+        if new_len != old_len {
+            let current_bytes = meta
+                .get(META_QUEUE_BYTES)?
+                .map(|value| value.value())
+                .unwrap_or(0);
+            let next_bytes = current_bytes.saturating_sub(old_len).saturating_add(new_len);
+            meta.insert(META_QUEUE_BYTES, next_bytes)?;
+            adjust_uuid_bytes(&mut per_uuid, &record_bytes, old_len, new_len)?;
+        }
     }
     write_txn.commit()?;
     Ok(())
 }
 
-fn encode_record(record: &QueueRecord) -> Result<Vec<u8>, AppError> {
-    let uuid_bytes = record.uuid.as_bytes();
-    let uuid_len = u8::try_from(uuid_bytes.len()).map_err(|_| {
-        AppError::new(
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "uuid too long",
-        )
-    })?;
-
-    let payload_len = u32::try_from(record.payload.len()).map_err(|_| {
-        AppError::new(
-            axum::http::StatusCode::PAYLOAD_TOO_LARGE,
-            "queue payload too large",
-        )
-    })?;
+// Move an inflight record that will never be delivered to the dead-letter
+// table so the loss stays visible; its bytes move from QUEUE_BYTES to the
+// dead-letter total, and the oldest dead letters are evicted past `max_bytes`.
+fn dead_letter_inflight(
+    db: &Database,
+    seq: u64,
+    claimed_at_ms: i64,
+    reason: &str,
+    now_ms: i64,
+    max_bytes: u64,
+) -> Result<(), AppError> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut inflight = write_txn.open_table(QUEUE_INFLIGHT)?;
+        let mut claims = write_txn.open_table(QUEUE_CLAIMS)?;
+        let mut dead = write_txn.open_table(QUEUE_DEAD)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        if !release_claim(&mut claims, seq, claimed_at_ms)? {
+            return Ok(());
+        }
+        if let Some(value) = inflight.remove(seq)? {
+            let record_bytes = value.value();
+            adjust_uuid_bytes(&mut per_uuid, record_bytes, record_bytes.len() as u64, 0)?;
+            let letter = DeadLetterEntry {
+                seq,
+                record_bytes,
+                reason,
+                now_ms,
+            };
+            insert_dead_letter(&mut dead, &mut meta, letter, max_bytes)?;
 
-    let mut out = Vec::with_capacity(
-        1 + uuid_bytes.len() + 8 + 4 + 4 + record.payload.len(),
-    );
-    out.push(uuid_len);
-    out.extend_from_slice(uuid_bytes);
+            let current_bytes = meta
+                .get(META_QUEUE_BYTES)?
+                .map(|value| value.value())
+                .unwrap_or(0);
+            let next_bytes = current_bytes.saturating_sub(record_bytes.len() as u64);
+            meta.insert(META_QUEUE_BYTES, next_bytes)?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+struct DeadLetterEntry<'a> {
+    seq: u64,
+    record_bytes: &'a [u8],
+    reason: &'a str,
+    now_ms: i64,
+}
+
+// Store a dead letter and move DEAD_BYTES, evicting the oldest letters past
+// `max_bytes`. The caller takes the record's bytes off QUEUE_BYTES.
+fn insert_dead_letter(
+    dead: &mut redb::Table<u64, &[u8]>,
+    meta: &mut redb::Table<&str, u64>,
+    letter: DeadLetterEntry<'_>,
+    max_bytes: u64,
+) -> Result<(), AppError> {
+    let reason = &letter.reason.as_bytes()[..letter.reason.len().min(u16::MAX as usize)];
+    let mut entry = Vec::with_capacity(8 + 2 + reason.len() + letter.record_bytes.len());
+    entry.extend_from_slice(&letter.now_ms.to_be_bytes());
+    entry.extend_from_slice(&(reason.len() as u16).to_be_bytes());
+    entry.extend_from_slice(reason);
+    entry.extend_from_slice(letter.record_bytes);
+
+    let mut dead_bytes = meta
+        .get(META_DEAD_BYTES)?
+        .map(|value| value.value())
+        .unwrap_or(0)
+        .saturating_add(entry.len() as u64);
+    let mut evicted = 0;
+    while dead_bytes > max_bytes {
+        let Some((_, value)) = dead.pop_first()? else {
+            break;
+        };
+        dead_bytes = dead_bytes.saturating_sub(value.value().len() as u64);
+        evicted += 1;
+    }
+    if evicted > 0 {
+        warn!("dead letters over DEAD_LETTER_MAX_BYTES; evicted {evicted} oldest");
+    }
+    dead.insert(letter.seq, entry.as_slice())?;
+    meta.insert(META_DEAD_BYTES, dead_bytes)?;
+    Ok(())
+}
+
+// Dead-letter pending records queued more than `ttl_ms` ago, which no worker
+// has reached (e.g. they wait behind a failing push). Returns how many.
+fn expire_pending(
+    db: &Database,
+    ttl_ms: i64,
+    now_ms: i64,
+    max_dead_bytes: u64,
+) -> Result<usize, AppError> {
+    let write_txn = db.begin_write()?;
+    let expired = {
+        let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut dead = write_txn.open_table(QUEUE_DEAD)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let mut stale = Vec::new();
+        for entry in pending.iter()? {
+            let (seq, value) = entry?;
+            if record_expired(value.value(), ttl_ms, now_ms) {
+                stale.push(seq.value());
+            }
+        }
+
+        let mut removed_bytes = 0;
+        for &seq in &stale {
+            let Some(value) = pending.remove(seq)? else {
+                continue;
+            };
+            let record_bytes = value.value();
+            removed_bytes += record_bytes.len() as u64;
+            adjust_uuid_bytes(&mut per_uuid, record_bytes, record_bytes.len() as u64, 0)?;
+            let letter = DeadLetterEntry {
+                seq,
+                record_bytes,
+                reason: EXPIRED_REASON,
+                now_ms,
+            };
+            insert_dead_letter(&mut dead, &mut meta, letter, max_dead_bytes)?;
+        }
+        if removed_bytes > 0 {
+            let queue_bytes = meta
+                .get(META_QUEUE_BYTES)?
+                .map(|value| value.value())
+                .unwrap_or(0)
+                .saturating_sub(removed_bytes);
+            meta.insert(META_QUEUE_BYTES, queue_bytes)?;
+        }
+        stale.len()
+    };
+    write_txn.commit()?;
+    Ok(expired)
+}
+
+// Remove dead letters recorded at or before `cutoff_ms`; returns how many.
+fn purge_dead_letters(db: &Database, cutoff_ms: i64) -> Result<usize, AppError> {
+    let write_txn = db.begin_write()?;
+    let mut purged = 0;
+    {
+        let mut dead = write_txn.open_table(QUEUE_DEAD)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut purged_bytes = 0;
+        dead.retain(|_, entry| {
+            let dead_at_ms = entry
+                .get(..8)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(i64::from_be_bytes)
+                .unwrap_or(i64::MIN);
+            let expired = dead_at_ms <= cutoff_ms;
+            if expired {
+                purged += 1;
+                purged_bytes += entry.len() as u64;
+            }
+            !expired
+        })?;
+        let dead_bytes = meta
+            .get(META_DEAD_BYTES)?
+            .map(|value| value.value())
+            .unwrap_or(0);
+        meta.insert(META_DEAD_BYTES, dead_bytes.saturating_sub(purged_bytes))?;
+    }
+    write_txn.commit()?;
+    Ok(purged)
+}
+
+// Counts come from table lengths and the oldest record from the first pending
+// key, so nothing is scanned.
+fn queue_stats(db: &Database) -> Result<QueueStats, AppError> {
+    let read_txn = db.begin_read()?;
+    let pending = read_txn.open_table(QUEUE_PENDING)?;
+    let meta = read_txn.open_table(QUEUE_META)?;
+    let counter = |key| -> Result<u64, AppError> {
+        Ok(meta.get(key)?.map(|value| value.value()).unwrap_or(0))
+    };
+    // Seqs only grow and retries keep theirs, so the first row is the
+    // earliest enqueued; its send_after can lie ahead after a retry.
+    // Heartbeats keep their seq across runs, so they are passed over.
+    let mut oldest_pending_enqueued_at_ms = None;
+    for entry in pending.iter()? {
+        let (_, data) = entry?;
+        let data = data.value();
+        if !record_repeats(data) {
+            oldest_pending_enqueued_at_ms =
+                record_trailer(data).and_then(|trailer| decode_trailer(trailer).enqueued_at_ms);
+            break;
+        }
+    }
+    Ok(QueueStats {
+        pending: pending.len()?,
+        inflight: read_txn.open_table(QUEUE_INFLIGHT)?.len()?,
+        queue_bytes: counter(META_QUEUE_BYTES)?,
+        oldest_pending_enqueued_at_ms,
+        dead_letters: read_txn.open_table(QUEUE_DEAD)?.len()?,
+        dead_letter_bytes: counter(META_DEAD_BYTES)?,
+    })
+}
+
+// dead_at_ms, error and encoded record of a QUEUE_DEAD entry.
+fn split_dead_letter(entry: &[u8]) -> Option<(i64, &str, &[u8])> {
+    let dead_at_ms = i64::from_be_bytes(entry.get(..8)?.try_into().ok()?);
+    let reason_len = u16::from_be_bytes(entry.get(8..10)?.try_into().ok()?) as usize;
+    let reason = entry.get(10..10 + reason_len)?;
+    // The error was cut at u16::MAX bytes, possibly mid-character.
+    let reason = match std::str::from_utf8(reason) {
+        Ok(reason) => reason,
+        Err(err) => std::str::from_utf8(&reason[..err.valid_up_to()]).ok()?,
+    };
+    Some((dead_at_ms, reason, &entry[10 + reason_len..]))
+}
+
+fn list_dead_letters(db: &Database, uuid: Option<&str>) -> Result<Vec<DeadLetter>, AppError> {
+    let read_txn = db.begin_read()?;
+    let dead = read_txn.open_table(QUEUE_DEAD)?;
+    let mut letters = Vec::new();
+    for entry in dead.iter()? {
+        let (seq, entry) = entry?;
+        let Some((dead_at_ms, error, record_bytes)) = split_dead_letter(entry.value()) else {
+            continue;
+        };
+        let Ok(record) = decode_record(record_bytes) else {
+            continue;
+        };
+        if uuid.is_some_and(|uuid| uuid != record.uuid) {
+            continue;
+        }
+        letters.push(DeadLetter {
+            seq: seq.value(),
+            uuid: record.uuid,
+            dead_at: DateTime::from_timestamp_millis(dead_at_ms).unwrap_or_default(),
+            error: error.to_string(),
+            attempts: record.attempts,
+            payload_bytes: record.payload.len(),
+        });
+    }
+    Ok(letters)
+}
+
+// Move a dead letter back to pending under its old seq, with its attempts
+// reset; it counts toward QUEUE_BYTES again, so a full queue refuses it.
+fn retry_dead_letter(
+    db: &Database,
+    seq: u64,
+    capacity: QueueCapacity,
+    now_ms: i64,
+) -> Result<(), AppError> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut dead = write_txn.open_table(QUEUE_DEAD)?;
+        let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let Some(entry) = dead.remove(seq)?.map(|value| value.value().to_vec()) else {
+            return Err(AppError::new(
+                axum::http::StatusCode::NOT_FOUND,
+                "dead letter not found",
+            )
+            .with_code("dead_letter_not_found"));
+        };
+        let mut record = split_dead_letter(&entry)
+            .map(|(_, _, record_bytes)| decode_record(record_bytes))
+            .ok_or_else(|| {
+                AppError::new(
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "corrupt dead letter",
+                )
+            })??;
+        record.attempts = 0;
+        record.send_after_ms = now_ms;
+        // A retried letter starts a fresh QUEUE_RECORD_TTL_SECS.
+        record.enqueued_at_ms = Some(now_ms);
+        let record_bytes = encode_record(&record)?;
+
+        let queue_bytes = meta
+            .get(META_QUEUE_BYTES)?
+            .map(|value| value.value())
+            .unwrap_or(0)
+            .saturating_add(record_bytes.len() as u64);
+        if queue_bytes > capacity.max_bytes {
+            return Err(AppError::new(
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                "queue full",
+            )
+            .with_code("queue_full"));
+        }
+        let dead_bytes = meta
+            .get(META_DEAD_BYTES)?
+            .map(|value| value.value())
+            .unwrap_or(0)
+            .saturating_sub(entry.len() as u64);
+        pending.insert(seq, record_bytes.as_slice())?;
+        if record.repeat_every_ms.is_some() {
+            let mut recurring = write_txn.open_table(QUEUE_RECURRING)?;
+            recurring.insert((record.uuid.as_str(), seq), ())?;
+        }
+        meta.insert(META_QUEUE_BYTES, queue_bytes)?;
+        meta.insert(META_DEAD_BYTES, dead_bytes)?;
+        adjust_uuid_bytes(&mut per_uuid, &record_bytes, 0, record_bytes.len() as u64)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Remove `uuid`'s pending records and their bytes; returns how many.
+fn purge_pending(db: &Database, uuid: &str) -> Result<usize, AppError> {
+    let write_txn = db.begin_write()?;
+    let purged;
+    {
+        let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let mut removed_bytes = 0;
+        let mut count = 0;
+        pending.retain(|_, data| {
+            let matches = peek_record(data).is_some_and(|(record_uuid, _)| record_uuid == uuid);
+            if matches {
+                removed_bytes += data.len() as u64;
+                count += 1;
+            }
+            !matches
+        })?;
+        purged = count;
+        if removed_bytes > 0 {
+            let queue_bytes = meta
+                .get(META_QUEUE_BYTES)?
+                .map(|value| value.value())
+                .unwrap_or(0)
+                .saturating_sub(removed_bytes);
+            meta.insert(META_QUEUE_BYTES, queue_bytes)?;
+            let current = per_uuid.get(uuid)?.map(|value| value.value()).unwrap_or(0);
+            match current.saturating_sub(removed_bytes) {
+                0 => per_uuid.remove(uuid)?,
+                next => per_uuid.insert(uuid, next)?,
+            };
+        }
+        let mut recurring = write_txn.open_table(QUEUE_RECURRING)?;
+        recurring.retain_in((uuid, 0)..=(uuid, u64::MAX), |_, _| false)?;
+    }
+    write_txn.commit()?;
+    Ok(purged)
+}
+
+fn remove_pending(db: &Database, seqs: &[Range<u64>]) -> Result<usize, AppError> {
+    let write_txn = db.begin_write()?;
+    let mut removed = 0;
+    {
+        let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut meta = write_txn.open_table(QUEUE_META)?;
+        let mut per_uuid = write_txn.open_table(QUEUE_PER_UUID)?;
+        let mut removed_bytes = 0;
+        for seq in seqs.iter().cloned().flatten() {
+            let Some(value) = pending.remove(seq)? else {
+                continue;
+            };
+            let record_bytes = value.value();
+            removed_bytes += record_bytes.len() as u64;
+            adjust_uuid_bytes(&mut per_uuid, record_bytes, record_bytes.len() as u64, 0)?;
+            removed += 1;
+        }
+        if removed_bytes > 0 {
+            let queue_bytes = meta
+                .get(META_QUEUE_BYTES)?
+                .map(|value| value.value())
+                .unwrap_or(0)
+                .saturating_sub(removed_bytes);
+            meta.insert(META_QUEUE_BYTES, queue_bytes)?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(removed)
+}
+
+// Rewrite send_after in place; record sizes and byte accounting are unchanged.
+fn flush_pending(db: &Database, uuid: &str, now_ms: i64) -> Result<usize, AppError> {
+    let write_txn = db.begin_write()?;
+    let flushed;
+    {
+        let mut pending = write_txn.open_table(QUEUE_PENDING)?;
+        let mut delayed = Vec::new();
+        for entry in pending.iter()? {
+            let (seq, data) = entry?;
+            let data = data.value();
+            if let Some((record_uuid, send_after_ms)) = peek_record(data)
+                && record_uuid == uuid
+                && send_after_ms > now_ms
+            {
+                let mut data = data.to_vec();
+                let offset = 1 + uuid.len();
+                data[offset..offset + 8].copy_from_slice(&now_ms.to_be_bytes());
+                delayed.push((seq.value(), data));
+            }
+        }
+        flushed = delayed.len();
+        for (seq, data) in delayed {
+            pending.insert(seq, data.as_slice())?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(flushed)
+}
+
+fn encode_record(record: &QueueRecord) -> Result<Vec<u8>, AppError> {
+    let uuid_bytes = record.uuid.as_bytes();
+    let uuid_len = u8::try_from(uuid_bytes.len()).map_err(|_| {
+        AppError::new(
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            "uuid too long",
+        )
+    })?;
+
+    let payload_len = u32::try_from(record.payload.len()).map_err(|_| {
+        AppError::new(
+            axum::http::StatusCode::PAYLOAD_TOO_LARGE,
+            "queue payload too large",
+        )
+    })?;
+
+    let mut out = Vec::with_capacity(
+        1 + uuid_bytes.len() + 8 + 4 + 4 + record.payload.len() + 4 + 8,
+    );
+    out.push(uuid_len);
+    out.extend_from_slice(uuid_bytes);
     out.extend_from_slice(&record.send_after_ms.to_be_bytes());
     out.extend_from_slice(&record.attempts.to_be_bytes());
     out.extend_from_slice(&payload_len.to_be_bytes());
     out.extend_from_slice(&record.payload);
+    // Optional trailers, so records written before they existed still decode.
+    // The repeat interval follows the TTL, which recurring records always set.
+    // Records with an enqueue time carry every field in a fixed-size trailer
+    // instead, told apart from the older ones by its length.
+    if let Some(enqueued_at_ms) = record.enqueued_at_ms {
+        out.push(u8::from(record.ttl_secs.is_some()));
+        out.extend_from_slice(&record.ttl_secs.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&record.repeat_every_ms.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&enqueued_at_ms.to_be_bytes());
+        return Ok(out);
+    }
+    match (record.ttl_secs, record.repeat_every_ms) {
+        (Some(ttl_secs), repeat_every_ms) => {
+            out.extend_from_slice(&ttl_secs.to_be_bytes());
+            if let Some(every_ms) = repeat_every_ms {
+                out.extend_from_slice(&every_ms.to_be_bytes());
+            }
+        }
+        (None, Some(_)) => {
+            return Err(AppError::new(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "recurring record without ttl",
+            ));
+        }
+        (None, None) => {}
+    }
     Ok(out)
 }
 
+// Whether `record` already has a pending or inflight twin, looked up through
+// its uuid's QUEUE_RECURRING entries. Runs in the inserting transaction, so
+// nothing can slip in between this check and the insert.
+fn recurring_scheduled(
+    recurring: &mut redb::Table<(&str, u64), ()>,
+    pending: &impl ReadableTable<u64, &'static [u8]>,
+    inflight: &impl ReadableTable<u64, &'static [u8]>,
+    record: &QueueRecord,
+) -> Result<bool, AppError> {
+    let uuid = record.uuid.as_str();
+    let mut gone = Vec::new();
+    let mut scheduled = false;
+    for entry in recurring.range((uuid, 0)..=(uuid, u64::MAX))? {
+        let seq = entry?.0.value().1;
+        let value = match pending.get(seq)? {
+            Some(value) => Some(value),
+            None => inflight.get(seq)?,
+        };
+        let Some(value) = value else {
+            gone.push(seq);
+            continue;
+        };
+        if decode_record(value.value()).is_ok_and(|existing| existing.payload == record.payload) {
+            scheduled = true;
+            break;
+        }
+    }
+    for seq in gone {
+        recurring.remove((uuid, seq))?;
+    }
+    Ok(scheduled)
+}
+
+// Bytes of a uuid's scheduled recurring records. Stale index entries are
+// skipped rather than pruned; `recurring_scheduled` prunes them.
+fn recurring_bytes(
+    recurring: &impl ReadableTable<(&'static str, u64), ()>,
+    pending: &impl ReadableTable<u64, &'static [u8]>,
+    inflight: &impl ReadableTable<u64, &'static [u8]>,
+    uuid: &str,
+) -> Result<u64, AppError> {
+    let mut bytes = 0;
+    for entry in recurring.range((uuid, 0)..=(uuid, u64::MAX))? {
+        let seq = entry?.0.value().1;
+        let value = match pending.get(seq)? {
+            Some(value) => Some(value),
+            None => inflight.get(seq)?,
+        };
+        if let Some(value) = value {
+            bytes += value.value().len() as u64;
+        }
+    }
+    Ok(bytes)
+}
+
+// Pending and inflight records, not counting heartbeats, which never drain.
+fn undelivered_count(db: &Database) -> Result<usize, AppError> {
+    let read_txn = db.begin_read()?;
+    let mut count = 0;
+    for table in [QUEUE_PENDING, QUEUE_INFLIGHT] {
+        for entry in read_txn.open_table(table)?.iter()? {
+            let (_, value) = entry?;
+            if !record_repeats(value.value()) {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+// Whether an encoded record carries the repeat trailer.
+fn record_repeats(data: &[u8]) -> bool {
+    record_trailer(data).is_some_and(|trailer| decode_trailer(trailer).repeat_every_ms.is_some())
+}
+
+// Whether a non-recurring record was queued more than `ttl_ms` before
+// `now_ms`. Records without an enqueue time never expire.
+fn record_expired(data: &[u8], ttl_ms: i64, now_ms: i64) -> bool {
+    let Some(trailer) = record_trailer(data).map(decode_trailer) else {
+        return false;
+    };
+    ttl_ms > 0
+        && trailer.repeat_every_ms.is_none()
+        && trailer
+            .enqueued_at_ms
+            .is_some_and(|enqueued_at_ms| now_ms - enqueued_at_ms > ttl_ms)
+}
+
+// The bytes after the payload.
+fn record_trailer(data: &[u8]) -> Option<&[u8]> {
+    let uuid_len = *data.first()? as usize;
+    let len_offset = 1 + uuid_len + 8 + 4;
+    let len_bytes = data.get(len_offset..len_offset + 4)?;
+    let payload_len = u32::from_be_bytes(len_bytes.try_into().ok()?) as usize;
+    data.get(len_offset + 4 + payload_len..)
+}
+
+const STAMPED_TRAILER_LEN: usize = 1 + 4 + 8 + 8;
+
+struct Trailer {
+    ttl_secs: Option<u32>,
+    repeat_every_ms: Option<u64>,
+    enqueued_at_ms: Option<i64>,
+}
+
+fn decode_trailer(trailer: &[u8]) -> Trailer {
+    let u32_at = |at: usize| {
+        trailer
+            .get(at..at + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_be_bytes)
+    };
+    let u64_at = |at: usize| {
+        trailer
+            .get(at..at + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_be_bytes)
+    };
+    if trailer.len() == STAMPED_TRAILER_LEN {
+        return Trailer {
+            ttl_secs: u32_at(1).filter(|_| trailer[0] == 1),
+            repeat_every_ms: u64_at(5).filter(|&every_ms| every_ms > 0),
+            enqueued_at_ms: u64_at(13).map(|at| at as i64),
+        };
+    }
+    Trailer {
+        ttl_secs: u32_at(0),
+        repeat_every_ms: u64_at(4),
+        enqueued_at_ms: None,
+    }
+}
+
+// Read the ordering fields without copying the payload.
+fn peek_record(data: &[u8]) -> Option<(&str, i64)> {
+    let uuid_len = *data.first()? as usize;
+    let uuid = std::str::from_utf8(data.get(1..1 + uuid_len)?).ok()?;
+    let send_after_bytes = data.get(1 + uuid_len..1 + uuid_len + 8)?;
+    let send_after_ms = i64::from_be_bytes(send_after_bytes.try_into().ok()?);
+    Some((uuid, send_after_ms))
+}
+
 fn decode_record(data: &[u8]) -> Result<QueueRecord, AppError> {
     if data.len() < 1 + 8 + 4 + 4 {
         return Err(AppError::new(
@@ -430,11 +1877,1591 @@ fn decode_record(data: &[u8]) -> Result<QueueRecord, AppError> {
         ));
     }
     let payload = data[offset..offset + payload_len].to_vec();
+    offset += payload_len;
 
+    let trailer = decode_trailer(&data[offset..]);
     Ok(QueueRecord {
         uuid,
         payload,
         send_after_ms,
         attempts,
+        ttl_secs: trailer.ttl_secs,
+        repeat_every_ms: trailer.repeat_every_ms,
+        enqueued_at_ms: trailer.enqueued_at_ms,
     })
 }
+
+#[cfg(test)]
+pub(crate) fn pending_records(db: &Database) -> Vec<(String, Vec<u8>)> {
+    let read_txn = db.begin_read().unwrap();
+    let pending = read_txn.open_table(QUEUE_PENDING).unwrap();
+    pending
+        .iter()
+        .unwrap()
+        .map(|entry| {
+            let (_, value) = entry.unwrap();
+            let record = decode_record(value.value()).unwrap();
+            (record.uuid, record.payload)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) fn pending_ttls(db: &Database) -> Vec<Option<u32>> {
+    let read_txn = db.begin_read().unwrap();
+    let pending = read_txn.open_table(QUEUE_PENDING).unwrap();
+    pending
+        .iter()
+        .unwrap()
+        .map(|entry| decode_record(entry.unwrap().1.value()).unwrap().ttl_secs)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arc_swap::ArcSwap;
+    use redb::backends::InMemoryBackend;
+
+    fn test_queue() -> (DiskQueue, Arc<Database>) {
+        let queue_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        init_queue_db(&queue_db).unwrap();
+        let capacity = QueueCapacity {
+            max_bytes: 1024 * 1024,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        let wakeup = Arc::new(Notify::new());
+        let sender = spawn_writer(queue_db.clone(), capacity, 1024, wakeup.clone());
+        let queue = DiskQueue {
+            writer: Arc::new(Mutex::new(sender)),
+            queue_db: queue_db.clone(),
+            capacity,
+            write_buffer: 1024,
+            idle_sleep: Duration::from_millis(50),
+            wakeup,
+            closed: Arc::new(AtomicBool::new(false)),
+            stopping: Arc::new(AtomicBool::new(false)),
+            workers: Arc::new(Mutex::new(Vec::new())),
+            send_timeout: Duration::from_secs(10),
+        };
+        (queue, queue_db)
+    }
+
+    const VISIBILITY_TIMEOUT_MS: i64 = 60_000;
+
+    fn claimed(db: &Database, now_ms: i64) -> Option<(Claim, QueueRecord)> {
+        claim_next(db, now_ms, VISIBILITY_TIMEOUT_MS)
+            .unwrap()
+            .map(|claim| {
+                let record = decode_record(&claim.record_bytes).unwrap();
+                (claim, record)
+            })
+    }
+
+    #[tokio::test]
+    async fn claim_keeps_per_uuid_order_across_workers() {
+        let (queue, queue_db) = test_queue();
+        for (uuid, chunk) in [("a", "a1"), ("b", "b1"), ("a", "a2"), ("b", "b2"), ("a", "a3")] {
+            queue.enqueue(uuid, chunk.as_bytes().to_vec(), 0, None).await.unwrap();
+        }
+
+        // Two workers claim concurrently: one record per uuid at a time.
+        let (a1, record) = claimed(&queue_db, 10).unwrap();
+        assert_eq!(record.payload, b"a1");
+        let (b1, record) = claimed(&queue_db, 10).unwrap();
+        assert_eq!(record.payload, b"b1");
+        assert!(claimed(&queue_db, 10).is_none());
+
+        // a1 fails and is retried later: a2 must not overtake it.
+        let retry = QueueRecord {
+            uuid: "a".to_string(),
+            payload: b"a1".to_vec(),
+            send_after_ms: 100,
+            attempts: 1,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+        requeue_inflight(&queue_db, a1.seq, a1.claimed_at_ms, &retry).unwrap();
+        drop_inflight(&queue_db, b1.seq, b1.claimed_at_ms).unwrap();
+        let (b2, record) = claimed(&queue_db, 10).unwrap();
+        assert_eq!(record.payload, b"b2");
+        drop_inflight(&queue_db, b2.seq, b2.claimed_at_ms).unwrap();
+        assert!(claimed(&queue_db, 10).is_none());
+
+        let mut delivered = Vec::new();
+        while let Some((claim, record)) = claimed(&queue_db, 100) {
+            assert_eq!(record.uuid, "a");
+            delivered.push(String::from_utf8(record.payload).unwrap());
+            drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+        }
+        assert_eq!(delivered, vec!["a1", "a2", "a3"]);
+    }
+
+    #[test]
+    fn concurrent_workers_send_each_request_in_order() {
+        let queue_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        init_queue_db(&queue_db).unwrap();
+        let capacity = QueueCapacity {
+            max_bytes: u64::MAX,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        // Three subscriptions, two requests each, chunks interleaved on enqueue.
+        const CHUNKS: usize = 20;
+        for index in 0..CHUNKS {
+            for uuid in ["a", "b", "c"] {
+                for request in 0..2 {
+                    let record = QueueRecord {
+                        uuid: uuid.to_string(),
+                        payload: format!("{uuid}-{request}-{index:02}").into_bytes(),
+                        send_after_ms: 0,
+                        attempts: 0,
+                        ttl_secs: None,
+                        repeat_every_ms: None,
+                        enqueued_at_ms: None,
+                    };
+                    enqueue_record(&queue_db, &record, capacity).unwrap();
+                }
+            }
+        }
+
+        // Mock sends: every fifth claim "fails" once and is retried.
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue_db = queue_db.clone();
+                let sent = sent.clone();
+                std::thread::spawn(move || {
+                    let mut claims = 0;
+                    while let Some((claim, mut record)) = claimed(&queue_db, 0) {
+                        claims += 1;
+                        if claims % 5 == 0 && record.attempts == 0 {
+                            record.attempts = 1;
+                            requeue_inflight(&queue_db, claim.seq, claim.claimed_at_ms, &record)
+                                .unwrap();
+                            continue;
+                        }
+                        sent.lock().unwrap().push(String::from_utf8(record.payload).unwrap());
+                        drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), CHUNKS * 6);
+        for uuid in ["a", "b", "c"] {
+            for request in 0..2 {
+                let prefix = format!("{uuid}-{request}-");
+                let order: Vec<&String> = sent.iter().filter(|p| p.starts_with(&prefix)).collect();
+                assert!(order.windows(2).all(|pair| pair[0] < pair[1]), "{order:?}");
+                assert_eq!(order.len(), CHUNKS);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_claims_are_reclaimed_after_visibility_timeout() {
+        let (queue, queue_db) = test_queue();
+        queue.enqueue("abc", b"one".to_vec(), 0, None).await.unwrap();
+        let bytes_before = queue_bytes(&queue_db);
+
+        let (stalled, _) = claimed(&queue_db, 0).unwrap();
+        assert!(claimed(&queue_db, VISIBILITY_TIMEOUT_MS - 1).is_none());
+        let (reclaimed, record) = claimed(&queue_db, VISIBILITY_TIMEOUT_MS).unwrap();
+        assert_eq!(reclaimed.seq, stalled.seq);
+        assert_eq!(record.payload, b"one");
+
+        // The stalled worker finishing late must not release the new claim.
+        drop_inflight(&queue_db, stalled.seq, stalled.claimed_at_ms).unwrap();
+        assert_eq!(queue_bytes(&queue_db), bytes_before);
+        assert!(claimed(&queue_db, VISIBILITY_TIMEOUT_MS + 1).is_none());
+
+        drop_inflight(&queue_db, reclaimed.seq, reclaimed.claimed_at_ms).unwrap();
+        assert_eq!(queue_bytes(&queue_db), 0);
+    }
+
+    #[tokio::test]
+    async fn flush_makes_delayed_records_claimable() {
+        let (queue, queue_db) = test_queue();
+        let later = Utc::now().timestamp_millis() + 60_000;
+        queue.enqueue("abc", b"one".to_vec(), later, None).await.unwrap();
+        queue.enqueue("abc", b"two".to_vec(), later, None).await.unwrap();
+        queue.enqueue("xyz", b"other".to_vec(), later, None).await.unwrap();
+        let bytes_before = queue_bytes(&queue_db);
+        let now = Utc::now().timestamp_millis();
+        assert!(claimed(&queue_db, now).is_none());
+
+        assert_eq!(queue.flush("abc").unwrap(), 2);
+        assert_eq!(queue_bytes(&queue_db), bytes_before);
+        let (claim, record) = claimed(&queue_db, Utc::now().timestamp_millis()).unwrap();
+        assert_eq!(record.payload, b"one");
+        drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+        let (_, record) = claimed(&queue_db, Utc::now().timestamp_millis()).unwrap();
+        assert_eq!(record.payload, b"two");
+        // Other subscriptions keep their schedule.
+        assert!(claimed(&queue_db, Utc::now().timestamp_millis()).is_none());
+    }
+
+    #[tokio::test]
+    async fn stats_report_backlog() {
+        let (queue, queue_db) = test_queue();
+        let empty = queue.stats().unwrap();
+        assert_eq!((empty.pending, empty.queue_bytes), (0, 0));
+        assert_eq!(empty.oldest_pending_enqueued_at_ms, None);
+
+        let capacity = QueueCapacity {
+            max_bytes: u64::MAX,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        let beat = QueueRecord {
+            uuid: "abc".to_string(),
+            payload: b"beat".to_vec(),
+            send_after_ms: i64::MAX,
+            attempts: 0,
+            ttl_secs: Some(60),
+            repeat_every_ms: Some(60_000),
+            enqueued_at_ms: Some(100),
+        };
+        enqueue_record(&queue_db, &beat, capacity).unwrap();
+        let records = [("abc", "one", 1_000), ("abc", "two", 2_000), ("xyz", "three", 3_000)];
+        for (uuid, payload, enqueued_at_ms) in records {
+            let record = QueueRecord {
+                uuid: uuid.to_string(),
+                payload: payload.as_bytes().to_vec(),
+                send_after_ms: enqueued_at_ms,
+                attempts: 0,
+                ttl_secs: None,
+                repeat_every_ms: None,
+                enqueued_at_ms: Some(enqueued_at_ms),
+            };
+            enqueue_record(&queue_db, &record, capacity).unwrap();
+        }
+        let (claim, _) = claimed(&queue_db, 10_000).unwrap();
+        dead_letter_inflight(&queue_db, claim.seq, claim.claimed_at_ms, "gone", 0, u64::MAX)
+            .unwrap();
+        let (claim, mut retry) = claimed(&queue_db, 10_000).unwrap();
+        retry.send_after_ms = 70_000;
+        requeue_inflight(&queue_db, claim.seq, claim.claimed_at_ms, &retry).unwrap();
+        claimed(&queue_db, 10_000).unwrap();
+
+        let stats = queue.stats().unwrap();
+        assert_eq!(
+            stats,
+            QueueStats {
+                pending: 2,
+                inflight: 1,
+                queue_bytes: stored_bytes(&queue_db),
+                // "one" is dead and "three" inflight; "two" waits for a retry
+                // and the heartbeat never drains.
+                oldest_pending_enqueued_at_ms: Some(2_000),
+                dead_letters: 1,
+                dead_letter_bytes: stored_dead_bytes(&queue_db),
+            }
+        );
+    }
+
+    #[test]
+    fn init_recovers_inflight_records() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        for payload in ["one", "two", "three"] {
+            let record = QueueRecord {
+                uuid: "abc".to_string(),
+                payload: payload.as_bytes().to_vec(),
+                send_after_ms: 0,
+                attempts: 0,
+                ttl_secs: None,
+                repeat_every_ms: None,
+                enqueued_at_ms: None,
+            };
+            let capacity = QueueCapacity {
+                max_bytes: u64::MAX,
+                max_bytes_per_uuid: 0,
+                full_policy: QueueFullPolicy::Reject,
+            };
+            enqueue_record(&queue_db, &record, capacity).unwrap();
+        }
+        // Simulate a crash with two records claimed.
+        claim_next(&queue_db, 0, VISIBILITY_TIMEOUT_MS).unwrap().unwrap();
+        let write_txn = queue_db.begin_write().unwrap();
+        {
+            let mut pending = write_txn.open_table(QUEUE_PENDING).unwrap();
+            let mut inflight = write_txn.open_table(QUEUE_INFLIGHT).unwrap();
+            let (seq, value) = pending.pop_first().unwrap().unwrap();
+            inflight.insert(seq.value(), value.value()).unwrap();
+        }
+        write_txn.commit().unwrap();
+        assert_eq!(pending_records(&queue_db).len(), 1);
+        let bytes_before = queue_bytes(&queue_db);
+
+        init_queue_db(&queue_db).unwrap();
+
+        let payloads: Vec<Vec<u8>> = pending_records(&queue_db)
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(payloads, vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        let read_txn = queue_db.begin_read().unwrap();
+        assert!(read_txn.open_table(QUEUE_INFLIGHT).unwrap().is_empty().unwrap());
+        assert_eq!(queue_bytes(&queue_db), bytes_before);
+    }
+
+    // Accepts every push and keeps the payloads.
+    #[derive(Default)]
+    struct RecordingPush(std::sync::Mutex<Vec<Vec<u8>>>);
+
+    impl PushTransport for RecordingPush {
+        fn send<'a>(
+            &'a self,
+            _uuid: &'a str,
+            _subscription: &'a crate::models::PushSubscription,
+            payload: &'a [u8],
+            _ttl_secs: u32,
+        ) -> futures_util::future::BoxFuture<'a, Result<(), crate::push::PushError>> {
+            self.0.lock().unwrap().push(payload.to_vec());
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn recovered_records_are_delivered_by_workers() {
+        let (queue, queue_db) = test_queue();
+        for payload in ["one", "two"] {
+            queue.enqueue("abc", payload.as_bytes().to_vec(), 0, None).await.unwrap();
+        }
+        // A crash after claiming: both rows stranded inflight.
+        let write_txn = queue_db.begin_write().unwrap();
+        {
+            let mut pending = write_txn.open_table(QUEUE_PENDING).unwrap();
+            let mut inflight = write_txn.open_table(QUEUE_INFLIGHT).unwrap();
+            while let Some((seq, value)) = pending.pop_first().unwrap() {
+                inflight.insert(seq.value(), value.value()).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        assert_eq!(init_queue_db(&queue_db).unwrap(), 2);
+        let (_, record) = claimed(&queue_db, 0).unwrap();
+        assert_eq!(record.attempts, 1);
+        // Hand the peeked record back for the worker.
+        recover_inflight(&queue_db).unwrap();
+
+        let push = Arc::new(RecordingPush::default());
+        let worker = tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db_with_abc(),
+            Arc::new(ArcSwap::from_pointee(worker_config())),
+            push.clone(),
+            Arc::new(Metrics::default()),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        ));
+        for _ in 0..100 {
+            if push.0.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        worker.abort();
+        assert_eq!(*push.0.lock().unwrap(), vec![b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(undelivered_count(&queue_db).unwrap(), 0);
+    }
+
+    fn subs_db_with_abc() -> Arc<Database> {
+        let subs_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        crate::db::init_db(&subs_db).unwrap();
+        let stored = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap();
+        crate::db::db_put(&subs_db, "abc", &stored).unwrap();
+        subs_db
+    }
+
+    fn worker_config() -> Config {
+        Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            _ => None,
+        })
+        .unwrap()
+    }
+
+    fn plant(db: &Database, payload: &str, enqueued_at_ms: i64, repeat_every_ms: Option<u64>) {
+        let record = QueueRecord {
+            uuid: "abc".to_string(),
+            payload: payload.as_bytes().to_vec(),
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: Some(60),
+            repeat_every_ms,
+            enqueued_at_ms: Some(enqueued_at_ms),
+        };
+        let capacity = QueueCapacity {
+            max_bytes: u64::MAX,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        enqueue_record(db, &record, capacity).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_records_are_dead_lettered_unsent() {
+        let (queue, queue_db) = test_queue();
+        let now_ms = Utc::now().timestamp_millis();
+        let hour_ago_ms = now_ms - 3_600_000;
+        plant(&queue_db, "stale", hour_ago_ms, None);
+        plant(&queue_db, "fresh", now_ms, None);
+        // Recurring records are rescheduled, not stale.
+        plant(&queue_db, "beat", hour_ago_ms, Some(60_000));
+
+        assert_eq!(expire_pending(&queue_db, 900_000, now_ms, u64::MAX).unwrap(), 1);
+        let letters = list_dead_letters(&queue_db, None).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].error, EXPIRED_REASON);
+        assert_eq!(pending_records(&queue_db).len(), 2);
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+        assert_eq!(uuid_bytes(&queue_db), stored_uuid_bytes(&queue_db));
+
+        // One that goes stale after the sweep is caught when claimed.
+        plant(&queue_db, "late", hour_ago_ms, None);
+        let push = Arc::new(RecordingPush::default());
+        let metrics = Arc::new(Metrics::default());
+        let worker = tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db_with_abc(),
+            Arc::new(ArcSwap::from_pointee(worker_config())),
+            push.clone(),
+            metrics.clone(),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        ));
+        for _ in 0..100 {
+            if metrics.pushes_expired.load(Ordering::Relaxed) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        worker.abort();
+        assert_eq!(metrics.pushes_expired.load(Ordering::Relaxed), 1);
+        let sent = push.0.lock().unwrap().clone();
+        assert!(sent.contains(&b"fresh".to_vec()));
+        assert!(!sent.contains(&b"late".to_vec()));
+        assert_eq!(list_dead_letters(&queue_db, None).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn requeue_keeps_queue_bytes_balanced() {
+        let (queue, queue_db) = test_queue();
+        queue.enqueue("abc", b"one".to_vec(), 0, None).await.unwrap();
+        queue.enqueue("def", b"two".to_vec(), 0, None).await.unwrap();
+
+        for attempt in 1..=5 {
+            let (claim, mut record) = claimed(&queue_db, i64::from(attempt) * 1_000).unwrap();
+            record.attempts = attempt;
+            record.send_after_ms = i64::from(attempt) * 1_000;
+            requeue_inflight(&queue_db, claim.seq, claim.claimed_at_ms, &record).unwrap();
+            assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+        }
+
+        while let Some((claim, _)) = claimed(&queue_db, i64::MAX) {
+            drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+        }
+        assert_eq!(queue_bytes(&queue_db), 0);
+    }
+
+    #[test]
+    fn batches_are_queued_all_or_nothing() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let record = |payload: &str| QueueRecord {
+            uuid: "abc".to_string(),
+            payload: payload.as_bytes().to_vec(),
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+        let record_len = encode_record(&record("p1")).unwrap().len() as u64;
+        let capacity = QueueCapacity {
+            max_bytes: record_len * 3,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+
+        enqueue_record(&queue_db, &record("p1"), capacity).unwrap();
+        // Three more would overflow, so not even the two that fit are queued.
+        let batch = [record("p2"), record("p3"), record("p4")];
+        let err = enqueue_records(&queue_db, &batch, capacity).unwrap_err();
+        assert_eq!(err.code, Some("queue_full"));
+        assert_eq!(queue_bytes(&queue_db), record_len);
+
+        enqueue_records(&queue_db, &batch[..2], capacity).unwrap();
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+        let mut payloads = Vec::new();
+        while let Some((claim, record)) = claimed(&queue_db, 0) {
+            payloads.push(record.payload);
+            drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+        }
+        assert_eq!(payloads, vec![b"p1".to_vec(), b"p2".to_vec(), b"p3".to_vec()]);
+    }
+
+    #[test]
+    fn full_queue_drops_oldest_pending_or_rejects() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let record = |payload: &str| QueueRecord {
+            uuid: "abc".to_string(),
+            payload: payload.as_bytes().to_vec(),
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+        // Room for exactly three records of this size.
+        let record_len = encode_record(&record("p1")).unwrap().len() as u64;
+        let reject = QueueCapacity {
+            max_bytes: record_len * 3,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        let drop_oldest = QueueCapacity {
+            full_policy: QueueFullPolicy::DropOldest,
+            ..reject
+        };
+
+        for payload in ["p1", "p2", "p3"] {
+            enqueue_record(&queue_db, &record(payload), reject).unwrap();
+        }
+        let err = enqueue_record(&queue_db, &record("p4"), reject).unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        // p1 is inflight and must survive; p2 is the oldest pending record.
+        let (inflight, _) = claimed(&queue_db, 0).unwrap();
+        enqueue_record(&queue_db, &record("p4"), drop_oldest).unwrap();
+        let payloads: Vec<Vec<u8>> = pending_records(&queue_db)
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(payloads, vec![b"p3".to_vec(), b"p4".to_vec()]);
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+
+        drop_inflight(&queue_db, inflight.seq, inflight.claimed_at_ms).unwrap();
+        assert_eq!(queue_bytes(&queue_db), record_len * 2);
+    }
+
+    #[test]
+    fn drop_oldest_passes_over_heartbeats() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let record = |payload: &str, repeat_every_ms| QueueRecord {
+            uuid: "abc".to_string(),
+            payload: payload.as_bytes().to_vec(),
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: Some(60),
+            repeat_every_ms,
+            enqueued_at_ms: None,
+        };
+        let record_len = encode_record(&record("p1", None)).unwrap().len() as u64;
+        let beat_len = encode_record(&record("hb", Some(60_000))).unwrap().len() as u64;
+        let capacity = QueueCapacity {
+            max_bytes: beat_len + record_len * 2,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::DropOldest,
+        };
+
+        // The heartbeat holds the lowest seq throughout.
+        enqueue_record(&queue_db, &record("hb", Some(60_000)), capacity).unwrap();
+        for payload in ["p1", "p2", "p3", "p4"] {
+            enqueue_record(&queue_db, &record(payload, None), capacity).unwrap();
+        }
+        let payloads: Vec<Vec<u8>> = pending_records(&queue_db)
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(payloads, vec![b"hb".to_vec(), b"p3".to_vec(), b"p4".to_vec()]);
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+        assert_eq!(uuid_bytes(&queue_db), stored_uuid_bytes(&queue_db));
+    }
+
+    #[test]
+    fn dead_letters_are_purged_after_ttl() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let capacity = QueueCapacity {
+            max_bytes: u64::MAX,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        for (uuid, dead_at_ms) in [("old", 1_000), ("new", 5_000)] {
+            let record = QueueRecord {
+                uuid: uuid.to_string(),
+                payload: b"chunk".to_vec(),
+                send_after_ms: 0,
+                attempts: 0,
+                ttl_secs: None,
+                repeat_every_ms: None,
+                enqueued_at_ms: None,
+            };
+            enqueue_record(&queue_db, &record, capacity).unwrap();
+            let (claim, _) = claimed(&queue_db, dead_at_ms).unwrap();
+            let (seq, claimed_at_ms) = (claim.seq, claim.claimed_at_ms);
+            dead_letter_inflight(&queue_db, seq, claimed_at_ms, "push failed", dead_at_ms, u64::MAX)
+                .unwrap();
+        }
+        assert_eq!(dead_letter_count(&queue_db), 2);
+        assert_eq!(queue_bytes(&queue_db), 0);
+        assert_eq!(dead_bytes(&queue_db), stored_dead_bytes(&queue_db));
+
+        assert_eq!(purge_dead_letters(&queue_db, 4_999).unwrap(), 1);
+        assert_eq!(dead_letter_count(&queue_db), 1);
+        assert_eq!(dead_bytes(&queue_db), stored_dead_bytes(&queue_db));
+        assert_eq!(purge_dead_letters(&queue_db, i64::MAX).unwrap(), 1);
+        assert_eq!(dead_letter_count(&queue_db), 0);
+        assert_eq!(dead_bytes(&queue_db), 0);
+    }
+
+    // Refuses pushes until `accept` is set, then records them.
+    #[derive(Default)]
+    struct FlakyPush {
+        accept: AtomicBool,
+        sent: std::sync::Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl PushTransport for FlakyPush {
+        fn send<'a>(
+            &'a self,
+            _uuid: &'a str,
+            _subscription: &'a crate::models::PushSubscription,
+            payload: &'a [u8],
+            _ttl_secs: u32,
+        ) -> futures_util::future::BoxFuture<'a, Result<(), crate::push::PushError>> {
+            let result = if self.accept.load(Ordering::SeqCst) {
+                self.sent.lock().unwrap().push(payload.to_vec());
+                Ok(())
+            } else {
+                Err(crate::push::PushError::permanent(AppError::new(
+                    axum::http::StatusCode::BAD_GATEWAY,
+                    "push failed: 400 Bad Request",
+                )))
+            };
+            Box::pin(async { result })
+        }
+    }
+
+    #[tokio::test]
+    async fn dead_letters_can_be_inspected_and_retried() {
+        let (queue, queue_db) = test_queue();
+        let subs_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        crate::db::init_db(&subs_db).unwrap();
+        let stored = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap();
+        crate::db::db_put(&subs_db, "abc", &stored).unwrap();
+        let cfg = Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let push = Arc::new(FlakyPush::default());
+        let worker = tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db,
+            Arc::new(ArcSwap::from_pointee(cfg)),
+            push.clone(),
+            Arc::new(Metrics::default()),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        ));
+
+        queue.enqueue("abc", b"chunk".to_vec(), 0, None).await.unwrap();
+        for _ in 0..100 {
+            if dead_letter_count(&queue_db) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let letters = queue.dead_letters(None).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].uuid, "abc");
+        assert_eq!(letters[0].error, "push failed: 400 Bad Request");
+        assert_eq!(letters[0].payload_bytes, 5);
+        assert!(queue.dead_letters(Some("other")).unwrap().is_empty());
+        assert_eq!(queue_bytes(&queue_db), 0);
+        assert_eq!(dead_bytes(&queue_db), stored_dead_bytes(&queue_db));
+
+        push.accept.store(true, Ordering::SeqCst);
+        queue.retry_dead_letter(letters[0].seq).unwrap();
+        assert_eq!(dead_bytes(&queue_db), 0);
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+        let missing = queue.retry_dead_letter(letters[0].seq).unwrap_err();
+        assert_eq!(missing.status, axum::http::StatusCode::NOT_FOUND);
+
+        for _ in 0..100 {
+            if !push.sent.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        worker.abort();
+        assert_eq!(*push.sent.lock().unwrap(), vec![b"chunk".to_vec()]);
+        assert_eq!(dead_letter_count(&queue_db), 0);
+    }
+
+    #[test]
+    fn dead_letters_are_bounded_oldest_first() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let capacity = QueueCapacity {
+            max_bytes: u64::MAX,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        for uuid in ["first", "second", "third"] {
+            let record = QueueRecord {
+                uuid: uuid.to_string(),
+                payload: vec![0; 100],
+                send_after_ms: 0,
+                attempts: 0,
+                ttl_secs: None,
+                repeat_every_ms: None,
+                enqueued_at_ms: None,
+            };
+            enqueue_record(&queue_db, &record, capacity).unwrap();
+            let (claim, _) = claimed(&queue_db, 0).unwrap();
+            // Room for two entries.
+            dead_letter_inflight(&queue_db, claim.seq, claim.claimed_at_ms, "gone", 0, 300)
+                .unwrap();
+        }
+        let uuids: Vec<String> = list_dead_letters(&queue_db, None)
+            .unwrap()
+            .into_iter()
+            .map(|letter| letter.uuid)
+            .collect();
+        assert_eq!(uuids, ["second", "third"]);
+        assert_eq!(dead_bytes(&queue_db), stored_dead_bytes(&queue_db));
+    }
+
+    fn dead_bytes(db: &Database) -> u64 {
+        let read_txn = db.begin_read().unwrap();
+        let meta = read_txn.open_table(QUEUE_META).unwrap();
+        meta.get(META_DEAD_BYTES).unwrap().unwrap().value()
+    }
+
+    fn stored_dead_bytes(db: &Database) -> u64 {
+        let read_txn = db.begin_read().unwrap();
+        let dead = read_txn.open_table(QUEUE_DEAD).unwrap();
+        dead.iter().unwrap().map(|entry| entry.unwrap().1.value().len() as u64).sum()
+    }
+
+    fn uuid_bytes(db: &Database) -> HashMap<String, u64> {
+        let read_txn = db.begin_read().unwrap();
+        let per_uuid = read_txn.open_table(QUEUE_PER_UUID).unwrap();
+        per_uuid
+            .iter()
+            .unwrap()
+            .map(|entry| {
+                let (uuid, bytes) = entry.unwrap();
+                (uuid.value().to_string(), bytes.value())
+            })
+            .collect()
+    }
+
+    fn stored_uuid_bytes(db: &Database) -> HashMap<String, u64> {
+        let read_txn = db.begin_read().unwrap();
+        let mut totals = HashMap::new();
+        for table in [QUEUE_PENDING, QUEUE_INFLIGHT] {
+            for entry in read_txn.open_table(table).unwrap().iter().unwrap() {
+                let (_, value) = entry.unwrap();
+                let (uuid, _) = peek_record(value.value()).unwrap();
+                *totals.entry(uuid.to_string()).or_default() += value.value().len() as u64;
+            }
+        }
+        totals
+    }
+
+    fn dead_letter_count(db: &Database) -> u64 {
+        let read_txn = db.begin_read().unwrap();
+        read_txn.open_table(QUEUE_DEAD).unwrap().len().unwrap()
+    }
+
+    // Sum of encoded record sizes actually sitting in the queue tables.
+    fn stored_bytes(db: &Database) -> u64 {
+        let read_txn = db.begin_read().unwrap();
+        let mut total = 0;
+        for table in [QUEUE_PENDING, QUEUE_INFLIGHT] {
+            for entry in read_txn.open_table(table).unwrap().iter().unwrap() {
+                total += entry.unwrap().1.value().len() as u64;
+            }
+        }
+        total
+    }
+
+    fn queue_bytes(db: &Database) -> u64 {
+        let read_txn = db.begin_read().unwrap();
+        let meta = read_txn.open_table(QUEUE_META).unwrap();
+        meta.get(META_QUEUE_BYTES).unwrap().unwrap().value()
+    }
+
+    #[tokio::test]
+    async fn supervisor_respawns_panicked_worker() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let starts = Arc::new(AtomicUsize::new(0));
+        let (running_tx, mut running_rx) = mpsc::channel::<usize>(4);
+        let counter = starts.clone();
+        supervise(0, move || {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            let running_tx = running_tx.clone();
+            async move {
+                if attempt == 0 {
+                    panic!("simulated worker crash");
+                }
+                let _ = running_tx.send(attempt).await;
+                std::future::pending::<()>().await;
+            }
+        });
+
+        let attempt = tokio::time::timeout(Duration::from_secs(5), running_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(attempt, 1);
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn enqueue_restarts_dead_writer() {
+        let (queue, queue_db) = test_queue();
+        // Simulate a crashed writer: its receiver is gone.
+        let (dead, receiver) = mpsc::channel(1);
+        drop(receiver);
+        *queue.writer.lock().unwrap() = dead;
+
+        let err = queue.enqueue("abc", b"one".to_vec(), 0, None).await.unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.message, "queue writer unavailable");
+
+        queue.enqueue("abc", b"two".to_vec(), 0, None).await.unwrap();
+        let pending = pending_records(&queue_db);
+        assert_eq!(pending, vec![("abc".to_string(), b"two".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_workers_then_refuses_enqueues() {
+        let (queue, queue_db) = test_queue();
+        queue.enqueue("abc", b"one".to_vec(), 0, None).await.unwrap();
+        queue
+            .enqueue_recurring("abc", b"beat".to_vec(), 60_000, 60)
+            .await
+            .unwrap();
+
+        // Stand-in for a worker delivering the chunk mid-shutdown.
+        let worker = tokio::spawn({
+            let db = queue_db.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let (claim, record) = claimed(&db, 0).unwrap();
+                finish_inflight(&db, claim.seq, claim.claimed_at_ms, &record, 0).unwrap();
+            }
+        });
+        assert_eq!(queue.shutdown(Duration::from_secs(5)).await.unwrap(), 0);
+        worker.await.unwrap();
+
+        let err = queue.enqueue("abc", b"two".to_vec(), 0, None).await.unwrap_err();
+        assert_eq!(err.code, Some("shutting_down"));
+
+        // Nothing claims this one, so the timeout ends the wait.
+        let (queue, _queue_db) = test_queue();
+        queue.enqueue("abc", b"one".to_vec(), 0, None).await.unwrap();
+        assert_eq!(queue.shutdown(Duration::from_millis(100)).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn stopped_writer_is_not_ready() {
+        let (queue, _queue_db) = test_queue();
+        queue.check_ready().unwrap();
+
+        let (dead, receiver) = mpsc::channel(1);
+        drop(receiver);
+        *queue.writer.lock().unwrap() = dead;
+        let err = queue.check_ready().unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code, Some("not_ready"));
+    }
+
+    #[tokio::test]
+    async fn enqueue_reports_full_writer_as_busy() {
+        let (queue, _queue_db) = test_queue();
+        // A writer that never drains its single slot.
+        let (stalled, _receiver) = mpsc::channel(1);
+        let (ack, _) = oneshot::channel();
+        stalled
+            .try_send(QueueInsert {
+                records: vec![QueueRecord {
+                    uuid: "abc".to_string(),
+                    payload: Vec::new(),
+                    send_after_ms: 0,
+                    attempts: 0,
+                    ttl_secs: None,
+                    repeat_every_ms: None,
+                    enqueued_at_ms: None,
+                }],
+                check_backlog: true,
+                ack,
+            })
+            .ok()
+            .unwrap();
+        *queue.writer.lock().unwrap() = stalled;
+
+        let err = queue.enqueue("abc", b"one".to_vec(), 0, None).await.unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn ttl_trailer_is_optional() {
+        let mut record = QueueRecord {
+            uuid: "abc".to_string(),
+            payload: b"chunk".to_vec(),
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+        let legacy = encode_record(&record).unwrap();
+        assert_eq!(legacy.len(), 1 + 3 + 8 + 4 + 4 + 5);
+        assert_eq!(decode_record(&legacy).unwrap().ttl_secs, None);
+
+        record.ttl_secs = Some(30);
+        let encoded = encode_record(&record).unwrap();
+        let decoded = decode_record(&encoded).unwrap();
+        assert_eq!(decoded.ttl_secs, Some(30));
+        assert_eq!(decoded.payload, b"chunk");
+        assert!(!record_expired(&encoded, 1, i64::MAX));
+
+        record.enqueued_at_ms = Some(1_000);
+        let stamped = decode_record(&encode_record(&record).unwrap()).unwrap();
+        assert_eq!(stamped.ttl_secs, Some(30));
+        assert_eq!(stamped.repeat_every_ms, None);
+        assert_eq!(stamped.enqueued_at_ms, Some(1_000));
+        record.ttl_secs = None;
+        record.repeat_every_ms = Some(5_000);
+        let stamped = decode_record(&encode_record(&record).unwrap()).unwrap();
+        assert_eq!((stamped.ttl_secs, stamped.repeat_every_ms), (None, Some(5_000)));
+    }
+
+    #[tokio::test]
+    async fn recurring_record_is_scheduled_once_per_uuid() {
+        let (queue, queue_db) = test_queue();
+        let runs = [("abc", "beat"), ("abc", "beat"), ("abc", "digest"), ("def", "beat")];
+        for (uuid, payload) in runs {
+            queue
+                .enqueue_recurring(uuid, payload.as_bytes().to_vec(), 60_000, 60)
+                .await
+                .unwrap();
+        }
+        assert_eq!(pending_records(&queue_db).len(), 3);
+
+        // A claimed run still counts as scheduled, also once the index is
+        // rebuilt from the rows.
+        let claim = claim_next(&queue_db, i64::MAX, VISIBILITY_TIMEOUT_MS).unwrap().unwrap();
+        recount_queue_bytes(&queue_db).unwrap();
+        queue
+            .enqueue_recurring("abc", b"beat".to_vec(), 60_000, 60)
+            .await
+            .unwrap();
+        assert_eq!(pending_records(&queue_db).len(), 2);
+
+        // A dropped run can be scheduled again.
+        drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+        queue
+            .enqueue_recurring("abc", b"beat".to_vec(), 60_000, 60)
+            .await
+            .unwrap();
+        assert_eq!(pending_records(&queue_db).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn recurring_record_reschedules_itself() {
+        let (queue, queue_db) = test_queue();
+        queue
+            .enqueue_recurring("abc", b"beat".to_vec(), 60_000, 60)
+            .await
+            .unwrap();
+        let now_ms = Utc::now().timestamp_millis();
+        assert!(claimed(&queue_db, now_ms).is_none());
+
+        // A waiting heartbeat doesn't hold back the uuid's hooks.
+        queue.enqueue("abc", b"hook".to_vec(), 0, None).await.unwrap();
+        let (claim, record) = claimed(&queue_db, now_ms).unwrap();
+        assert_eq!(record.payload, b"hook");
+        finish_inflight(&queue_db, claim.seq, claim.claimed_at_ms, &record, now_ms).unwrap();
+        assert_eq!(pending_records(&queue_db).len(), 1);
+
+        let due_ms = now_ms + 60_000;
+        let (claim, record) = claimed(&queue_db, due_ms).unwrap();
+        assert_eq!(record.payload, b"beat");
+        assert_eq!(record.ttl_secs, Some(60));
+        finish_inflight(&queue_db, claim.seq, claim.claimed_at_ms, &record, due_ms).unwrap();
+
+        assert!(claimed(&queue_db, due_ms + 59_999).is_none());
+        let (_, record) = claimed(&queue_db, due_ms + 60_000).unwrap();
+        assert_eq!(record.repeat_every_ms, Some(60_000));
+    }
+
+    #[test]
+    fn retry_delay_backs_off_exponentially_with_jitter() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let cfg = Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            "PUSH_RETRY_BASE_MS" => Some("1000".to_string()),
+            "PUSH_RETRY_MAX_MS" => Some("10000".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempt| retry_delay_ms(&cfg, attempt, &mut rng))
+            .collect();
+        // 1s, 2s, 4s, 8s, then capped at 10s, each within +/-20%.
+        let expected = [1_000u64, 2_000, 4_000, 8_000, 10_000, 10_000];
+        for (delay, expected) in delays.iter().zip(expected) {
+            assert!(*delay >= expected * 8 / 10, "{delay} < {expected}");
+            assert!(*delay <= (expected * 12 / 10).min(10_000), "{delay} > {expected}");
+        }
+        assert!(delays.windows(2).take(3).all(|pair| pair[0] < pair[1]));
+        // Same seed, same schedule.
+        let mut rng = StdRng::seed_from_u64(7);
+        let again: Vec<u64> = (1..=6)
+            .map(|attempt| retry_delay_ms(&cfg, attempt, &mut rng))
+            .collect();
+        assert_eq!(delays, again);
+        // Huge attempt counts saturate instead of overflowing.
+        assert!(retry_delay_ms(&cfg, u32::MAX, &mut rng) <= 10_000);
+    }
+
+    #[derive(Default)]
+    struct UnavailablePush(std::sync::atomic::AtomicU32);
+
+    impl PushTransport for UnavailablePush {
+        fn send<'a>(
+            &'a self,
+            _uuid: &'a str,
+            _subscription: &'a crate::models::PushSubscription,
+            _payload: &'a [u8],
+            _ttl_secs: u32,
+        ) -> futures_util::future::BoxFuture<'a, Result<(), crate::push::PushError>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async {
+                Err(AppError::new(
+                    axum::http::StatusCode::BAD_GATEWAY,
+                    "push failed: 503 Service Unavailable",
+                )
+                .into())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn max_attempts_controls_when_records_are_dead_lettered() {
+        let (queue, queue_db) = test_queue();
+        let subs_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        crate::db::init_db(&subs_db).unwrap();
+        let stored = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap();
+        crate::db::db_put(&subs_db, "abc", &stored).unwrap();
+        let cfg = Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            "QUEUE_MAX_ATTEMPTS" => Some("2".to_string()),
+            "QUEUE_IDLE_SLEEP_MS" => Some("5".to_string()),
+            "PUSH_RETRY_BASE_MS" | "PUSH_RETRY_MAX_MS" => Some("1".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let push = Arc::new(UnavailablePush::default());
+        let worker = tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db,
+            Arc::new(ArcSwap::from_pointee(cfg)),
+            push.clone(),
+            Arc::new(Metrics::default()),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        ));
+
+        queue.enqueue("abc", b"chunk".to_vec(), 0, None).await.unwrap();
+        for _ in 0..100 {
+            if dead_letter_count(&queue_db) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        worker.abort();
+        // One retry, instead of the default four.
+        assert_eq!(dead_letter_count(&queue_db), 1);
+        assert_eq!(push.0.load(Ordering::SeqCst), 2);
+    }
+
+    struct RetryLaterPush(Duration);
+
+    impl PushTransport for RetryLaterPush {
+        fn send<'a>(
+            &'a self,
+            _uuid: &'a str,
+            _subscription: &'a crate::models::PushSubscription,
+            _payload: &'a [u8],
+            _ttl_secs: u32,
+        ) -> futures_util::future::BoxFuture<'a, Result<(), crate::push::PushError>> {
+            let retry_after = self.0;
+            Box::pin(async move {
+                Err(crate::push::PushError {
+                    error: AppError::new(
+                        axum::http::StatusCode::BAD_GATEWAY,
+                        "push failed: 429 Too Many Requests",
+                    ),
+                    retryable: true,
+                    retry_after: Some(retry_after),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_capped_at_the_retry_max() {
+        let (queue, queue_db) = test_queue();
+        let cfg = Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            "QUEUE_MAX_ATTEMPTS" => Some("2".to_string()),
+            "QUEUE_IDLE_SLEEP_MS" => Some("5".to_string()),
+            "PUSH_RETRY_BASE_MS" | "PUSH_RETRY_MAX_MS" => Some("1".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let worker = tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db_with_abc(),
+            Arc::new(ArcSwap::from_pointee(cfg)),
+            Arc::new(RetryLaterPush(Duration::from_millis(i64::MAX as u64))),
+            Arc::new(Metrics::default()),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        ));
+
+        // Retried after PUSH_RETRY_MAX_MS rather than parked for good.
+        queue.enqueue("abc", b"chunk".to_vec(), 0, None).await.unwrap();
+        for _ in 0..100 {
+            if dead_letter_count(&queue_db) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        worker.abort();
+        assert_eq!(dead_letter_count(&queue_db), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn idle_workers_wake_on_enqueue() {
+        let (queue, queue_db) = test_queue();
+        let subs_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        crate::db::init_db(&subs_db).unwrap();
+        let stored = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap();
+        crate::db::db_put(&subs_db, "abc", &stored).unwrap();
+        // Far longer than the pickup allowed below, so only a wakeup can explain
+        // it. The bound leaves room for the claim and push in a debug build.
+        let cfg = Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            "QUEUE_IDLE_SLEEP_MS" => Some("10000".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let push = Arc::new(RecordingPush::default());
+        let worker = tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db,
+            Arc::new(ArcSwap::from_pointee(cfg)),
+            push.clone(),
+            Arc::new(Metrics::default()),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        ));
+        // Let the worker find the queue empty and go idle.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        queue.enqueue("abc", b"now".to_vec(), 0, None).await.unwrap();
+        let enqueued = Instant::now();
+        while push.0.lock().unwrap().is_empty() && enqueued.elapsed() < Duration::from_secs(1) {
+            tokio::task::yield_now().await;
+        }
+        let pickup = enqueued.elapsed();
+        worker.abort();
+        assert_eq!(*push.0.lock().unwrap(), vec![b"now".to_vec()]);
+        assert!(pickup < Duration::from_millis(25), "picked up after {pickup:?}");
+    }
+
+    #[test]
+    fn byte_counters_match_rows_across_random_operations() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let queue_db = Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap();
+            init_queue_db(&queue_db).unwrap();
+            let mut now_ms = 0;
+            let mut claims: Vec<(Claim, QueueRecord)> = Vec::new();
+            let record = |rng: &mut StdRng| QueueRecord {
+                uuid: ["a", "b", "c"][rng.gen_range(0..3)].to_string(),
+                payload: vec![0; rng.gen_range(0..200)],
+                send_after_ms: 0,
+                attempts: 0,
+                ttl_secs: rng.gen_bool(0.5).then_some(60),
+                repeat_every_ms: None,
+                enqueued_at_ms: None,
+            };
+
+            for _ in 0..200 {
+                now_ms += rng.gen_range(0..100);
+                let capacity = QueueCapacity {
+                    max_bytes: 2_000,
+                    max_bytes_per_uuid: if rng.gen_bool(0.5) { 800 } else { 0 },
+                    full_policy: if rng.gen_bool(0.5) {
+                        QueueFullPolicy::Reject
+                    } else {
+                        QueueFullPolicy::DropOldest
+                    },
+                };
+                let taken = (!claims.is_empty()).then(|| {
+                    let index = rng.gen_range(0..claims.len());
+                    claims.swap_remove(index)
+                });
+                match rng.gen_range(0..10) {
+                    0 | 1 => {
+                        let len = rng.gen_range(1..4);
+                        let batch: Vec<_> = (0..len).map(|_| record(&mut rng)).collect();
+                        // Full queues refuse; the counters must still hold.
+                        let _ = enqueue_records(&queue_db, &batch, capacity);
+                    }
+                    2 | 3 => {
+                        claims.extend(claimed(&queue_db, now_ms));
+                    }
+                    4 => {
+                        if let Some((claim, _)) = taken {
+                            drop_inflight(&queue_db, claim.seq, claim.claimed_at_ms).unwrap();
+                        }
+                    }
+                    5 => {
+                        // Retries may grow or shrink the record.
+                        if let Some((claim, mut record)) = taken {
+                            record.attempts += 1;
+                            record.ttl_secs = rng.gen_bool(0.5).then_some(30);
+                            requeue_inflight(&queue_db, claim.seq, claim.claimed_at_ms, &record)
+                                .unwrap();
+                        }
+                    }
+                    6 => {
+                        if let Some((claim, _)) = taken {
+                            let (seq, at) = (claim.seq, claim.claimed_at_ms);
+                            dead_letter_inflight(&queue_db, seq, at, "gone", now_ms, 1_500)
+                                .unwrap();
+                        }
+                    }
+                    7 => {
+                        let letters = list_dead_letters(&queue_db, None).unwrap();
+                        if let Some(letter) = letters.first() {
+                            let _ = retry_dead_letter(&queue_db, letter.seq, capacity, now_ms);
+                        }
+                        purge_dead_letters(&queue_db, now_ms - 500).unwrap();
+                    }
+                    8 => {
+                        // Claims past the visibility timeout are reclaimed.
+                        now_ms += VISIBILITY_TIMEOUT_MS;
+                        flush_pending(&queue_db, "a", now_ms).unwrap();
+                        purge_pending(&queue_db, "b").unwrap();
+                    }
+                    _ => {
+                        // A restart returns everything inflight to pending.
+                        recover_inflight(&queue_db).unwrap();
+                        claims.clear();
+                    }
+                }
+                assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db), "seed {seed}");
+                assert_eq!(dead_bytes(&queue_db), stored_dead_bytes(&queue_db), "seed {seed}");
+                assert_eq!(uuid_bytes(&queue_db), stored_uuid_bytes(&queue_db), "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn recount_repairs_a_drifted_counter() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let record = QueueRecord {
+            uuid: "abc".to_string(),
+            payload: b"chunk".to_vec(),
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+        let capacity = QueueCapacity {
+            max_bytes: 1024,
+            max_bytes_per_uuid: 0,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        enqueue_record(&queue_db, &record, capacity).unwrap();
+        claimed(&queue_db, 0).unwrap();
+        enqueue_record(&queue_db, &record, capacity).unwrap();
+
+        let write_txn = queue_db.begin_write().unwrap();
+        write_txn
+            .open_table(QUEUE_META)
+            .unwrap()
+            .insert(META_QUEUE_BYTES, 1_000)
+            .unwrap();
+        write_txn.commit().unwrap();
+        assert_eq!(recount_queue_bytes(&queue_db).unwrap(), stored_bytes(&queue_db));
+
+        // Startup runs the same repair.
+        let write_txn = queue_db.begin_write().unwrap();
+        write_txn.open_table(QUEUE_META).unwrap().remove(META_QUEUE_BYTES).unwrap();
+        write_txn.commit().unwrap();
+        init_queue_db(&queue_db).unwrap();
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+    }
+
+    #[derive(Default)]
+    struct SlowPush(std::sync::atomic::AtomicU32);
+
+    impl PushTransport for SlowPush {
+        fn send<'a>(
+            &'a self,
+            _uuid: &'a str,
+            _subscription: &'a crate::models::PushSubscription,
+            _payload: &'a [u8],
+            _ttl_secs: u32,
+        ) -> futures_util::future::BoxFuture<'a, Result<(), crate::push::PushError>> {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_finishes_inflight_sends_and_stops_workers_by_the_deadline() {
+        let (queue, queue_db) = test_queue();
+        let subs_db = Arc::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .unwrap(),
+        );
+        crate::db::init_db(&subs_db).unwrap();
+        let stored = serde_json::from_value(serde_json::json!({
+            "subscription": {
+                "endpoint": "https://fcm.googleapis.com/fcm/send/x",
+                "expirationTime": null,
+                "keys": {"p256dh": "p", "auth": "a"}
+            },
+            "created_at": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap();
+        crate::db::db_put(&subs_db, "abc", &stored).unwrap();
+        let cfg = Config::from_source(&|key| match key {
+            "VAPID_PUBLIC_KEY" | "VAPID_PRIVATE_KEY" => Some("key".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        for n in 0..10u8 {
+            queue.enqueue("abc", vec![n], 0, None).await.unwrap();
+        }
+        let push = Arc::new(SlowPush::default());
+        queue.workers.lock().unwrap().push(tokio::spawn(worker_loop(
+            queue_db.clone(),
+            subs_db,
+            Arc::new(ArcSwap::from_pointee(cfg)),
+            push.clone(),
+            Arc::new(Metrics::default()),
+            queue.wakeup.clone(),
+            queue.stopping.clone(),
+        )));
+
+        // The send running at the deadline completes; nothing more is claimed.
+        let started = Instant::now();
+        let remaining = queue.shutdown(Duration::from_millis(300)).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(800));
+        let sent = push.0.load(Ordering::SeqCst) as usize;
+        assert!(sent >= 2);
+        assert_eq!(remaining, 10 - sent);
+        let read_txn = queue_db.begin_read().unwrap();
+        assert!(read_txn.open_table(QUEUE_INFLIGHT).unwrap().is_empty().unwrap());
+        assert!(queue.workers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn one_uuid_filling_its_budget_leaves_others_enqueuing() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let capacity = QueueCapacity {
+            max_bytes: 1024 * 1024,
+            max_bytes_per_uuid: 1_000,
+            full_policy: QueueFullPolicy::DropOldest,
+        };
+        let record = |uuid: &str, len: usize| QueueRecord {
+            uuid: uuid.to_string(),
+            payload: vec![0; len],
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+
+        // An empty backlog takes a batch over the cap; nothing more fits.
+        enqueue_records(&queue_db, &[record("abc", 600), record("abc", 600)], capacity).unwrap();
+        let err = enqueue_record(&queue_db, &record("abc", 10), capacity).unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(err.code, Some("subscriber_backlog_full"));
+        assert_eq!(err.headers.unwrap()["retry-after"], "5");
+
+        enqueue_record(&queue_db, &record("def", 600), capacity).unwrap();
+        enqueue_record(&queue_db, &record("def", 300), capacity).unwrap();
+        assert_eq!(pending_records(&queue_db).len(), 4);
+
+        // Unsubscribing frees the uuid's share.
+        assert_eq!(purge_pending(&queue_db, "abc").unwrap(), 2);
+        assert!(!uuid_bytes(&queue_db).contains_key("abc"));
+        assert_eq!(queue_bytes(&queue_db), stored_bytes(&queue_db));
+        enqueue_record(&queue_db, &record("abc", 10), capacity).unwrap();
+    }
+
+    #[test]
+    fn a_pending_heartbeat_leaves_the_backlog_empty() {
+        let queue_db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        init_queue_db(&queue_db).unwrap();
+        let capacity = QueueCapacity {
+            max_bytes: 1024 * 1024,
+            max_bytes_per_uuid: 1_000,
+            full_policy: QueueFullPolicy::Reject,
+        };
+        let record = |len: usize, repeat_every_ms| QueueRecord {
+            uuid: "abc".to_string(),
+            payload: vec![0; len],
+            send_after_ms: 0,
+            attempts: 0,
+            ttl_secs: Some(60),
+            repeat_every_ms,
+            enqueued_at_ms: None,
+        };
+
+        // A hook over the cap still goes in beside the heartbeat, once.
+        enqueue_record(&queue_db, &record(10, Some(60_000)), capacity).unwrap();
+        enqueue_record(&queue_db, &record(1_200, None), capacity).unwrap();
+        let err = enqueue_record(&queue_db, &record(1_200, None), capacity).unwrap_err();
+        assert_eq!(err.code, Some("subscriber_backlog_full"));
+        assert_eq!(pending_records(&queue_db).len(), 2);
+    }
+
+    #[test]
+    fn push_span_names_the_chunk() {
+        let (buffer, _guard) = crate::access_log::tests::capture_json_logs();
+        let record = QueueRecord {
+            uuid: "abc".to_string(),
+            payload: br#"{"version":3,"request_id":"r1","chunk_index":2,"is_last":false}"#.to_vec(),
+            send_after_ms: 0,
+            attempts: 1,
+            ttl_secs: None,
+            repeat_every_ms: None,
+            enqueued_at_ms: None,
+        };
+        push_span(&record, 7).in_scope(|| error!("push failed"));
+
+        let lines = crate::access_log::tests::logged_lines(&buffer, module_path!());
+        let span = &lines[0]["span"];
+        assert_eq!(span["uuid"], "abc");
+        assert_eq!(span["request_id"], "r1");
+        assert_eq!(span["chunk_index"], 2);
+        assert_eq!(span["attempt"], 2);
+    }
+}
@@ -1,11 +1,16 @@
+use axum::http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue};
 use std::{
     collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
     time::{Duration, Instant},
 };
 use tokio::sync::Mutex;
 
+const WINDOW: Duration = Duration::from_secs(60);
+
 pub struct RateLimiter {
-    limit_per_minute: u32,
+    // Changed by a config reload; current windows keep their counts.
+    limit_per_minute: AtomicU32,
     inner: Mutex<HashMap<String, RateEntry>>,
 }
 
@@ -14,36 +19,139 @@ struct RateEntry {
     count: u32,
 }
 
+// Outcome of one `allow` call, with what the sender needs to pace itself.
+#[derive(Debug, PartialEq)]
+pub struct RateDecision {
+    pub allowed: bool,
+    // 0 when rate limiting is off.
+    pub limit: u32,
+    pub remaining: u32,
+    // Until the current window ends and the count starts over.
+    pub reset_after: Duration,
+}
+
+impl RateDecision {
+    // `X-RateLimit-*` always, `Retry-After` on rejection; none when unlimited.
+    // Times are whole seconds, rounded up so a retry never comes early.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if self.limit == 0 {
+            return headers;
+        }
+        let reset_secs = self.reset_after.as_millis().div_ceil(1000) as u64;
+        headers.insert(HeaderName::from_static("x-ratelimit-limit"), self.limit.into());
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-remaining"),
+            self.remaining.into(),
+        );
+        headers.insert(HeaderName::from_static("x-ratelimit-reset"), reset_secs.into());
+        if !self.allowed {
+            headers.insert(RETRY_AFTER, HeaderValue::from(reset_secs));
+        }
+        headers
+    }
+}
+
 impl RateLimiter {
     pub fn new(limit_per_minute: u32) -> Self {
         Self {
-            limit_per_minute,
+            limit_per_minute: AtomicU32::new(limit_per_minute),
             inner: Mutex::new(HashMap::new()),
         }
     }
 
-    pub async fn allow(&self, key: &str) -> bool {
-        if self.limit_per_minute == 0 {
-            return true;
+    pub fn set_limit(&self, limit_per_minute: u32) {
+        self.limit_per_minute.store(limit_per_minute, Ordering::Relaxed);
+    }
+
+    pub async fn allow(&self, key: &str) -> RateDecision {
+        self.allow_at(key, Instant::now()).await
+    }
+
+    async fn allow_at(&self, key: &str, now: Instant) -> RateDecision {
+        let limit = self.limit_per_minute.load(Ordering::Relaxed);
+        if limit == 0 {
+            return RateDecision {
+                allowed: true,
+                limit: 0,
+                remaining: 0,
+                reset_after: Duration::ZERO,
+            };
         }
 
         let mut map = self.inner.lock().await;
-        let now = Instant::now();
         let entry = map.entry(key.to_string()).or_insert(RateEntry {
             window_start: now,
             count: 0,
         });
 
-        if now.duration_since(entry.window_start) >= Duration::from_secs(60) {
+        if now.duration_since(entry.window_start) >= WINDOW {
             entry.window_start = now;
             entry.count = 0;
         }
 
-        if entry.count >= self.limit_per_minute {
-            return false;
+        let allowed = entry.count < limit;
+        if allowed {
+            entry.count += 1;
+        }
+        RateDecision {
+            allowed,
+            limit,
+            // A lowered limit can leave a window already past it.
+            remaining: limit.saturating_sub(entry.count),
+            reset_after: WINDOW.saturating_sub(now.duration_since(entry.window_start)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn headers_count_down_and_reset_with_the_window() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        let first = limiter.allow_at("abc", start).await;
+        assert_eq!(first.headers()["x-ratelimit-remaining"], "1");
+        assert_eq!(first.headers()["x-ratelimit-reset"], "60");
+        assert!(first.headers().get("retry-after").is_none());
+        assert!(limiter.allow_at("abc", start).await.allowed);
+
+        // Half a second before the window ends: rejected, retry in 1s.
+        let late = start + Duration::from_millis(59_500);
+        let denied = limiter.allow_at("abc", late).await;
+        assert!(!denied.allowed);
+        let headers = denied.headers();
+        assert_eq!(headers["x-ratelimit-limit"], "2");
+        assert_eq!(headers["x-ratelimit-remaining"], "0");
+        assert_eq!(headers["x-ratelimit-reset"], "1");
+        assert_eq!(headers["retry-after"], "1");
+
+        // At the boundary a fresh window starts.
+        let next = limiter.allow_at("abc", start + WINDOW).await;
+        assert!(next.allowed);
+        assert_eq!(next.remaining, 1);
+        assert_eq!(next.reset_after, WINDOW);
+
+        assert!(RateLimiter::new(0).allow("abc").await.headers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_limit_applies_to_the_current_window() {
+        let limiter = RateLimiter::new(3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.allow_at("abc", start).await.allowed);
         }
+        limiter.set_limit(1);
+        let denied = limiter.allow_at("abc", start).await;
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
 
-        entry.count += 1;
-        true
+        limiter.set_limit(5);
+        let allowed = limiter.allow_at("abc", start).await;
+        assert!(allowed.allowed);
+        assert_eq!((allowed.limit, allowed.remaining), (5, 1));
     }
 }
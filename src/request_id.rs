@@ -0,0 +1,57 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+// Id of the request being handled, for errors built deep inside a handler.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+// Reuses a sane inbound X-Request-Id or mints one, then tags the tracing
+// span, the handler's errors and the response with it. A minted id is also
+// set on the request, so hook metadata (which captures x-request-id by
+// default) carries it to the browser.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let inbound = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string);
+    let id = match inbound {
+        Some(id) => id,
+        None => {
+            let id = Uuid::new_v4().to_string();
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                req.headers_mut().insert(X_REQUEST_ID, value);
+            }
+            id
+        }
+    };
+
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(req))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
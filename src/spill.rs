@@ -0,0 +1,151 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, Read, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+use uuid::Uuid;
+
+// Bytes kept in memory up to `threshold`, then moved to a temp file that is
+// removed on drop. A threshold of 0 never spills. File writes run on the
+// blocking pool so a spilling hook doesn't stall the runtime.
+pub struct SpillBuffer {
+    threshold: usize,
+    memory: Vec<u8>,
+    file: Option<SpillFile>,
+    len: usize,
+}
+
+struct SpillFile {
+    path: PathBuf,
+    file: Arc<File>,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl SpillBuffer {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn clear(&mut self) {
+        self.memory.clear();
+        self.file = None;
+        self.len = 0;
+    }
+
+    // The whole buffer; spilled contents are read back from disk.
+    pub fn contents(&self) -> io::Result<Cow<'_, [u8]>> {
+        match &self.file {
+            Some(spilled) => std::fs::read(&spilled.path).map(Cow::Owned),
+            None => Ok(Cow::Borrowed(&self.memory)),
+        }
+    }
+
+    // Reads the buffer from the start without loading a spilled file whole.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + Send + '_>> {
+        match &self.file {
+            Some(spilled) => Ok(Box::new(File::open(&spilled.path)?)),
+            None => Ok(Box::new(self.memory.as_slice())),
+        }
+    }
+
+    pub async fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.file.is_none()
+            && self.threshold > 0
+            && self.memory.len() + bytes.len() > self.threshold
+        {
+            self.spill().await?;
+        }
+        match &self.file {
+            Some(spilled) => {
+                let file = spilled.file.clone();
+                let bytes = bytes.to_vec();
+                blocking(move || (&*file).write_all(&bytes)).await?;
+            }
+            None => self.memory.extend_from_slice(bytes),
+        }
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    async fn spill(&mut self) -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("webhookpush-{}.body", Uuid::new_v4()));
+        let memory = std::mem::take(&mut self.memory);
+        let spilled = blocking(move || {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            // Removed again if the first write fails.
+            let spilled = SpillFile {
+                path,
+                file: Arc::new(file),
+            };
+            (&*spilled.file).write_all(&memory)?;
+            Ok(spilled)
+        })
+        .await?;
+        self.file = Some(spilled);
+        Ok(())
+    }
+}
+
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(io::Error::other)?
+}
+
+impl From<Vec<u8>> for SpillBuffer {
+    fn from(memory: Vec<u8>) -> Self {
+        Self {
+            threshold: 0,
+            len: memory.len(),
+            memory,
+            file: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spills_past_threshold_and_cleans_up() {
+        let mut buffer = SpillBuffer::new(16);
+        buffer.write_all(b"0123456789").await.unwrap();
+        assert!(buffer.file.is_none());
+        buffer.write_all(b"abcdefghij").await.unwrap();
+        assert!(buffer.file.is_some());
+        assert_eq!(buffer.len(), 20);
+        assert_eq!(&buffer.contents().unwrap()[..], b"0123456789abcdefghij");
+
+        let mut read_back = Vec::new();
+        buffer.reader().unwrap().read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, b"0123456789abcdefghij");
+
+        let path = buffer.file.as_ref().unwrap().path.clone();
+        assert!(path.exists());
+        drop(buffer);
+        assert!(!path.exists());
+    }
+}
@@ -1,12 +1,38 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use redb::Database;
-use crate::{config::Config, queue::DiskQueue, rate_limiter::RateLimiter};
+use crate::{
+    config::{Config, SharedConfig}, content_dedup::ContentDedup, cooldown::OversizeCooldown,
+    hook_lock::HookLocks, live::LiveStreams, metrics::Metrics, poll::PollWaiters,
+    push::PushTransport, queue::DiskQueue, rate_limiter::RateLimiter,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<Database>,
-    pub cfg: Arc<Config>,
+    // Swapped on SIGHUP; read it through `cfg()`.
+    pub config: SharedConfig,
     pub rate_limiter: Arc<RateLimiter>,
     pub push_queue: DiskQueue,
+    // Used directly for `delivery=sync`; the queue workers share it.
+    pub push: Arc<dyn PushTransport>,
+    pub metrics: Arc<Metrics>,
+    // UUID_ALLOWLIST_FILE ids; when set, subscribe is closed.
+    pub uuid_allowlist: Option<Arc<HashSet<String>>>,
+    pub oversize_cooldown: Arc<OversizeCooldown>,
+    // Per-uuid hook serialization (SERIALIZE_HOOKS).
+    pub hook_locks: Arc<HookLocks>,
+    // Watchers of GET /api/subscribe/:uuid/stream.
+    pub live_streams: Arc<LiveStreams>,
+    // Waiters of GET /api/subscribe/:uuid/poll.
+    pub poll_waiters: Arc<PollWaiters>,
+    // Recently relayed hook contents (CONTENT_DEDUP).
+    pub content_dedup: Arc<ContentDedup>,
+}
+
+impl AppState {
+    // The settings now in force; hold on to it for a consistent view.
+    pub fn cfg(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
 }
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use crate::models::HookSummary;
+
+// Characters kept per field; the first envelope reserves room for all of them.
+pub const MAX_METHOD_CHARS: usize = 16;
+pub const MAX_PATH_CHARS: usize = 128;
+pub const MAX_SOURCE_CHARS: usize = 64;
+pub const MAX_CONTENT_TYPE_CHARS: usize = 64;
+pub const MAX_PREVIEW_CHARS: usize = 200;
+// Body bytes needed for a full preview of 4-byte characters.
+pub const PREVIEW_READ_BYTES: usize = MAX_PREVIEW_CHARS * 4;
+
+// Headers that name the sending provider outright.
+const PROVIDER_HEADERS: &[(&str, &str)] = &[
+    ("x-github-event", "GitHub"),
+    ("stripe-signature", "Stripe"),
+    ("x-slack-signature", "Slack"),
+    ("x-gitlab-event", "GitLab"),
+    ("x-shopify-topic", "Shopify"),
+    ("x-twilio-signature", "Twilio"),
+];
+
+// `headers` is the captured map, so anything left out of the relayed
+// metadata stays out of the notification too.
+pub fn hook_summary(
+    method: &str,
+    path: &str,
+    headers: &HashMap<String, String>,
+    body_start: Option<&[u8]>,
+) -> HookSummary {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let source = PROVIDER_HEADERS
+        .iter()
+        .find(|(name, _)| header(name).is_some())
+        .map(|(_, provider)| provider.to_string())
+        .or_else(|| {
+            header("user-agent")
+                .and_then(|agent| agent.split(['/', ' ']).next())
+                .filter(|product| !product.is_empty())
+                .map(str::to_string)
+        });
+    let content_type = header("content-type");
+    let preview = body_start
+        .filter(|_| content_type.is_none_or(is_textual))
+        .and_then(text_prefix)
+        .map(|text| clip(text, MAX_PREVIEW_CHARS).trim().to_string())
+        .filter(|text| !text.is_empty());
+    HookSummary {
+        method: clip(method, MAX_METHOD_CHARS),
+        path: clip(path, MAX_PATH_CHARS),
+        source: source.map(|source| clip(&source, MAX_SOURCE_CHARS)),
+        content_type: content_type.map(|value| clip(value, MAX_CONTENT_TYPE_CHARS)),
+        preview,
+    }
+}
+
+fn is_textual(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || ["json", "xml", "x-www-form-urlencoded"]
+            .iter()
+            .any(|kind| essence.ends_with(kind))
+}
+
+// Valid UTF-8 up to a character cut off by the read; None for binary.
+fn text_prefix(bytes: &[u8]) -> Option<&str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some(text),
+        Err(err) if err.error_len().is_none() => std::str::from_utf8(&bytes[..err.valid_up_to()]).ok(),
+        Err(_) => None,
+    }
+}
+
+// Control characters would need six-byte JSON escapes; spaces keep the budget.
+fn clip(value: &str, max_chars: usize) -> String {
+    value
+        .chars()
+        .take(max_chars)
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_names_provider_and_previews_text_only() {
+        let mut headers = HashMap::from([
+            ("content-type".to_string(), "application/json".to_string()),
+            ("user-agent".to_string(), "GitHub-Hookshot/abc123".to_string()),
+        ]);
+        let body = format!("{{\"action\":\"opened\",\n\"pad\":\"{}\"}}", "x".repeat(300));
+        let summary = hook_summary("POST", "/abc", &headers, Some(body.as_bytes()));
+        assert_eq!(summary.source.as_deref(), Some("GitHub-Hookshot"));
+        let preview = summary.preview.unwrap();
+        assert_eq!(preview.chars().count(), MAX_PREVIEW_CHARS);
+        assert!(preview.starts_with("{\"action\":\"opened\", \"pad\""));
+
+        headers.insert("x-github-event".to_string(), "push".to_string());
+        headers.insert("content-type".to_string(), "image/png".to_string());
+        let summary = hook_summary("POST", "/abc", &headers, Some(b"\x89PNG"));
+        assert_eq!(summary.source.as_deref(), Some("GitHub"));
+        assert_eq!(summary.preview, None);
+
+        // Untyped bodies are previewed only when they are text.
+        headers.remove("content-type");
+        assert_eq!(hook_summary("POST", "/", &headers, Some(b"\xff\xfe")).preview, None);
+        let cut = "é".as_bytes()[..1].to_vec();
+        let text = [b"ok ".as_slice(), &cut].concat();
+        assert_eq!(
+            hook_summary("POST", "/", &headers, Some(&text)).preview.as_deref(),
+            Some("ok")
+        );
+    }
+}
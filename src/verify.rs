@@ -0,0 +1,278 @@
+use axum::http::{HeaderMap, StatusCode};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{error::AppError, models::Verification};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Marks a hashed delete token; anything else is a legacy plaintext token.
+const DELETE_TOKEN_HASH_PREFIX: &str = "sha256:";
+
+// Stripe's recommended replay window.
+pub const DEFAULT_STRIPE_TOLERANCE_SECS: u64 = 300;
+
+// Checks a provider signature over the raw body, fed as it is read.
+pub struct Verifier {
+    mac: HmacSha256,
+    // Any one matching signature is accepted (Stripe may send several).
+    expected: Vec<Vec<u8>>,
+}
+
+impl Verifier {
+    // Fails fast when the signature header is missing, malformed or stale.
+    pub fn new(
+        verification: &Verification,
+        headers: &HeaderMap,
+        now_secs: i64,
+    ) -> Result<Self, AppError> {
+        match verification {
+            Verification::Github { secret } => {
+                let expected = header_str(headers, "x-hub-signature-256")
+                    .and_then(|value| value.strip_prefix("sha256="))
+                    .and_then(|signature| hex::decode(signature).ok())
+                    .ok_or_else(invalid_signature)?;
+                Ok(Self {
+                    mac: new_mac(secret)?,
+                    expected: vec![expected],
+                })
+            }
+            Verification::Stripe {
+                secret,
+                tolerance_secs,
+            } => {
+                let header =
+                    header_str(headers, "stripe-signature").ok_or_else(invalid_signature)?;
+                let (timestamp, expected) = parse_stripe_header(header)?;
+                let tolerance = tolerance_secs.unwrap_or(DEFAULT_STRIPE_TOLERANCE_SECS);
+                if now_secs.abs_diff(timestamp) > tolerance {
+                    return Err(AppError::new(
+                        StatusCode::UNAUTHORIZED,
+                        "signature timestamp outside tolerance",
+                    )
+                    .with_code("invalid_signature"));
+                }
+                // Stripe signs "{t}.{body}".
+                let mut mac = new_mac(secret)?;
+                mac.update(format!("{timestamp}.").as_bytes());
+                Ok(Self { mac, expected })
+            }
+            Verification::Hmac { secret, header } => {
+                let expected = header_str(headers, header)
+                    .and_then(decode_signature)
+                    .ok_or_else(invalid_signature)?;
+                Ok(Self {
+                    mac: new_mac(secret)?,
+                    expected: vec![expected],
+                })
+            }
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.mac.update(bytes);
+    }
+
+    pub fn finish(self) -> Result<(), AppError> {
+        let computed = self.mac.finalize().into_bytes();
+        if self
+            .expected
+            .iter()
+            .any(|expected| constant_time_eq(&computed, expected))
+        {
+            Ok(())
+        } else {
+            Err(invalid_signature())
+        }
+    }
+}
+
+// `t=<unix secs>,v1=<hex>[,v1=<hex>...]`; other schemes (v0) are ignored.
+fn parse_stripe_header(header: &str) -> Result<(i64, Vec<Vec<u8>>), AppError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => {
+                if let Ok(signature) = hex::decode(value) {
+                    signatures.push(signature);
+                }
+            }
+            _ => {}
+        }
+    }
+    match timestamp {
+        Some(timestamp) if !signatures.is_empty() => Ok((timestamp, signatures)),
+        _ => Err(invalid_signature()),
+    }
+}
+
+// Senders disagree on encoding; a SHA-256 MAC is 64 hex chars or 44 base64 chars.
+fn decode_signature(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    let value = value.strip_prefix("sha256=").unwrap_or(value);
+    hex::decode(value)
+        .ok()
+        .or_else(|| base64::decode(value).ok())
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn new_mac(secret: &str) -> Result<HmacSha256, AppError> {
+    HmacSha256::new_from_slice(secret.as_bytes())
+        .map_err(|_| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "invalid hmac key"))
+}
+
+fn invalid_signature() -> AppError {
+    AppError::new(StatusCode::UNAUTHORIZED, "invalid signature").with_code("invalid_signature")
+}
+
+// Compare secrets without leaking the position of the first mismatch.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Delete tokens are stored as SHA-256 salted with the uuid, never in plaintext.
+pub fn hash_delete_token(uuid: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(uuid.as_bytes());
+    hasher.update(b":");
+    hasher.update(token.as_bytes());
+    format!("{DELETE_TOKEN_HASH_PREFIX}{}", hex::encode(hasher.finalize()))
+}
+
+pub fn is_hashed_delete_token(stored: &str) -> bool {
+    stored.starts_with(DELETE_TOKEN_HASH_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    // Example from GitHub's "Validating webhook deliveries" documentation.
+    const GITHUB_SECRET: &str = "It's a Secret to Everybody";
+    const GITHUB_PAYLOAD: &[u8] = b"Hello, World!";
+    const GITHUB_SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    fn github(signature: &'static str) -> Result<Verifier, AppError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-hub-signature-256", HeaderValue::from_static(signature));
+        Verifier::new(
+            &Verification::Github {
+                secret: GITHUB_SECRET.to_string(),
+            },
+            &headers,
+            0,
+        )
+    }
+
+    #[test]
+    fn github_signature_matches_documented_example() {
+        let mut verifier = github(GITHUB_SIGNATURE).unwrap();
+        // Fed in pieces, as the streaming reader would.
+        verifier.update(&GITHUB_PAYLOAD[..5]);
+        verifier.update(&GITHUB_PAYLOAD[5..]);
+        assert!(verifier.finish().is_ok());
+
+        let mut verifier = github(GITHUB_SIGNATURE).unwrap();
+        verifier.update(b"Hello, World?");
+        assert_eq!(verifier.finish().unwrap_err().status, StatusCode::UNAUTHORIZED);
+
+        assert!(github("sha1=757107ea").is_err());
+        let missing = Verifier::new(
+            &Verification::Github {
+                secret: GITHUB_SECRET.to_string(),
+            },
+            &HeaderMap::new(),
+            0,
+        );
+        assert!(missing.is_err());
+    }
+
+    const STRIPE_SECRET: &str = "whsec_test_secret";
+    const STRIPE_TIMESTAMP: i64 = 1_700_000_000;
+    const STRIPE_PAYLOAD: &[u8] = br#"{"id":"evt_1","type":"charge.succeeded"}"#;
+    const STRIPE_HEADER: &str = "t=1700000000,v0=6ffbb59b2300aae63f272406069a9788598b792a944a07aba816edb039989a39,v1=c0475716eb2200f1fe97fcf35a3c05d1fd4f8ba9a07a596730c8d18bb87c0672";
+
+    fn stripe(header: &'static str, now_secs: i64) -> Result<Verifier, AppError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("stripe-signature", HeaderValue::from_static(header));
+        Verifier::new(
+            &Verification::Stripe {
+                secret: STRIPE_SECRET.to_string(),
+                tolerance_secs: None,
+            },
+            &headers,
+            now_secs,
+        )
+    }
+
+    #[test]
+    fn stripe_signature_accepts_valid_payload() {
+        let mut verifier = stripe(STRIPE_HEADER, STRIPE_TIMESTAMP + 60).unwrap();
+        verifier.update(STRIPE_PAYLOAD);
+        assert!(verifier.finish().is_ok());
+    }
+
+    #[test]
+    fn stripe_signature_rejects_expired_timestamp() {
+        let expired = stripe(STRIPE_HEADER, STRIPE_TIMESTAMP + 301);
+        assert!(matches!(expired, Err(err) if err.status == StatusCode::UNAUTHORIZED));
+        assert!(stripe(STRIPE_HEADER, STRIPE_TIMESTAMP + 300).is_ok());
+    }
+
+    #[test]
+    fn stripe_signature_rejects_tampered_body() {
+        let mut verifier = stripe(STRIPE_HEADER, STRIPE_TIMESTAMP).unwrap();
+        verifier.update(br#"{"id":"evt_1","type":"charge.refunded"}"#);
+        assert!(verifier.finish().is_err());
+
+        assert!(stripe("t=1700000000", STRIPE_TIMESTAMP).is_err());
+    }
+
+    fn generic(header_value: &'static str) -> Result<Verifier, AppError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-signature", HeaderValue::from_static(header_value));
+        Verifier::new(
+            &Verification::Hmac {
+                secret: GITHUB_SECRET.to_string(),
+                header: "X-Signature".to_string(),
+            },
+            &headers,
+            0,
+        )
+    }
+
+    #[test]
+    fn generic_hmac_accepts_hex_and_base64() {
+        for header_value in [
+            "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+            GITHUB_SIGNATURE,
+            "dXEH6g6yUJ/CESIczphLijdXC211hsIsRvQ3nIsEPhc=",
+        ] {
+            let mut verifier = generic(header_value).unwrap();
+            verifier.update(GITHUB_PAYLOAD);
+            assert!(verifier.finish().is_ok(), "{header_value}");
+        }
+
+        let mut verifier = generic(GITHUB_SIGNATURE).unwrap();
+        verifier.update(b"forged");
+        assert!(verifier.finish().is_err());
+        assert!(generic("not a signature").is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_contents_and_length() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
+}